@@ -0,0 +1,135 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use stm_core::{assert_no_transaction, atomically, guard, StmResult, TVar, Transaction};
+
+#[derive(Clone, Copy)]
+struct BarrierState {
+    /// Number of threads, that have arrived in the current generation.
+    arrived: usize,
+
+    /// Counts how often the barrier has been passed.
+    generation: usize,
+}
+
+/// A transactional barrier, that lets a fixed number of parties meet.
+///
+/// Arriving at the barrier and waiting for the others can not happen in the same 
+/// transaction, because the arrival would be discarded together with the `retry`.
+/// Therefore `arrive` returns the generation of the barrier, and `wait_for` blocks 
+/// in a later transaction until that generation has passed.
+/// `wait` does both in two transactions.
+///
+/// The barrier resets itself, when all parties have arrived, so that it can be reused.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// # use std::thread;
+/// let barrier = TBarrier::new(2);
+/// let barrierc = barrier.clone();
+///
+/// let t = thread::spawn(move || barrierc.wait());
+/// barrier.wait();
+/// t.join().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct TBarrier {
+    parties: usize,
+    state: TVar<BarrierState>,
+}
+
+impl TBarrier {
+    /// Create a new barrier for `parties` participants.
+    pub fn new(parties: usize) -> TBarrier {
+        TBarrier {
+            parties,
+            state: TVar::new(BarrierState { arrived: 0, generation: 0 }),
+        }
+    }
+
+    /// Arrive at the barrier and return the current generation.
+    ///
+    /// If this is the last party, the barrier opens and resets itself.
+    pub fn arrive(&self, tx: &mut Transaction) -> StmResult<usize> {
+        let mut state = self.state.read(tx)?;
+        let generation = state.generation;
+
+        state.arrived += 1;
+        if state.arrived >= self.parties {
+            state.arrived = 0;
+            state.generation += 1;
+        }
+
+        self.state.write(tx, state)?;
+        Ok(generation)
+    }
+
+    /// Retry until all parties of `generation` have arrived.
+    pub fn wait_for(&self, tx: &mut Transaction, generation: usize) -> StmResult<()> {
+        let state = self.state.read(tx)?;
+        guard(state.generation != generation)
+    }
+
+    /// Arrive at the barrier and block until all parties have arrived.
+    ///
+    /// This runs two transactions and must not be called inside of a transaction.
+    pub fn wait(&self) {
+        assert_no_transaction();
+        let generation = atomically(|tx| self.arrive(tx));
+        atomically(|tx| self.wait_for(tx, generation));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::optionally;
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// No thread passes the barrier, before all have arrived.
+    /// Run two rounds to check that the barrier resets.
+    #[test]
+    fn threads_meet() {
+        let barrier = TBarrier::new(4);
+        let arrived = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let barrier = barrier.clone();
+            let arrived = arrived.clone();
+
+            thread::spawn(move || {
+                for round in 1..3 {
+                    arrived.fetch_add(1, Ordering::SeqCst);
+                    barrier.wait();
+                    assert!(arrived.load(Ordering::SeqCst) >= 4 * round);
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(arrived.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn arrive_opens_generation() {
+        let barrier = TBarrier::new(2);
+
+        let g = atomically(|tx| barrier.arrive(tx));
+        let passed = atomically(|tx| optionally(tx, |tx| barrier.wait_for(tx, g)));
+        assert_eq!(passed, None);
+
+        atomically(|tx| barrier.arrive(tx));
+        atomically(|tx| barrier.wait_for(tx, g));
+    }
+}
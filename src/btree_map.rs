@@ -0,0 +1,205 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional sorted map.
+///
+/// Besides the usual map operations it supports ordered queries like `range`,
+/// `first` and `last`.
+/// Every query reads the map, so a transaction, that has queried a range,
+/// conflicts with a concurrent insert into that range and reruns.
+///
+/// The whole map is stored in a single `TVar`, so all accesses conflict with
+/// every write and a write copies the map. This keeps ordered queries consistent
+/// in the simplest way. If the contention gets too high, the map can be sharded
+/// into several `TVar`s by key range later, so that a range query only reads the
+/// shards it overlaps.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let map = TBTreeMap::new();
+///
+/// atomically(|tx| {
+///     map.insert(tx, 3, "c")?;
+///     map.insert(tx, 1, "a")?;
+///     map.insert(tx, 2, "b")
+/// });
+///
+/// assert_eq!(atomically(|tx| map.range(tx, 2..)), vec![(2, "b"), (3, "c")]);
+/// assert_eq!(atomically(|tx| map.first(tx)), Some((1, "a")));
+/// ```
+#[derive(Clone)]
+pub struct TBTreeMap<K, V> {
+    map: TVar<BTreeMap<K, V>>,
+}
+
+impl<K, V> TBTreeMap<K, V>
+    where K: Any + Sync + Send + Clone + Ord,
+          V: Any + Sync + Send + Clone,
+{
+    /// Create an empty map.
+    pub fn new() -> TBTreeMap<K, V> {
+        TBTreeMap {
+            map: TVar::new(BTreeMap::new()),
+        }
+    }
+
+    /// Get a copy of the value for `key`.
+    pub fn get(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        Ok(self.map.read(tx)?.get(key).cloned())
+    }
+
+    /// Check if the map contains `key`.
+    pub fn contains_key(&self, tx: &mut Transaction, key: &K) -> StmResult<bool> {
+        Ok(self.map.read(tx)?.contains_key(key))
+    }
+
+    /// Insert a value and return the previous one.
+    pub fn insert(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let mut map = self.map.read(tx)?;
+        let old = map.insert(key, value);
+        self.map.write(tx, map)?;
+        Ok(old)
+    }
+
+    /// Remove a key and return its value.
+    pub fn remove(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let mut map = self.map.read(tx)?;
+        let old = map.remove(key);
+        if old.is_some() {
+            self.map.write(tx, map)?;
+        }
+        Ok(old)
+    }
+
+    /// Get all entries with a key in `range` in ascending order.
+    pub fn range<R>(&self, tx: &mut Transaction, range: R) -> StmResult<Vec<(K, V)>>
+        where R: RangeBounds<K>
+    {
+        Ok(self.map.read(tx)?
+            .range(range)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    /// Get the entry with the smallest key.
+    pub fn first(&self, tx: &mut Transaction) -> StmResult<Option<(K, V)>> {
+        Ok(self.map.read(tx)?
+            .first_key_value()
+            .map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    /// Get the entry with the largest key.
+    pub fn last(&self, tx: &mut Transaction) -> StmResult<Option<(K, V)>> {
+        Ok(self.map.read(tx)?
+            .last_key_value()
+            .map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    /// Number of entries in the map.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.map.read(tx)?.len())
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.map.read(tx)?.is_empty())
+    }
+}
+
+impl<K, V> Default for TBTreeMap<K, V>
+    where K: Any + Sync + Send + Clone + Ord,
+          V: Any + Sync + Send + Clone,
+{
+    fn default() -> Self {
+        TBTreeMap::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn ordered() {
+        let map = TBTreeMap::new();
+
+        atomically(|tx| {
+            for k in [5, 1, 4, 2, 3].iter() {
+                map.insert(tx, *k, k * 10)?;
+            }
+            Ok(())
+        });
+
+        assert_eq!(atomically(|tx| map.len(tx)), 5);
+        assert_eq!(atomically(|tx| map.range(tx, ..)),
+                   vec![(1, 10), (2, 20), (3, 30), (4, 40), (5, 50)]);
+        assert_eq!(atomically(|tx| map.range(tx, 2..4)), vec![(2, 20), (3, 30)]);
+        assert_eq!(atomically(|tx| map.first(tx)), Some((1, 10)));
+        assert_eq!(atomically(|tx| map.last(tx)), Some((5, 50)));
+
+        assert_eq!(atomically(|tx| map.remove(tx, &1)), Some(10));
+        assert_eq!(atomically(|tx| map.remove(tx, &1)), None);
+        assert_eq!(atomically(|tx| map.get(tx, &2)), Some(20));
+        assert!(!atomically(|tx| map.contains_key(tx, &1)));
+    }
+
+    /// An insert into a queried range forces the query to rerun.
+    #[test]
+    fn range_conflict() {
+        let map = TBTreeMap::new();
+        atomically(|tx| map.insert(tx, 1, 1));
+
+        let mut log = Transaction::new();
+        assert_eq!(map.range(&mut log, 0..10).unwrap(), vec![(1, 1)]);
+
+        atomically(|tx| map.insert(tx, 5, 5));
+
+        assert!(!log.commit());
+        assert_eq!(atomically(|tx| map.range(tx, 0..10)), vec![(1, 1), (5, 5)]);
+    }
+
+    /// Concurrent inserts never show a partial state to a range query.
+    #[test]
+    fn concurrent_range() {
+        use std::thread;
+
+        let map = TBTreeMap::new();
+
+        let writers: Vec<_> = (0..4).map(|t| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 0..50 {
+                    // Every transaction inserts a pair of keys.
+                    atomically(|tx| {
+                        map.insert(tx, 2 * (t * 50 + i), t)?;
+                        map.insert(tx, 2 * (t * 50 + i) + 1, t)
+                    });
+                }
+            })
+        }).collect();
+
+        for _ in 0..50 {
+            let entries = atomically(|tx| map.range(tx, ..));
+            assert_eq!(entries.len() % 2, 0);
+        }
+
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(atomically(|tx| map.len(tx)), 400);
+    }
+}
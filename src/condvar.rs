@@ -0,0 +1,105 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use stm_core::{guard, StmResult, Transaction};
+
+/// A condition variable for transactions.
+///
+/// It is meant for users, that migrate code from locks and condition variables.
+/// Unlike `std::sync::Condvar`, it has no state and there is no `notify`.
+/// `wait_while` retries the transaction, and STM wakes it automatically, whenever
+/// a transaction commits a write to one of the vars, that the predicate has read.
+/// Therefore a wakeup can neither be lost nor be spurious.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// # use std::thread;
+/// let ready = TVar::new(false);
+/// let readyc = ready.clone();
+///
+/// let t = thread::spawn(move || {
+///     atomically(|tx| TCondvar::new().wait_while(tx, |tx| Ok(!readyc.read(tx)?)))
+/// });
+///
+/// atomically(|tx| ready.write(tx, true));
+/// t.join().unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TCondvar;
+
+impl TCondvar {
+    /// Create a new condition variable.
+    pub fn new() -> TCondvar {
+        TCondvar
+    }
+
+    /// Retry while `pred` returns `true`.
+    ///
+    /// `pred` is evaluated again, after any var, that it has read, has changed.
+    pub fn wait_while<P>(&self, tx: &mut Transaction, pred: P) -> StmResult<()>
+        where P: Fn(&mut Transaction) -> StmResult<bool>
+    {
+        guard(!pred(tx)?)
+    }
+
+    /// Retry until `pred` returns `true`.
+    pub fn wait_until<P>(&self, tx: &mut Transaction, pred: P) -> StmResult<()>
+        where P: Fn(&mut Transaction) -> StmResult<bool>
+    {
+        guard(pred(tx)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::{atomically, TVar};
+
+    /// A change of either var re-evaluates the predicate.
+    #[test]
+    fn two_vars() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let evaluations = Arc::new(AtomicUsize::new(0));
+
+        let waiter = {
+            let a = a.clone();
+            let b = b.clone();
+            let evaluations = evaluations.clone();
+            thread::spawn(move || {
+                atomically(|tx| TCondvar::new().wait_while(tx, |tx| {
+                    evaluations.fetch_add(1, Ordering::SeqCst);
+                    Ok(a.read(tx)? + b.read(tx)? < 2)
+                }))
+            })
+        };
+
+        let wait_for_evaluations = |n| {
+            while evaluations.load(Ordering::SeqCst) < n {
+                thread::yield_now();
+            }
+        };
+
+        wait_for_evaluations(1);
+        atomically(|tx| a.write(tx, 1));
+
+        // The change of `a` wakes the waiter, but the condition still holds.
+        wait_for_evaluations(2);
+        assert!(!waiter.is_finished());
+
+        atomically(|tx| b.write(tx, 1));
+        waiter.join().unwrap();
+        assert_eq!(atomically(|tx| Ok(a.read(tx)? + b.read(tx)?)), 2);
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional configuration, that is cheap to read as a whole.
+///
+/// The entries are stored in an `Arc`, that is replaced by every update.
+/// A `snapshot` only clones the `Arc`, so readers on a hot path get a consistent
+/// view of all entries without copying them. Every update copies the map, so
+/// writes should be rare. Readers of a snapshot never conflict with each other.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let config = TConfig::new();
+///
+/// atomically(|tx| {
+///     config.set(tx, "host", String::from("localhost"))?;
+///     config.set(tx, "port", String::from("8080"))
+/// });
+///
+/// let snapshot = atomically(|tx| config.snapshot(tx));
+/// assert_eq!(snapshot["port"], "8080");
+/// ```
+#[derive(Clone)]
+pub struct TConfig<V> {
+    entries: TVar<Arc<HashMap<String, V>>>,
+}
+
+impl<V> TConfig<V>
+    where V: Any + Sync + Send + Clone
+{
+    /// Create an empty configuration.
+    pub fn new() -> TConfig<V> {
+        TConfig {
+            entries: TVar::new(Arc::new(HashMap::new())),
+        }
+    }
+
+    /// Get a copy of the value for `key`.
+    pub fn get(&self, tx: &mut Transaction, key: &str) -> StmResult<Option<V>> {
+        Ok(self.entries.read(tx)?.get(key).cloned())
+    }
+
+    /// Set the value for `key` and return the previous one.
+    pub fn set<K>(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<V>>
+        where K: Into<String>
+    {
+        let mut entries = self.entries.read(tx)?;
+        let old = Arc::make_mut(&mut entries).insert(key.into(), value);
+        self.entries.write(tx, entries)?;
+        Ok(old)
+    }
+
+    /// Remove the value for `key` and return it.
+    pub fn remove(&self, tx: &mut Transaction, key: &str) -> StmResult<Option<V>> {
+        let mut entries = self.entries.read(tx)?;
+        if !entries.contains_key(key) {
+            return Ok(None);
+        }
+        let old = Arc::make_mut(&mut entries).remove(key);
+        self.entries.write(tx, entries)?;
+        Ok(old)
+    }
+
+    /// Get all entries.
+    ///
+    /// The snapshot does not change, when the configuration is updated later.
+    pub fn snapshot(&self, tx: &mut Transaction) -> StmResult<Arc<HashMap<String, V>>> {
+        self.entries.read(tx)
+    }
+}
+
+impl<V> Default for TConfig<V>
+    where V: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TConfig::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn get_set() {
+        let config = TConfig::new();
+
+        assert_eq!(atomically(|tx| config.set(tx, "a", 1)), None);
+        assert_eq!(atomically(|tx| config.set(tx, "a", 2)), Some(1));
+        assert_eq!(atomically(|tx| config.get(tx, "a")), Some(2));
+
+        let snapshot = atomically(|tx| config.snapshot(tx));
+        assert_eq!(atomically(|tx| config.remove(tx, "a")), Some(2));
+        assert_eq!(atomically(|tx| config.get(tx, "a")), None);
+
+        // The snapshot is not affected by the update.
+        assert_eq!(snapshot.get("a"), Some(&2));
+    }
+
+    /// Readers never see a partial update.
+    #[test]
+    fn consistent_snapshots() {
+        use std::thread;
+
+        let config = TConfig::new();
+        atomically(|tx| {
+            config.set(tx, "min", 0)?;
+            config.set(tx, "max", 10)
+        });
+
+        let writer = {
+            let config = config.clone();
+            thread::spawn(move || {
+                // Every update keeps `max - min == 10`.
+                for i in 1..500 {
+                    atomically(|tx| {
+                        config.set(tx, "min", i)?;
+                        config.set(tx, "max", i + 10)
+                    });
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let config = config.clone();
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    let snapshot = atomically(|tx| config.snapshot(tx));
+                    assert_eq!(snapshot["max"] - snapshot["min"], 10);
+                }
+            })
+        }).collect();
+
+        writer.join().unwrap();
+        for r in readers {
+            r.join().unwrap();
+        }
+        assert_eq!(atomically(|tx| config.get(tx, "min")), Some(499));
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional double buffer for values, that are read often and replaced rarely.
+///
+/// Readers only read the front buffer. An update prepares the new value from the
+/// current one and swaps it to the front in the same transaction, while the
+/// previous value moves to the back. Readers therefore see either the old or the
+/// new value as a whole and only conflict with updates, never with each other.
+/// Hot readers outside of a transaction use `read_front`.
+///
+/// The back buffer keeps the previous value, so that an update can be undone with `rollback`.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let settings = TDoubleBuffer::new(vec![1, 2]);
+///
+/// atomically(|tx| settings.update(tx, |mut v| { v.push(3); v }));
+///
+/// assert_eq!(settings.read_front(), vec![1, 2, 3]);
+/// assert_eq!(atomically(|tx| settings.previous(tx)), vec![1, 2]);
+/// ```
+#[derive(Clone)]
+pub struct TDoubleBuffer<T> {
+    front: TVar<T>,
+    back: TVar<T>,
+}
+
+impl<T> TDoubleBuffer<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create a new buffer with `value` in front and back.
+    pub fn new(value: T) -> TDoubleBuffer<T> {
+        TDoubleBuffer {
+            front: TVar::new(value.clone()),
+            back: TVar::new(value),
+        }
+    }
+
+    /// Read the current value.
+    pub fn read(&self, tx: &mut Transaction) -> StmResult<T> {
+        self.front.read(tx)
+    }
+
+    /// Read the current value atomically outside of a transaction.
+    pub fn read_front(&self) -> T {
+        self.front.read_atomic()
+    }
+
+    /// Read the value before the last update.
+    pub fn previous(&self, tx: &mut Transaction) -> StmResult<T> {
+        self.back.read(tx)
+    }
+
+    /// Compute the new value from the current one and swap it to the front.
+    pub fn update<F>(&self, tx: &mut Transaction, f: F) -> StmResult<()>
+        where F: FnOnce(T) -> T
+    {
+        let current = self.front.read(tx)?;
+        self.back.write(tx, current.clone())?;
+        self.front.write(tx, f(current))
+    }
+
+    /// Swap the previous value back to the front.
+    pub fn rollback(&self, tx: &mut Transaction) -> StmResult<()> {
+        let current = self.front.read(tx)?;
+        let previous = self.back.replace(tx, current)?;
+        self.front.write(tx, previous)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn update_rollback() {
+        let buffer = TDoubleBuffer::new(1);
+
+        atomically(|tx| buffer.update(tx, |x| x + 1));
+        assert_eq!(buffer.read_front(), 2);
+        assert_eq!(atomically(|tx| buffer.previous(tx)), 1);
+
+        atomically(|tx| buffer.rollback(tx));
+        assert_eq!(atomically(|tx| buffer.read(tx)), 1);
+        assert_eq!(atomically(|tx| buffer.previous(tx)), 2);
+    }
+
+    /// Readers never see a partially updated value.
+    #[test]
+    fn readers_see_whole_values() {
+        use std::thread;
+
+        // Every element of a value is the same.
+        let buffer = TDoubleBuffer::new(vec![0; 16]);
+
+        let updater = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                for i in 1..200 {
+                    atomically(|tx| buffer.update(tx, |v| vec![i; v.len()]));
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..4).map(|r| {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    let v = if r % 2 == 0 {
+                        buffer.read_front()
+                    } else {
+                        atomically(|tx| buffer.read(tx))
+                    };
+                    assert!(v.iter().all(|&x| x == v[0]));
+                }
+            })
+        }).collect();
+
+        updater.join().unwrap();
+        for r in readers {
+            r.join().unwrap();
+        }
+        assert_eq!(buffer.read_front(), vec![199; 16]);
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional slot for reporting a failure.
+///
+/// A transaction stores an error with `set_error` and another one consumes it
+/// with `take_error`. Like every write, the error only becomes visible, when the
+/// transaction commits. It is discarded together with a branch of `or` or a rerun.
+///
+/// Only the latest error is kept.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let errors = TErrorSlot::new();
+///
+/// atomically(|tx| errors.set_error(tx, "disk full"));
+///
+/// assert_eq!(atomically(|tx| errors.take_error(tx)), Some("disk full"));
+/// assert_eq!(atomically(|tx| errors.take_error(tx)), None);
+/// ```
+#[derive(Clone)]
+pub struct TErrorSlot<E> {
+    error: TVar<Option<E>>,
+}
+
+impl<E> TErrorSlot<E>
+    where E: Any + Sync + Send + Clone
+{
+    /// Create a new slot without an error.
+    pub fn new() -> TErrorSlot<E> {
+        TErrorSlot {
+            error: TVar::new(None),
+        }
+    }
+
+    /// Store an error and replace the previous one.
+    pub fn set_error(&self, tx: &mut Transaction, error: E) -> StmResult<()> {
+        self.error.write(tx, Some(error))
+    }
+
+    /// Remove the error from the slot and return it.
+    pub fn take_error(&self, tx: &mut Transaction) -> StmResult<Option<E>> {
+        self.error.replace(tx, None)
+    }
+
+    /// Get the error without removing it.
+    pub fn error(&self, tx: &mut Transaction) -> StmResult<Option<E>> {
+        self.error.read(tx)
+    }
+
+    /// Check if an error is stored.
+    pub fn has_error(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.error.read(tx)?.is_some())
+    }
+}
+
+impl<E> Default for TErrorSlot<E>
+    where E: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TErrorSlot::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::{atomically, retry};
+
+    #[test]
+    fn set_and_take_in_one_transaction() {
+        let errors = TErrorSlot::new();
+
+        let taken = atomically(|tx| {
+            errors.set_error(tx, 1)?;
+            errors.set_error(tx, 2)?;
+            assert!(errors.has_error(tx)?);
+            errors.take_error(tx)
+        });
+
+        assert_eq!(taken, Some(2));
+        assert!(!atomically(|tx| errors.has_error(tx)));
+    }
+
+    /// An error set in a discarded branch is not committed.
+    #[test]
+    fn discarded_with_branch() {
+        let errors = TErrorSlot::new();
+
+        atomically(|tx| tx.or(
+            |tx| {
+                errors.set_error(tx, "first")?;
+                retry()
+            },
+            |_| Ok(()),
+        ));
+
+        assert_eq!(atomically(|tx| errors.error(tx)), None);
+    }
+}
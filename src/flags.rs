@@ -0,0 +1,137 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use stm_core::{guard, StmResult, TVar, Transaction};
+
+/// A group of up to 64 transactional flags.
+///
+/// All flags are packed into a single `TVar<u64>`. That is much cheaper than
+/// a `TVar<bool>` per flag, but all operations on the group conflict with each other.
+/// It is meant for a small number of conditions, that are waited on together.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let flags = TFlags::new();
+///
+/// atomically(|tx| {
+///     flags.set(tx, 0)?;
+///     flags.set(tx, 3)
+/// });
+///
+/// // Returns immediately, because both flags are set.
+/// atomically(|tx| flags.wait_for(tx, 0b1001));
+/// ```
+#[derive(Clone)]
+pub struct TFlags {
+    bits: TVar<u64>,
+}
+
+impl TFlags {
+    /// Create a new group, where no flag is set.
+    pub fn new() -> TFlags {
+        TFlags {
+            bits: TVar::new(0),
+        }
+    }
+
+    /// Get the mask for `bit`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is 64 or more.
+    fn mask(bit: u32) -> u64 {
+        assert!(bit < 64, "TFlags only holds 64 flags");
+        1 << bit
+    }
+
+    /// Set a flag.
+    pub fn set(&self, tx: &mut Transaction, bit: u32) -> StmResult<()> {
+        self.bits.modify(tx, |bits| bits | TFlags::mask(bit))
+    }
+
+    /// Clear a flag.
+    pub fn clear(&self, tx: &mut Transaction, bit: u32) -> StmResult<()> {
+        self.bits.modify(tx, |bits| bits & !TFlags::mask(bit))
+    }
+
+    /// Check if a flag is set.
+    pub fn is_set(&self, tx: &mut Transaction, bit: u32) -> StmResult<bool> {
+        Ok(self.bits.read(tx)? & TFlags::mask(bit) != 0)
+    }
+
+    /// Get all flags as bitset.
+    pub fn get(&self, tx: &mut Transaction) -> StmResult<u64> {
+        self.bits.read(tx)
+    }
+
+    /// Retry until all flags in `mask` are set.
+    pub fn wait_for(&self, tx: &mut Transaction, mask: u64) -> StmResult<()> {
+        let bits = self.bits.read(tx)?;
+        guard(bits & mask == mask)
+    }
+}
+
+impl Default for TFlags {
+    fn default() -> Self {
+        TFlags::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn set_clear() {
+        let flags = TFlags::new();
+
+        atomically(|tx| flags.set(tx, 5));
+        assert!(atomically(|tx| flags.is_set(tx, 5)));
+        assert!(!atomically(|tx| flags.is_set(tx, 4)));
+
+        atomically(|tx| flags.set(tx, 63));
+        assert_eq!(atomically(|tx| flags.get(tx)), 1 << 63 | 1 << 5);
+
+        atomically(|tx| flags.clear(tx, 5));
+        assert_eq!(atomically(|tx| flags.get(tx)), 1 << 63);
+    }
+
+    /// Several threads set one flag each, until the mask of the waiter is satisfied.
+    #[test]
+    fn wait_for_setters() {
+        use std::thread;
+        use std::time::Duration;
+
+        let flags = TFlags::new();
+
+        let setters: Vec<_> = (0..4).map(|bit| {
+            let flags = flags.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10 * u64::from(bit)));
+                atomically(|tx| flags.set(tx, bit));
+            })
+        }).collect();
+
+        atomically(|tx| flags.wait_for(tx, 0b1111));
+        assert_eq!(atomically(|tx| flags.get(tx)), 0b1111);
+
+        for t in setters {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn bit_out_of_range() {
+        let flags = TFlags::new();
+        atomically(|tx| flags.set(tx, 64));
+    }
+}
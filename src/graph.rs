@@ -0,0 +1,242 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// The outgoing edges of a node.
+type Edges<N, E> = TVar<Vec<(N, E)>>;
+
+/// A transactional directed graph with labeled edges.
+///
+/// Every node stores its outgoing edges in a separate `TVar`, so transactions,
+/// that change the edges of different nodes, don't conflict with each other.
+/// Adding an edge to an existing node only reads the node index.
+/// Only adding a new node writes the index and conflicts with every other access.
+///
+/// There is at most one edge between two nodes in the same direction.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let graph = TGraph::new();
+///
+/// atomically(|tx| {
+///     graph.add_edge(tx, "a", "b", 1)?;
+///     graph.add_edge(tx, "a", "c", 2)?;
+///     Ok(())
+/// });
+///
+/// assert_eq!(atomically(|tx| graph.neighbors(tx, &"a")), vec![("b", 1), ("c", 2)]);
+/// assert_eq!(atomically(|tx| graph.neighbors(tx, &"b")), vec![]);
+/// ```
+#[derive(Clone)]
+pub struct TGraph<N, E> {
+    /// The outgoing edges of every node.
+    nodes: TVar<HashMap<N, Edges<N, E>>>,
+}
+
+impl<N, E> TGraph<N, E>
+    where N: Any + Sync + Send + Clone + Hash + Eq,
+          E: Any + Sync + Send + Clone,
+{
+    /// Create an empty graph.
+    pub fn new() -> TGraph<N, E> {
+        TGraph {
+            nodes: TVar::new(HashMap::new()),
+        }
+    }
+
+    /// Get the adjacency list of `node`, if it exists.
+    fn edges(&self, tx: &mut Transaction, node: &N) -> StmResult<Option<Edges<N, E>>> {
+        Ok(self.nodes.read(tx)?.get(node).cloned())
+    }
+
+    /// Add a node without edges.
+    ///
+    /// Return `false`, if the node already exists.
+    pub fn add_node(&self, tx: &mut Transaction, node: N) -> StmResult<bool> {
+        let mut nodes = self.nodes.read(tx)?;
+        if nodes.contains_key(&node) {
+            return Ok(false);
+        }
+        nodes.insert(node, TVar::new(Vec::new()));
+        self.nodes.write(tx, nodes)?;
+        Ok(true)
+    }
+
+    /// Check if the graph contains `node`.
+    pub fn contains_node(&self, tx: &mut Transaction, node: &N) -> StmResult<bool> {
+        Ok(self.nodes.read(tx)?.contains_key(node))
+    }
+
+    /// Add an edge from `from` to `to` and return the label of the
+    /// edge, that it replaces.
+    ///
+    /// Both nodes are added, if they don't exist.
+    pub fn add_edge(&self, tx: &mut Transaction, from: N, to: N, edge: E) -> StmResult<Option<E>> {
+        self.add_node(tx, to.clone())?;
+        let var = match self.edges(tx, &from)? {
+            Some(var) => var,
+            None      => {
+                self.add_node(tx, from.clone())?;
+                // The node has just been added.
+                self.edges(tx, &from)?.unwrap()
+            }
+        };
+
+        let mut edges = var.read(tx)?;
+        let old = match edges.iter_mut().find(|(n, _)| *n == to) {
+            Some(entry) => Some(mem::replace(&mut entry.1, edge)),
+            None        => {
+                edges.push((to, edge));
+                None
+            }
+        };
+        var.write(tx, edges)?;
+        Ok(old)
+    }
+
+    /// Remove the edge from `from` to `to` and return its label.
+    pub fn remove_edge(&self, tx: &mut Transaction, from: &N, to: &N) -> StmResult<Option<E>> {
+        let var = match self.edges(tx, from)? {
+            Some(var) => var,
+            None      => return Ok(None),
+        };
+
+        let mut edges = var.read(tx)?;
+        match edges.iter().position(|(n, _)| n == to) {
+            Some(i) => {
+                let (_, e) = edges.remove(i);
+                var.write(tx, edges)?;
+                Ok(Some(e))
+            }
+            None    => Ok(None),
+        }
+    }
+
+    /// Get the outgoing edges of `node` in the order, in which they have been added.
+    ///
+    /// A node, that does not exist, has no neighbors.
+    pub fn neighbors(&self, tx: &mut Transaction, node: &N) -> StmResult<Vec<(N, E)>> {
+        match self.edges(tx, node)? {
+            Some(var) => var.read(tx),
+            None      => Ok(Vec::new()),
+        }
+    }
+}
+
+impl<N, E> Default for TGraph<N, E>
+    where N: Any + Sync + Send + Clone + Hash + Eq,
+          E: Any + Sync + Send + Clone,
+{
+    fn default() -> Self {
+        TGraph::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn edges() {
+        let graph = TGraph::new();
+
+        assert_eq!(atomically(|tx| graph.add_edge(tx, 1, 2, 'a')), None);
+        assert_eq!(atomically(|tx| graph.add_edge(tx, 1, 3, 'b')), None);
+        assert_eq!(atomically(|tx| graph.add_edge(tx, 1, 2, 'c')), Some('a'));
+        assert!(atomically(|tx| graph.contains_node(tx, &3)));
+        assert!(!atomically(|tx| graph.add_node(tx, 3)));
+
+        assert_eq!(atomically(|tx| graph.neighbors(tx, &1)), vec![(2, 'c'), (3, 'b')]);
+        assert_eq!(atomically(|tx| graph.remove_edge(tx, &1, &2)), Some('c'));
+        assert_eq!(atomically(|tx| graph.remove_edge(tx, &1, &2)), None);
+        assert_eq!(atomically(|tx| graph.neighbors(tx, &1)), vec![(3, 'b')]);
+        assert_eq!(atomically(|tx| graph.neighbors(tx, &4)), vec![]);
+    }
+
+    /// Adding edges to different existing nodes does not conflict.
+    #[test]
+    fn disjoint_nodes_dont_conflict() {
+        let graph = TGraph::new();
+        atomically(|tx| {
+            graph.add_node(tx, 1)?;
+            graph.add_node(tx, 2)?;
+            Ok(())
+        });
+
+        let mut log1 = Transaction::new();
+        let mut log2 = Transaction::new();
+        graph.add_edge(&mut log1, 1, 2, ()).unwrap();
+        graph.add_edge(&mut log2, 2, 1, ()).unwrap();
+
+        assert!(log1.commit());
+        assert!(log2.commit());
+
+        assert_eq!(atomically(|tx| graph.neighbors(tx, &1)), vec![(2, ())]);
+        assert_eq!(atomically(|tx| graph.neighbors(tx, &2)), vec![(1, ())]);
+    }
+
+    /// Concurrent writers, while a reader sees a consistent view.
+    #[test]
+    fn concurrent_edges() {
+        use std::thread;
+
+        const NODES: usize = 4;
+        const EDGES: usize = 100;
+
+        // Add all nodes upfront, so that the writers don't conflict on the index.
+        let graph = TGraph::new();
+        atomically(|tx| {
+            for n in 0..NODES * (2 * EDGES + 1) {
+                graph.add_node(tx, n)?;
+            }
+            Ok(())
+        });
+
+        let writers: Vec<_> = (0..NODES).map(|n| {
+            let graph = graph.clone();
+            thread::spawn(move || {
+                for i in 0..EDGES {
+                    // Keep the edges of all nodes in sync by writing one edge
+                    // to the node and its successor in the same transaction.
+                    atomically(|tx| {
+                        graph.add_edge(tx, n, NODES + n * EDGES + i, ())?;
+                        graph.add_edge(tx, (n + 1) % NODES, NODES * (EDGES + 1) + n * EDGES + i, ())?;
+                        Ok(())
+                    });
+                }
+            })
+        }).collect();
+
+        for _ in 0..50 {
+            let total: usize = atomically(|tx| {
+                let mut total = 0;
+                for n in 0..NODES {
+                    total += graph.neighbors(tx, &n)?.len();
+                }
+                Ok(total)
+            });
+            assert_eq!(total % 2, 0);
+        }
+
+        for w in writers {
+            w.join().unwrap();
+        }
+        for n in 0..NODES {
+            assert_eq!(atomically(|tx| graph.neighbors(tx, &n)).len(), 2 * EDGES);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional hash map, that is striped over several buckets.
+///
+/// Every bucket is a separate `TVar`, so transactions, that access
+/// keys in different buckets, don't conflict with each other.
+/// The bucket of a key is chosen by its hash. The hasher can be replaced
+/// with `with_hasher` in order to control, which keys share a bucket.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let map = THashMap::new(16);
+///
+/// atomically(|tx| map.insert(tx, "answer", 42));
+/// let x = atomically(|tx| map.get(tx, &"answer"));
+///
+/// assert_eq!(x, Some(42));
+/// ```
+#[derive(Clone)]
+pub struct THashMap<K, V, S = RandomState> {
+    buckets: Vec<TVar<Vec<(K, V)>>>,
+    hasher: S,
+}
+
+impl<K, V> THashMap<K, V>
+    where K: Any + Sync + Send + Clone + Hash + Eq,
+          V: Any + Sync + Send + Clone,
+{
+    /// Create an empty map with `n_buckets` buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n_buckets` is zero.
+    pub fn new(n_buckets: usize) -> THashMap<K, V> {
+        THashMap::with_hasher(n_buckets, RandomState::new())
+    }
+}
+
+impl<K, V, S> THashMap<K, V, S>
+    where K: Any + Sync + Send + Clone + Hash + Eq,
+          V: Any + Sync + Send + Clone,
+          S: BuildHasher,
+{
+    /// Create an empty map with `n_buckets` buckets, that uses
+    /// `hasher` to distribute the keys over the buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n_buckets` is zero.
+    pub fn with_hasher(n_buckets: usize, hasher: S) -> THashMap<K, V, S> {
+        assert!(n_buckets > 0, "THashMap needs at least one bucket");
+        THashMap {
+            buckets: (0..n_buckets).map(|_| TVar::new(Vec::new())).collect(),
+            hasher,
+        }
+    }
+
+    /// Get the bucket, that contains `key`.
+    fn bucket(&self, key: &K) -> &TVar<Vec<(K, V)>> {
+        let hash = self.hasher.hash_one(key);
+        &self.buckets[(hash % self.buckets.len() as u64) as usize]
+    }
+
+    /// Get a copy of the value for `key`.
+    pub fn get(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let bucket = self.bucket(key).read(tx)?;
+        Ok(bucket.into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v))
+    }
+
+    /// Check if the map contains `key`.
+    pub fn contains_key(&self, tx: &mut Transaction, key: &K) -> StmResult<bool> {
+        let bucket = self.bucket(key).read(tx)?;
+        Ok(bucket.iter().any(|(k, _)| k == key))
+    }
+
+    /// Insert a value and return the old one.
+    pub fn insert(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let var = self.bucket(&key);
+        let mut bucket = var.read(tx)?;
+
+        let old = match bucket.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => Some(mem::replace(&mut entry.1, value)),
+            None        => {
+                bucket.push((key, value));
+                None
+            }
+        };
+        var.write(tx, bucket)?;
+        Ok(old)
+    }
+
+    /// Remove a value and return it.
+    pub fn remove(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let var = self.bucket(key);
+        let mut bucket = var.read(tx)?;
+
+        match bucket.iter().position(|(k, _)| k == key) {
+            Some(i) => {
+                let (_, v) = bucket.swap_remove(i);
+                var.write(tx, bucket)?;
+                Ok(Some(v))
+            }
+            None    => Ok(None),
+        }
+    }
+
+    /// Number of entries in the map.
+    ///
+    /// This reads all buckets and therefore conflicts with every write.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        let mut len = 0;
+        for bucket in &self.buckets {
+            len += bucket.read(tx)?.len();
+        }
+        Ok(len)
+    }
+
+    /// Check if the map is empty.
+    ///
+    /// This reads all buckets and therefore conflicts with every write.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.len(tx)? == 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::hash::{BuildHasherDefault, Hasher};
+    use std::thread;
+    use stm_core::{atomically, atomically_counted};
+
+    /// Hasher, that uses the integer key as the hash.
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes.iter().rev() {
+                self.0 = (self.0 << 8) | u64::from(*b);
+            }
+        }
+    }
+
+    type IdentityMap = THashMap<u64, u64, BuildHasherDefault<IdentityHasher>>;
+
+    #[test]
+    fn insert_get_remove() {
+        let map = THashMap::new(4);
+
+        assert_eq!(atomically(|tx| map.insert(tx, 1, "a")), None);
+        assert_eq!(atomically(|tx| map.insert(tx, 1, "b")), Some("a"));
+        assert_eq!(atomically(|tx| map.get(tx, &1)), Some("b"));
+        assert_eq!(atomically(|tx| map.len(tx)), 1);
+
+        assert_eq!(atomically(|tx| map.remove(tx, &1)), Some("b"));
+        assert_eq!(atomically(|tx| map.remove(tx, &1)), None);
+        assert!(atomically(|tx| map.is_empty(tx)));
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let map = IdentityMap::with_hasher(4, Default::default());
+
+        atomically(|tx| {
+            for i in 0..20 {
+                map.insert(tx, i, i * 10)?;
+            }
+            Ok(())
+        });
+
+        for i in 0..20 {
+            assert_eq!(atomically(|tx| map.get(tx, &i)), Some(i * 10));
+        }
+        // The keys are spread evenly.
+        for bucket in &map.buckets {
+            assert_eq!(bucket.read_atomic().len(), 5);
+        }
+    }
+
+    /// Insert `concurrent` from another thread in the middle of
+    /// a transaction, that updates `key`, and return the number of reruns.
+    fn reruns_with_concurrent_insert(key: u64, concurrent: u64) -> usize {
+        let map = IdentityMap::with_hasher(4, Default::default());
+        let first = TVar::new(true);
+
+        let (_, reruns) = atomically_counted(|tx| {
+            let x = map.get(tx, &key)?.unwrap_or(0);
+            if first.read_atomic() {
+                let (map, first) = (map.clone(), first.clone());
+                thread::spawn(move || atomically(|tx| {
+                    first.write(tx, false)?;
+                    map.insert(tx, concurrent, 1)
+                })).join().unwrap();
+            }
+            map.insert(tx, key, x + 1)
+        });
+        reruns
+    }
+
+    /// Keys in different buckets don't conflict, keys in the same bucket do.
+    #[test]
+    fn bucket_conflicts() {
+        assert_eq!(reruns_with_concurrent_insert(0, 1), 0);
+        assert_eq!(reruns_with_concurrent_insert(0, 4), 1);
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use im::Vector;
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional append-only journal of events.
+///
+/// Every event gets a fixed index in the order of the commits.
+/// Readers can remember the length of the journal and later
+/// fetch all events, that were appended since.
+///
+/// The events are stored in a persistent vector, so appending does not copy
+/// the journal and reading a range only clones the requested events.
+/// All appends still go through one var, so concurrent appends conflict
+/// and are serialized.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let journal = TJournal::new();
+///
+/// atomically(|tx| {
+///     journal.append(tx, "created")?;
+///     journal.append(tx, "updated")
+/// });
+///
+/// let events = atomically(|tx| journal.read_from(tx, 1));
+/// assert_eq!(events, ["updated"]);
+/// ```
+#[derive(Clone)]
+pub struct TJournal<T> {
+    events: TVar<Vector<T>>,
+}
+
+impl<T> TJournal<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create an empty journal.
+    pub fn new() -> TJournal<T> {
+        TJournal {
+            events: TVar::new(Vector::new()),
+        }
+    }
+
+    /// Append an event and return its index.
+    pub fn append(&self, tx: &mut Transaction, event: T) -> StmResult<usize> {
+        let mut events = self.events.read(tx)?;
+        let index = events.len();
+        events.push_back(event);
+        self.events.write(tx, events)?;
+        Ok(index)
+    }
+
+    /// Number of events in the journal.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.events.read(tx)?.len())
+    }
+
+    /// Check if the journal is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.events.read(tx)?.is_empty())
+    }
+
+    /// Get all events starting at `index`.
+    ///
+    /// Return an empty vector, if `index` is not smaller than the length.
+    pub fn read_from(&self, tx: &mut Transaction, index: usize) -> StmResult<Vec<T>> {
+        let events = self.events.read(tx)?;
+        if index >= events.len() {
+            return Ok(Vec::new());
+        }
+        Ok(events.skip(index).into_iter().collect())
+    }
+}
+
+impl<T> Default for TJournal<T>
+    where T: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TJournal::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn append_read_from() {
+        let journal = TJournal::new();
+
+        assert_eq!(atomically(|tx| journal.append(tx, 1)), 0);
+        assert_eq!(atomically(|tx| journal.append(tx, 2)), 1);
+        assert_eq!(atomically(|tx| journal.append(tx, 3)), 2);
+
+        assert_eq!(atomically(|tx| journal.len(tx)), 3);
+        assert_eq!(atomically(|tx| journal.read_from(tx, 0)), [1, 2, 3]);
+        assert_eq!(atomically(|tx| journal.read_from(tx, 2)), [3]);
+        assert!(atomically(|tx| journal.read_from(tx, 5)).is_empty());
+    }
+
+    /// Concurrent appenders don't lose events and every appender sees its
+    /// own events in order, while a reader follows the journal.
+    #[test]
+    fn concurrent_appenders() {
+        use std::thread;
+
+        const THREADS: usize = 4;
+        const EVENTS: usize = 100;
+
+        let journal = TJournal::new();
+
+        let appenders: Vec<_> = (0..THREADS).map(|t| {
+            let journal = journal.clone();
+            thread::spawn(move || {
+                for i in 0..EVENTS {
+                    atomically(|tx| journal.append(tx, (t, i)));
+                }
+            })
+        }).collect();
+
+        // Follow the journal while it is written.
+        let mut seen = Vec::new();
+        while seen.len() < THREADS * EVENTS {
+            let new = atomically(|tx| journal.read_from(tx, seen.len()));
+            seen.extend(new);
+        }
+
+        for t in appenders {
+            t.join().unwrap();
+        }
+
+        assert_eq!(seen, atomically(|tx| journal.read_from(tx, 0)));
+        for t in 0..THREADS {
+            let own: Vec<_> = seen.iter()
+                .filter(|e| e.0 == t)
+                .map(|e| e.1)
+                .collect();
+            assert_eq!(own, (0..EVENTS).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use stm_core::{guard, StmResult, TVar, Transaction};
+
+/// A transactional latch, that can be set once and never be reset.
+///
+/// Because the latch only changes from unset to set, a waiter, that has seen it
+/// set, never needs to check it again.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// # use std::thread;
+/// let latch = TLatch::new();
+/// let latchc = latch.clone();
+///
+/// let t = thread::spawn(move || atomically(|tx| latchc.wait(tx)));
+///
+/// atomically(|tx| latch.set(tx));
+/// t.join().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct TLatch {
+    set: TVar<bool>,
+}
+
+impl TLatch {
+    /// Create a new latch, that is not set.
+    pub fn new() -> TLatch {
+        TLatch {
+            set: TVar::new(false),
+        }
+    }
+
+    /// Set the latch.
+    ///
+    /// Setting it again does nothing.
+    pub fn set(&self, tx: &mut Transaction) -> StmResult<()> {
+        // Don't write a set latch, so that its readers don't conflict.
+        if !self.set.read(tx)? {
+            self.set.write(tx, true)?;
+        }
+        Ok(())
+    }
+
+    /// Check if the latch is set.
+    pub fn is_set(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.set.read(tx)
+    }
+
+    /// Retry until the latch is set.
+    pub fn wait(&self, tx: &mut Transaction) -> StmResult<()> {
+        guard(self.set.read(tx)?)
+    }
+}
+
+impl Default for TLatch {
+    fn default() -> Self {
+        TLatch::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    /// All waiters proceed, when the latch is set.
+    #[test]
+    fn wakes_all_waiters() {
+        use std::thread;
+        use std::time::Duration;
+
+        let latch = TLatch::new();
+
+        let waiters: Vec<_> = (0..8).map(|_| {
+            let latch = latch.clone();
+            thread::spawn(move || atomically(|tx| latch.wait(tx)))
+        }).collect();
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!atomically(|tx| latch.is_set(tx)));
+        atomically(|tx| latch.set(tx));
+
+        for w in waiters {
+            w.join().unwrap();
+        }
+    }
+
+    /// Setting a set latch does not write it again.
+    #[test]
+    fn set_twice() {
+        use std::sync::Arc;
+
+        let latch = TLatch::new();
+
+        atomically(|tx| latch.set(tx));
+        let before = latch.set.read_ref_atomic();
+
+        atomically(|tx| latch.set(tx));
+        assert!(atomically(|tx| latch.is_set(tx)));
+        assert!(Arc::ptr_eq(&before, &latch.set.read_ref_atomic()));
+    }
+}
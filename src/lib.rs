@@ -85,21 +85,21 @@
 //! you should obey when dealing with software transactional memory.
 //!
 //! * Don't run code with side effects, especially no IO-code.
-//! Transactions repeat in failure cases. Using IO would repeat this IO-code.
-//! Return a closure if you have to.
+//!   Transactions repeat in failure cases. Using IO would repeat this IO-code.
+//!   Return a closure if you have to.
 //! * Don't handle `StmResult` yourself.
-//! Use `Transaction::or` to combine alternative paths and `optionally` to check if an inner
-//! function has failed. Always use `?` and 
-//! never ignore a `StmResult`.
+//!   Use `Transaction::or` to combine alternative paths and `optionally` to check if an inner
+//!   function has failed. Always use `?` and 
+//!   never ignore a `StmResult`.
 //! * Don't run `atomically` inside of another. `atomically` is designed to have side effects
-//! and will therefore break transaction safety. 
-//! Nested calls are detected at runtime and handled with panicking.
-//! When you use STM in the inner of a function, then
-//! express it in the public interface, by taking `&mut Transaction` as parameter and 
-//! returning `StmResult<T>`. Callers can safely compose it into
-//! larger blocks.
+//!   and will therefore break transaction safety. 
+//!   Nested calls are detected at runtime and handled with panicking.
+//!   When you use STM in the inner of a function, then
+//!   express it in the public interface, by taking `&mut Transaction` as parameter and 
+//!   returning `StmResult<T>`. Callers can safely compose it into
+//!   larger blocks.
 //! * Don't mix locks and transactions. Your code will easily deadlock or slow
-//! down unpredictably.
+//!   down unpredictably.
 //! * Don't use inner mutability to change the content of a `TVar`.
 //!
 //! Panicking in a transaction is transaction-safe. The transaction aborts and 
@@ -127,7 +127,7 @@
 //! The programming model for deterministic STM is a bit more involved:
 //!
 //! ```
-//! # use stm_core::{ dtm, det_atomically, freeze};
+//! # use stm::{ dtm, det_atomically, freeze};
 //! # use std::thread;
 //!
 //! let f = |tx: &mut _| {
@@ -157,6 +157,9 @@
 //! Whether we call `freeze` before or after spawning the threads for the transactions is not
 //! important.
 //! But processing only starts when the set of transactions is frozen.
+//! Dropping the `DTM` without `freeze` freezes it as well.
+//! `DTM::run` freezes the set as well, but coordinates on the calling thread and
+//! returns only after all transactions have committed.
 //!
 //! ## Limitations of the programming model
 //! 
@@ -181,6 +184,57 @@
 //! of the execution.
 //!
 extern crate stm_core;
+extern crate im;
 
 // Reexport everything from stm-core.
 pub use stm_core::*;
+
+mod pool;
+mod barrier;
+mod persistent_map;
+mod journal;
+mod hash_map;
+mod flags;
+mod rendezvous;
+mod promise;
+mod error_slot;
+mod latch;
+mod ring_buffer;
+mod btree_map;
+mod versioned;
+mod graph;
+mod pair;
+mod lru_cache;
+mod config;
+mod condvar;
+mod state_machine;
+mod multi_set;
+mod double_buffer;
+mod queue;
+mod mvar;
+mod vec;
+
+pub use pool::TPool;
+pub use barrier::TBarrier;
+pub use persistent_map::PersistentTMap;
+pub use journal::TJournal;
+pub use hash_map::THashMap;
+pub use flags::TFlags;
+pub use rendezvous::TRendezvous;
+pub use promise::TPromise;
+pub use error_slot::TErrorSlot;
+pub use latch::TLatch;
+pub use ring_buffer::TRingBuffer;
+pub use btree_map::TBTreeMap;
+pub use versioned::TVersioned;
+pub use graph::TGraph;
+pub use pair::TPair;
+pub use lru_cache::TLruCache;
+pub use config::TConfig;
+pub use condvar::TCondvar;
+pub use state_machine::TStateMachine;
+pub use multi_set::TMultiSet;
+pub use double_buffer::TDoubleBuffer;
+pub use queue::TQueue;
+pub use mvar::TMVar;
+pub use vec::TVec;
@@ -0,0 +1,210 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// The entries of the cache together with their recency.
+#[derive(Clone)]
+struct Entries<K, V> {
+    map: HashMap<K, V>,
+
+    /// The keys from the least to the most recently used.
+    order: VecDeque<K>,
+}
+
+impl<K, V> Entries<K, V>
+    where K: Clone + Hash + Eq
+{
+    /// Mark `key` as the most recently used.
+    fn touch(&mut self, key: &K) {
+        if let Some(i) = self.order.iter().position(|k| k == key) {
+            let k = self.order.remove(i).unwrap();
+            self.order.push_back(k);
+        }
+    }
+}
+
+/// A transactional cache, that evicts the least recently used entry, when it is full.
+///
+/// The whole cache is stored in a single `TVar`.
+/// `get` marks the entry as recently used and therefore writes the cache,
+/// so concurrent `get`s conflict with each other like writes do.
+/// Use `peek` for lookups, that should not update the recency and only read.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let cache = TLruCache::new(2);
+///
+/// atomically(|tx| {
+///     cache.put(tx, "a", 1)?;
+///     cache.put(tx, "b", 2)?;
+///
+///     // "a" is used, so "b" is evicted next.
+///     cache.get(tx, &"a")?;
+///     cache.put(tx, "c", 3)
+/// });
+///
+/// assert_eq!(atomically(|tx| cache.peek(tx, &"b")), None);
+/// assert_eq!(atomically(|tx| cache.peek(tx, &"a")), Some(1));
+/// ```
+#[derive(Clone)]
+pub struct TLruCache<K, V> {
+    capacity: usize,
+    entries: TVar<Entries<K, V>>,
+}
+
+impl<K, V> TLruCache<K, V>
+    where K: Any + Sync + Send + Clone + Hash + Eq,
+          V: Any + Sync + Send + Clone,
+{
+    /// Create an empty cache, that holds up to `capacity` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> TLruCache<K, V> {
+        assert!(capacity > 0, "TLruCache needs a capacity of at least 1");
+        TLruCache {
+            capacity,
+            entries: TVar::new(Entries {
+                map: HashMap::with_capacity(capacity),
+                order: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    /// Get a copy of the value for `key` and mark it as the most recently used.
+    pub fn get(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let mut entries = self.entries.read(tx)?;
+        let value = entries.map.get(key).cloned();
+        if value.is_some() {
+            entries.touch(key);
+            self.entries.write(tx, entries)?;
+        }
+        Ok(value)
+    }
+
+    /// Get a copy of the value for `key` without changing the recency.
+    pub fn peek(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        Ok(self.entries.read(tx)?.map.get(key).cloned())
+    }
+
+    /// Insert a value and mark it as the most recently used.
+    ///
+    /// If the cache is full, the least recently used entry is evicted and returned.
+    pub fn put(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<(K, V)>> {
+        let mut entries = self.entries.read(tx)?;
+
+        let mut evicted = None;
+        if entries.map.insert(key.clone(), value).is_some() {
+            entries.touch(&key);
+        } else {
+            entries.order.push_back(key);
+            if entries.order.len() > self.capacity {
+                let k = entries.order.pop_front().unwrap();
+                let v = entries.map.remove(&k).unwrap();
+                evicted = Some((k, v));
+            }
+        }
+
+        self.entries.write(tx, entries)?;
+        Ok(evicted)
+    }
+
+    /// Remove an entry and return its value.
+    pub fn remove(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let mut entries = self.entries.read(tx)?;
+        let value = entries.map.remove(key);
+        if value.is_some() {
+            entries.order.retain(|k| k != key);
+            self.entries.write(tx, entries)?;
+        }
+        Ok(value)
+    }
+
+    /// Number of entries in the cache.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.entries.read(tx)?.map.len())
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.entries.read(tx)?.map.is_empty())
+    }
+
+    /// Maximum number of entries in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn eviction_order() {
+        let cache = TLruCache::new(3);
+
+        atomically(|tx| {
+            for k in 1..4 {
+                cache.put(tx, k, k * 10)?;
+            }
+            Ok(())
+        });
+
+        // Use 1, so that 2 is the least recently used.
+        assert_eq!(atomically(|tx| cache.get(tx, &1)), Some(10));
+        assert_eq!(atomically(|tx| cache.put(tx, 4, 40)), Some((2, 20)));
+
+        // `peek` does not change the order, so 3 is evicted next.
+        assert_eq!(atomically(|tx| cache.peek(tx, &3)), Some(30));
+        assert_eq!(atomically(|tx| cache.put(tx, 5, 50)), Some((3, 30)));
+
+        // Updating an entry marks it as used and evicts nothing.
+        assert_eq!(atomically(|tx| cache.put(tx, 1, 11)), None);
+        assert_eq!(atomically(|tx| cache.put(tx, 6, 60)), Some((4, 40)));
+
+        assert_eq!(atomically(|tx| cache.remove(tx, &5)), Some(50));
+        assert_eq!(atomically(|tx| cache.len(tx)), 2);
+        assert_eq!(atomically(|tx| cache.get(tx, &1)), Some(11));
+    }
+
+    /// Concurrent `put`s never exceed the capacity.
+    #[test]
+    fn concurrent_capacity() {
+        use std::thread;
+
+        let cache = TLruCache::new(10);
+
+        let writers: Vec<_> = (0..4).map(|t| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    atomically(|tx| cache.put(tx, t * 1000 + i, i));
+                }
+            })
+        }).collect();
+
+        for _ in 0..100 {
+            assert!(atomically(|tx| cache.len(tx)) <= 10);
+        }
+
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(atomically(|tx| cache.len(tx)), 10);
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use stm_core::{retry, StmResult, TVar, Transaction};
+
+/// A transactional multiset, that counts how often every item has been added.
+///
+/// It is useful for tokens and tagged resources, where a consumer takes
+/// one of several kinds of items. `remove_one_of` blocks, until one of them is present.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let tokens = TMultiSet::new();
+///
+/// atomically(|tx| {
+///     tokens.add(tx, "read")?;
+///     tokens.add(tx, "read")?;
+///     tokens.add(tx, "write")
+/// });
+///
+/// assert_eq!(atomically(|tx| tokens.count(tx, &"read")), 2);
+/// assert_eq!(atomically(|tx| tokens.remove_one_of(tx, &["write", "read"])), "write");
+/// ```
+#[derive(Clone)]
+pub struct TMultiSet<T> {
+    counts: TVar<HashMap<T, usize>>,
+}
+
+impl<T> TMultiSet<T>
+    where T: Any + Sync + Send + Clone + Hash + Eq
+{
+    /// Create an empty multiset.
+    pub fn new() -> TMultiSet<T> {
+        TMultiSet {
+            counts: TVar::new(HashMap::new()),
+        }
+    }
+
+    /// Add an item once.
+    pub fn add(&self, tx: &mut Transaction, item: T) -> StmResult<()> {
+        let mut counts = self.counts.read(tx)?;
+        *counts.entry(item).or_insert(0) += 1;
+        self.counts.write(tx, counts)
+    }
+
+    /// Remove an item once.
+    ///
+    /// Return `false`, if the item is not present.
+    pub fn remove_one(&self, tx: &mut Transaction, item: &T) -> StmResult<bool> {
+        let mut counts = self.counts.read(tx)?;
+        let removed = match counts.get_mut(item) {
+            Some(1)     => { counts.remove(item); true }
+            Some(n)     => { *n -= 1; true }
+            None        => false,
+        };
+        if removed {
+            self.counts.write(tx, counts)?;
+        }
+        Ok(removed)
+    }
+
+    /// Remove the first of `items`, that is present, once and return it.
+    ///
+    /// Retries until one of them is present.
+    pub fn remove_one_of(&self, tx: &mut Transaction, items: &[T]) -> StmResult<T> {
+        for item in items {
+            if self.remove_one(tx, item)? {
+                return Ok(item.clone());
+            }
+        }
+        retry()
+    }
+
+    /// Get, how often `item` is present.
+    pub fn count(&self, tx: &mut Transaction, item: &T) -> StmResult<usize> {
+        Ok(self.counts.read(tx)?.get(item).cloned().unwrap_or(0))
+    }
+
+    /// Number of items, counting every occurrence.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.counts.read(tx)?.values().sum())
+    }
+
+    /// Check if the multiset is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.counts.read(tx)?.is_empty())
+    }
+}
+
+impl<T> Default for TMultiSet<T>
+    where T: Any + Sync + Send + Clone + Hash + Eq
+{
+    fn default() -> Self {
+        TMultiSet::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn counts() {
+        let set = TMultiSet::new();
+
+        atomically(|tx| {
+            set.add(tx, 'a')?;
+            set.add(tx, 'a')?;
+            set.add(tx, 'b')
+        });
+        assert_eq!(atomically(|tx| set.len(tx)), 3);
+
+        assert!(atomically(|tx| set.remove_one(tx, &'a')));
+        assert_eq!(atomically(|tx| set.count(tx, &'a')), 1);
+        assert!(atomically(|tx| set.remove_one(tx, &'a')));
+        assert!(!atomically(|tx| set.remove_one(tx, &'a')));
+        assert_eq!(atomically(|tx| set.count(tx, &'a')), 0);
+        assert_eq!(atomically(|tx| set.len(tx)), 1);
+    }
+
+    /// `remove_one_of` blocks until a matching item is added and takes only one.
+    #[test]
+    fn remove_one_of_blocks() {
+        use std::thread;
+        use std::time::Duration;
+
+        let set = TMultiSet::new();
+        atomically(|tx| set.add(tx, 0));
+
+        let consumer = {
+            let set = set.clone();
+            thread::spawn(move || atomically(|tx| set.remove_one_of(tx, &[1, 2])))
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!consumer.is_finished());
+
+        atomically(|tx| {
+            set.add(tx, 2)?;
+            set.add(tx, 2)
+        });
+
+        assert_eq!(consumer.join().unwrap(), 2);
+        assert_eq!(atomically(|tx| set.count(tx, &2)), 1);
+        assert_eq!(atomically(|tx| set.count(tx, &0)), 1);
+    }
+}
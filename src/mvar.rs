@@ -0,0 +1,140 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{guard, optionally, unwrap_or_retry, StmResult, TVar, Transaction};
+
+/// A transactional box, that is either empty or holds one value.
+///
+/// It is the `TMVar` of Haskell and hands values over from one thread to another.
+/// `take` retries while the box is empty and `put` retries while it is full.
+/// `try_take` and `try_put` return immediately instead.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let mvar = TMVar::new_empty();
+///
+/// atomically(|tx| mvar.put(tx, 1));
+/// assert_eq!(atomically(|tx| mvar.try_put(tx, 2)), Err(2));
+///
+/// assert_eq!(atomically(|tx| mvar.take(tx)), 1);
+/// assert_eq!(atomically(|tx| mvar.try_take(tx)), None);
+/// ```
+#[derive(Clone)]
+pub struct TMVar<T> {
+    value: TVar<Option<T>>,
+}
+
+impl<T> TMVar<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create a box, that holds `value`.
+    pub fn new(value: T) -> TMVar<T> {
+        TMVar {
+            value: TVar::new(Some(value)),
+        }
+    }
+
+    /// Create an empty box.
+    pub fn new_empty() -> TMVar<T> {
+        TMVar {
+            value: TVar::new(None),
+        }
+    }
+
+    /// Take the value out of the box.
+    ///
+    /// Retries while the box is empty.
+    pub fn take(&self, tx: &mut Transaction) -> StmResult<T> {
+        let value = unwrap_or_retry(self.value.read(tx)?)?;
+        self.value.write(tx, None)?;
+        Ok(value)
+    }
+
+    /// Put a value into the box.
+    ///
+    /// Retries while the box is full.
+    pub fn put(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        guard(self.value.read(tx)?.is_none())?;
+        self.value.write(tx, Some(value))
+    }
+
+    /// Take the value out of the box, if there is one.
+    pub fn try_take(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        optionally(tx, |tx| self.take(tx))
+    }
+
+    /// Put a value into the box, if it is empty.
+    ///
+    /// If the box is full, `value` is given back as error.
+    pub fn try_put(&self, tx: &mut Transaction, value: T) -> StmResult<Result<(), T>> {
+        match optionally(tx, |tx| self.put(tx, value.clone()))? {
+            Some(()) => Ok(Ok(())),
+            None => Ok(Err(value)),
+        }
+    }
+
+    /// Check if the box is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.value.read(tx)?.is_none())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn take_put() {
+        let mvar = TMVar::new(1);
+
+        assert_eq!(atomically(|tx| mvar.try_put(tx, 2)), Err(2));
+        assert_eq!(atomically(|tx| mvar.take(tx)), 1);
+        assert!(atomically(|tx| mvar.is_empty(tx)));
+        assert_eq!(atomically(|tx| mvar.try_take(tx)), None);
+
+        assert_eq!(atomically(|tx| mvar.try_put(tx, 3)), Ok(()));
+        assert_eq!(atomically(|tx| mvar.try_take(tx)), Some(3));
+    }
+
+    /// Two threads pass a counter back and forth through two boxes.
+    #[test]
+    fn ping_pong() {
+        use std::thread;
+
+        const ROUNDS: usize = 100;
+
+        let ping = TMVar::new_empty();
+        let pong = TMVar::new_empty();
+
+        let other = {
+            let (ping, pong) = (ping.clone(), pong.clone());
+            thread::spawn(move || {
+                for _ in 0..ROUNDS {
+                    let x = atomically(|tx| ping.take(tx));
+                    atomically(|tx| pong.put(tx, x + 1));
+                }
+            })
+        };
+
+        let mut x = 0;
+        for _ in 0..ROUNDS {
+            atomically(|tx| ping.put(tx, x));
+            x = atomically(|tx| pong.take(tx));
+        }
+
+        other.join().unwrap();
+        assert_eq!(x, ROUNDS);
+        assert!(atomically(|tx| ping.is_empty(tx)));
+        assert!(atomically(|tx| pong.is_empty(tx)));
+    }
+}
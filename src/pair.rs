@@ -0,0 +1,100 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// Two `TVar`s, that must be updated together.
+///
+/// `TPair` does not give more guarantees than accessing both vars in the same
+/// transaction. It only states the contract in the type, so that no code
+/// can write one of the values without the other.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// // The balances of two accounts, that sum up to 100.
+/// let accounts = TPair::new(60, 40);
+///
+/// atomically(|tx| {
+///     let (a, b) = accounts.read_both(tx)?;
+///     accounts.write_both(tx, a - 10, b + 10)
+/// });
+///
+/// assert_eq!(atomically(|tx| accounts.read_both(tx)), (50, 50));
+/// ```
+#[derive(Clone)]
+pub struct TPair<A, B> {
+    first: TVar<A>,
+    second: TVar<B>,
+}
+
+impl<A, B> TPair<A, B>
+    where A: Any + Sync + Send + Clone,
+          B: Any + Sync + Send + Clone,
+{
+    /// Create a new pair.
+    pub fn new(a: A, b: B) -> TPair<A, B> {
+        TPair {
+            first: TVar::new(a),
+            second: TVar::new(b),
+        }
+    }
+
+    /// Read both values.
+    pub fn read_both(&self, tx: &mut Transaction) -> StmResult<(A, B)> {
+        Ok((self.first.read(tx)?, self.second.read(tx)?))
+    }
+
+    /// Write both values.
+    pub fn write_both(&self, tx: &mut Transaction, a: A, b: B) -> StmResult<()> {
+        self.first.write(tx, a)?;
+        self.second.write(tx, b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    /// The sum of both values never changes, while several threads move
+    /// amounts between them.
+    #[test]
+    fn sum_invariant() {
+        use std::thread;
+
+        let pair = TPair::new(1000i64, 0i64);
+
+        let writers: Vec<_> = (0..4).map(|t| {
+            let pair = pair.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    let amount = if (t + i) % 2 == 0 { 3 } else { -3 };
+                    atomically(|tx| {
+                        let (a, b) = pair.read_both(tx)?;
+                        pair.write_both(tx, a - amount, b + amount)
+                    });
+                }
+            })
+        }).collect();
+
+        for _ in 0..100 {
+            let (a, b) = atomically(|tx| pair.read_both(tx));
+            assert_eq!(a + b, 1000);
+        }
+
+        for w in writers {
+            w.join().unwrap();
+        }
+        let (a, b) = atomically(|tx| pair.read_both(tx));
+        assert_eq!(a + b, 1000);
+    }
+}
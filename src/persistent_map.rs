@@ -0,0 +1,234 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::hash::Hash;
+
+use im::HashMap;
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional hash map with cheap snapshots.
+///
+/// The map is stored as a persistent hash array mapped trie in a single `TVar`.
+/// Cloning it only increments a reference count, and a write copies only the
+/// path to the changed entry instead of the whole map.
+/// Readers therefore get a consistent snapshot of the whole map almost for free.
+///
+/// The downside is, that every write conflicts with every other access to the map.
+/// It is best suited for maps, that are read often and written rarely.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let map = PersistentTMap::new();
+///
+/// atomically(|tx| map.insert(tx, "answer", 42));
+/// let x = atomically(|tx| map.get(tx, &"answer"));
+///
+/// assert_eq!(x, Some(42));
+/// ```
+#[derive(Clone)]
+pub struct PersistentTMap<K, V> {
+    map: TVar<HashMap<K, V>>,
+}
+
+impl<K, V> PersistentTMap<K, V>
+    where K: Any + Sync + Send + Clone + Hash + Eq,
+          V: Any + Sync + Send + Clone,
+{
+    /// Create an empty map.
+    pub fn new() -> PersistentTMap<K, V> {
+        PersistentTMap {
+            map: TVar::new(HashMap::new()),
+        }
+    }
+
+    /// Get a copy of the value for `key`.
+    pub fn get(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        Ok(self.map.read(tx)?.get(key).cloned())
+    }
+
+    /// Check if the map contains `key`.
+    pub fn contains_key(&self, tx: &mut Transaction, key: &K) -> StmResult<bool> {
+        Ok(self.map.read(tx)?.contains_key(key))
+    }
+
+    /// Insert a value and return the old one.
+    pub fn insert(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let mut map = self.map.read(tx)?;
+        let old = map.insert(key, value);
+        self.map.write(tx, map)?;
+        Ok(old)
+    }
+
+    /// Remove a value and return it.
+    pub fn remove(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let mut map = self.map.read(tx)?;
+        let old = map.remove(key);
+        if old.is_some() {
+            self.map.write(tx, map)?;
+        }
+        Ok(old)
+    }
+
+    /// Number of entries in the map.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.map.read(tx)?.len())
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.map.read(tx)?.is_empty())
+    }
+
+    /// Get a snapshot of the whole map.
+    ///
+    /// This is cheap, because the snapshot shares its structure with the map.
+    pub fn snapshot(&self, tx: &mut Transaction) -> StmResult<HashMap<K, V>> {
+        self.map.read(tx)
+    }
+}
+
+impl<K, V> Default for PersistentTMap<K, V>
+    where K: Any + Sync + Send + Clone + Hash + Eq,
+          V: Any + Sync + Send + Clone,
+{
+    fn default() -> Self {
+        PersistentTMap::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn insert_get_remove() {
+        let map = PersistentTMap::new();
+
+        assert_eq!(atomically(|tx| map.insert(tx, 1, "a")), None);
+        assert_eq!(atomically(|tx| map.insert(tx, 1, "b")), Some("a"));
+        assert_eq!(atomically(|tx| map.get(tx, &1)), Some("b"));
+        assert_eq!(atomically(|tx| map.len(tx)), 1);
+
+        assert_eq!(atomically(|tx| map.remove(tx, &1)), Some("b"));
+        assert_eq!(atomically(|tx| map.remove(tx, &1)), None);
+        assert!(atomically(|tx| map.is_empty(tx)));
+    }
+
+    /// Snapshots share their structure instead of copying the map,
+    /// unlike a `TVar<std::collections::HashMap>`.
+    #[test]
+    fn snapshot_shares_structure() {
+        let map = PersistentTMap::new();
+        atomically(|tx| {
+            for i in 0..1000 {
+                map.insert(tx, i, i)?;
+            }
+            Ok(())
+        });
+
+        let a = atomically(|tx| map.snapshot(tx));
+        let b = atomically(|tx| map.snapshot(tx));
+        assert!(a.ptr_eq(&b));
+
+        // A write does not change older snapshots.
+        atomically(|tx| map.insert(tx, 0, 42));
+        assert_eq!(a.get(&0), Some(&0));
+        assert_eq!(atomically(|tx| map.get(tx, &0)), Some(42));
+    }
+
+    thread_local!(static CLONES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) });
+
+    /// A value, that counts its clones.
+    #[derive(Debug, PartialEq)]
+    struct Counted(usize);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.with(|c| c.set(c.get() + 1));
+            Counted(self.0)
+        }
+    }
+
+    /// Count the clones of values, while `f` runs.
+    fn clones<F: FnOnce()>(f: F) -> usize {
+        let before = CLONES.with(|c| c.get());
+        f();
+        CLONES.with(|c| c.get()) - before
+    }
+
+    /// A write to a `TVar<std::collections::HashMap>` copies every entry,
+    /// while the persistent map only copies the path to the changed one.
+    #[test]
+    fn write_clones_less_than_std_map() {
+        use std::collections::HashMap as StdHashMap;
+
+        const N: usize = 1000;
+
+        let std_map = TVar::new(StdHashMap::new());
+        let map = PersistentTMap::new();
+        atomically(|tx| {
+            let mut m = std_map.read(tx)?;
+            for i in 0..N {
+                m.insert(i, Counted(i));
+                map.insert(tx, i, Counted(i))?;
+            }
+            std_map.write(tx, m)
+        });
+
+        let std_clones = clones(|| atomically(|tx| {
+            let mut m = std_map.read(tx)?;
+            m.insert(0, Counted(42));
+            std_map.write(tx, m)
+        }));
+        let persistent_clones = clones(|| {
+            atomically(|tx| map.insert(tx, 0, Counted(42)));
+        });
+
+        assert!(std_clones >= N, "{}", std_clones);
+        assert!(persistent_clones < N / 10, "{}", persistent_clones);
+
+        // Both maps hold the same entries afterwards.
+        let snapshot = atomically(|tx| map.snapshot(tx));
+        let std_snapshot = std_map.read_atomic();
+        assert_eq!(snapshot.len(), std_snapshot.len());
+        for (k, v) in snapshot.iter() {
+            assert_eq!(std_snapshot.get(k), Some(v));
+        }
+    }
+
+    /// Concurrent writers don't lose any entries.
+    #[test]
+    fn concurrent_inserts() {
+        use std::thread;
+
+        let map = PersistentTMap::new();
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    atomically(|tx| map.insert(tx, t * 100 + i, t));
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let snapshot = atomically(|tx| map.snapshot(tx));
+        assert_eq!(snapshot.len(), 400);
+        for (k, v) in snapshot.iter() {
+            assert_eq!(k / 100, *v);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{unwrap_or_retry, StmResult, TVar, Transaction};
+
+/// A transactional pool of resources.
+///
+/// `acquire` takes a resource out of the pool and blocks, if the pool is empty.
+/// `release` puts it back.
+/// Both are transactional, so that they compose with other operations.
+/// For example two resources can be acquired atomically, or none of them.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let pool = TPool::new(vec![1, 2]);
+///
+/// let (a, b) = atomically(|tx| {
+///     let a = pool.acquire(tx)?;
+///     let b = pool.acquire(tx)?;
+///     Ok((a, b))
+/// });
+///
+/// atomically(|tx| {
+///     pool.release(tx, a)?;
+///     pool.release(tx, b)
+/// });
+/// ```
+#[derive(Clone)]
+pub struct TPool<R> {
+    resources: TVar<Vec<R>>,
+}
+
+impl<R> TPool<R>
+    where R: Any + Sync + Send + Clone
+{
+    /// Create a new pool, that manages the given resources.
+    pub fn new(resources: Vec<R>) -> TPool<R> {
+        TPool {
+            resources: TVar::new(resources),
+        }
+    }
+
+    /// Take a resource out of the pool.
+    ///
+    /// Retry if the pool is empty.
+    pub fn acquire(&self, tx: &mut Transaction) -> StmResult<R> {
+        let mut resources = self.resources.read(tx)?;
+        let r = unwrap_or_retry(resources.pop())?;
+        self.resources.write(tx, resources)?;
+        Ok(r)
+    }
+
+    /// Put a resource back into the pool.
+    pub fn release(&self, tx: &mut Transaction, r: R) -> StmResult<()> {
+        self.resources.modify(tx, |mut resources| {
+            resources.push(r);
+            resources
+        })
+    }
+
+    /// Number of resources, that are currently available.
+    pub fn available(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.resources.read(tx)?.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn acquire_release() {
+        let pool = TPool::new(vec![42]);
+
+        let r = atomically(|tx| pool.acquire(tx));
+        assert_eq!(r, 42);
+        assert_eq!(atomically(|tx| pool.available(tx)), 0);
+
+        atomically(|tx| pool.release(tx, r));
+        assert_eq!(atomically(|tx| pool.available(tx)), 1);
+    }
+
+    /// More consumers than resources. 
+    /// The number of resources in use never exceeds the size of the pool.
+    #[test]
+    fn limited_resources() {
+        use std::thread;
+
+        let pool = TPool::new(vec![1, 2]);
+        let in_use = TVar::new(0);
+        let max_in_use = TVar::new(0);
+
+        let threads: Vec<_> = (0..6).map(|_| {
+            let pool = pool.clone();
+            let in_use = in_use.clone();
+            let max_in_use = max_in_use.clone();
+
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    let r = atomically(|tx| {
+                        let r = pool.acquire(tx)?;
+                        let n = in_use.read(tx)? + 1;
+                        in_use.write(tx, n)?;
+                        max_in_use.modify(tx, |m| if n > m { n } else { m })?;
+                        Ok(r)
+                    });
+
+                    thread::yield_now();
+
+                    atomically(|tx| {
+                        in_use.modify(tx, |n| n - 1)?;
+                        pool.release(tx, r)
+                    });
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(max_in_use.read_atomic(), 2);
+        assert_eq!(in_use.read_atomic(), 0);
+        assert_eq!(atomically(|tx| pool.available(tx)), 2);
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{retry, StmResult, TVar, Transaction};
+
+/// A transactional one-shot value.
+///
+/// The promise is fulfilled once and can then be read by any number of waiters.
+/// `get` retries until the value is there, so all waiters wake up together, when
+/// the fulfilling transaction commits.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// # use std::thread;
+/// let promise = TPromise::new();
+/// let promisec = promise.clone();
+///
+/// let t = thread::spawn(move || atomically(|tx| promisec.get(tx)));
+///
+/// atomically(|tx| promise.fulfill(tx, 42)).unwrap();
+/// assert_eq!(t.join().unwrap(), 42);
+/// ```
+#[derive(Clone)]
+pub struct TPromise<T> {
+    value: TVar<Option<T>>,
+}
+
+impl<T> TPromise<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create a new promise, that is not fulfilled.
+    pub fn new() -> TPromise<T> {
+        TPromise {
+            value: TVar::new(None),
+        }
+    }
+
+    /// Fulfill the promise with `value`.
+    ///
+    /// If the promise has already been fulfilled, it keeps its value and
+    /// `value` is given back as error.
+    pub fn fulfill(&self, tx: &mut Transaction, value: T) -> StmResult<Result<(), T>> {
+        if self.value.read(tx)?.is_some() {
+            return Ok(Err(value));
+        }
+        self.value.write(tx, Some(value))?;
+        Ok(Ok(()))
+    }
+
+    /// Get the value of the promise.
+    ///
+    /// Retries until the promise is fulfilled.
+    pub fn get(&self, tx: &mut Transaction) -> StmResult<T> {
+        match self.value.read(tx)? {
+            Some(value) => Ok(value),
+            None => retry(),
+        }
+    }
+
+    /// Get the value of the promise, if it is fulfilled.
+    pub fn try_get(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        self.value.read(tx)
+    }
+
+    /// Check if the promise has been fulfilled.
+    pub fn is_fulfilled(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.value.read(tx)?.is_some())
+    }
+}
+
+impl<T> Default for TPromise<T>
+    where T: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TPromise::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    /// All readers blocked in `get` see the single fulfilled value.
+    #[test]
+    fn fulfill_wakes_all_readers() {
+        use std::thread;
+        use std::time::Duration;
+
+        let promise = TPromise::new();
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let promise = promise.clone();
+            thread::spawn(move || atomically(|tx| promise.get(tx)))
+        }).collect();
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!atomically(|tx| promise.is_fulfilled(tx)));
+        atomically(|tx| promise.fulfill(tx, 7)).unwrap();
+
+        for r in readers {
+            assert_eq!(r.join().unwrap(), 7);
+        }
+    }
+
+    #[test]
+    fn double_fulfill() {
+        let promise = TPromise::new();
+
+        assert_eq!(atomically(|tx| promise.fulfill(tx, 1)), Ok(()));
+        assert_eq!(atomically(|tx| promise.fulfill(tx, 2)), Err(2));
+        assert_eq!(atomically(|tx| promise.try_get(tx)), Some(1));
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{retry, StmResult, TVar, Transaction};
+
+/// A transactional unbounded FIFO queue.
+///
+/// Like `TQueue` in Haskell, it is made of two stacks. `push` puts elements on
+/// the write stack and `pop` takes them from the read stack. When the read stack
+/// is empty, the write stack is reversed and becomes the new read stack.
+/// Producers and consumers therefore only conflict, when the read stack runs empty.
+///
+/// `pop` blocks, when the queue is empty. Use `optionally` or `Transaction::or`
+/// for a non-blocking pop.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let queue = TQueue::new();
+///
+/// atomically(|tx| {
+///     queue.push(tx, 1)?;
+///     queue.push(tx, 2)
+/// });
+///
+/// assert_eq!(atomically(|tx| queue.pop(tx)), 1);
+/// assert_eq!(atomically(|tx| optionally(tx, |tx| queue.pop(tx))), Some(2));
+/// assert_eq!(atomically(|tx| optionally(tx, |tx| queue.pop(tx))), None);
+/// ```
+#[derive(Clone)]
+pub struct TQueue<T> {
+    /// Oldest element last.
+    read: TVar<Vec<T>>,
+
+    /// Newest element last.
+    write: TVar<Vec<T>>,
+}
+
+impl<T> TQueue<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create an empty queue.
+    pub fn new() -> TQueue<T> {
+        TQueue {
+            read: TVar::new(Vec::new()),
+            write: TVar::new(Vec::new()),
+        }
+    }
+
+    /// Append an element.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        self.write.modify(tx, |mut w| { w.push(value); w })
+    }
+
+    /// Take the oldest element.
+    ///
+    /// Retries while the queue is empty.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<T> {
+        let mut read = self.read.read(tx)?;
+        if let Some(value) = read.pop() {
+            self.read.write(tx, read)?;
+            return Ok(value);
+        }
+
+        // Turn the write stack into the read stack.
+        let mut read = self.write.read(tx)?;
+        if read.is_empty() {
+            return retry();
+        }
+        read.reverse();
+        let value = read.pop().expect("read stack is not empty");
+        self.write.write(tx, Vec::new())?;
+        self.read.write(tx, read)?;
+        Ok(value)
+    }
+
+    /// Check if the queue is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.read.read(tx)?.is_empty() && self.write.read(tx)?.is_empty())
+    }
+}
+
+impl<T> Default for TQueue<T>
+    where T: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    /// Elements pushed in between pops keep their order.
+    #[test]
+    fn fifo_order() {
+        let queue = TQueue::new();
+
+        atomically(|tx| { queue.push(tx, 1)?; queue.push(tx, 2) });
+        assert_eq!(atomically(|tx| queue.pop(tx)), 1);
+
+        atomically(|tx| queue.push(tx, 3));
+        assert_eq!(atomically(|tx| queue.pop(tx)), 2);
+        assert_eq!(atomically(|tx| queue.pop(tx)), 3);
+        assert!(atomically(|tx| queue.is_empty(tx)));
+    }
+
+    /// `pop` of an empty queue in the first branch of `or` falls through to the second one.
+    #[test]
+    fn pop_or() {
+        let a = TQueue::new();
+        let b = TQueue::new();
+        atomically(|tx| b.push(tx, 'b'));
+
+        let x = atomically(|tx| tx.or(|tx| a.pop(tx), |tx| b.pop(tx)));
+        assert_eq!(x, 'b');
+    }
+
+    /// A consumer blocked on the empty queue is woken by the producers
+    /// and gets the elements of every producer in order.
+    #[test]
+    fn producers_consumer() {
+        use std::thread;
+        use std::time::Duration;
+
+        const PRODUCERS: usize = 4;
+        const N: usize = 100;
+
+        let queue = TQueue::new();
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                (0..PRODUCERS * N).map(|_| atomically(|tx| queue.pop(tx)))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!consumer.is_finished());
+
+        let producers: Vec<_> = (0..PRODUCERS).map(|p| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..N {
+                    atomically(|tx| queue.push(tx, (p, i)));
+                }
+            })
+        }).collect();
+
+        for p in producers {
+            p.join().unwrap();
+        }
+        let popped = consumer.join().unwrap();
+
+        for p in 0..PRODUCERS {
+            let own: Vec<_> = popped.iter().filter(|x| x.0 == p).map(|x| x.1).collect();
+            assert_eq!(own, (0..N).collect::<Vec<_>>());
+        }
+        assert!(atomically(|tx| queue.is_empty(tx)));
+    }
+}
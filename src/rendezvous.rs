@@ -0,0 +1,151 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{assert_no_transaction, atomically, guard, retry, StmResult, TVar, Transaction};
+
+/// A transactional channel without buffer.
+///
+/// A value is handed over directly from a sender to a receiver.
+/// `send` only returns after a receiver has taken the value, so that the sender
+/// knows that it arrived.
+///
+/// Offering a value and waiting for the receiver can not happen in the same
+/// transaction, because the offer would be discarded together with the `retry`.
+/// Therefore `offer` returns a ticket, and `wait_received` blocks in a later
+/// transaction until the value with that ticket has been received.
+/// `send` does both in two transactions.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// # use std::thread;
+/// let chan = TRendezvous::new();
+/// let chanc = chan.clone();
+///
+/// let t = thread::spawn(move || atomically(|tx| chanc.recv(tx)));
+/// chan.send(42);
+/// assert_eq!(t.join().unwrap(), 42);
+/// ```
+#[derive(Clone)]
+pub struct TRendezvous<T> {
+    /// The value, that is currently offered.
+    slot: TVar<Option<T>>,
+
+    /// Number of values, that have been received.
+    received: TVar<u64>,
+}
+
+impl<T> TRendezvous<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create a new rendezvous channel.
+    pub fn new() -> TRendezvous<T> {
+        TRendezvous {
+            slot: TVar::new(None),
+            received: TVar::new(0),
+        }
+    }
+
+    /// Offer a value to a receiver and return its ticket.
+    ///
+    /// Retries while another value is offered.
+    pub fn offer(&self, tx: &mut Transaction, value: T) -> StmResult<u64> {
+        guard(self.slot.read(tx)?.is_none())?;
+        self.slot.write(tx, Some(value))?;
+
+        // The slot is empty, so every earlier value has been received.
+        self.received.read(tx)
+    }
+
+    /// Retry until the value with `ticket` has been received.
+    pub fn wait_received(&self, tx: &mut Transaction, ticket: u64) -> StmResult<()> {
+        guard(self.received.read(tx)? > ticket)
+    }
+
+    /// Send a value and block until a receiver has taken it.
+    pub fn send(&self, value: T) {
+        assert_no_transaction();
+        let ticket = atomically(|tx| self.offer(tx, value.clone()));
+        atomically(|tx| self.wait_received(tx, ticket));
+    }
+
+    /// Take the offered value.
+    ///
+    /// Retries until a sender offers a value.
+    pub fn recv(&self, tx: &mut Transaction) -> StmResult<T> {
+        match self.slot.replace(tx, None)? {
+            Some(value) => {
+                self.received.modify(tx, |n| n + 1)?;
+                Ok(value)
+            }
+            None => retry(),
+        }
+    }
+}
+
+impl<T> Default for TRendezvous<T>
+    where T: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TRendezvous::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `send` returns only after the receiving transaction has committed.
+    #[test]
+    fn send_waits_for_recv() {
+        use std::thread;
+        use std::time::Duration;
+
+        let chan = TRendezvous::new();
+        let got = TVar::new(None);
+
+        let receiver = {
+            let chan = chan.clone();
+            let got = got.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                atomically(|tx| {
+                    let value = chan.recv(tx)?;
+                    got.write(tx, Some(value))
+                });
+            })
+        };
+
+        assert_eq!(got.read_atomic(), None);
+        chan.send(7);
+        assert_eq!(got.read_atomic(), Some(7));
+
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn several_handoffs() {
+        use std::thread;
+
+        let chan = TRendezvous::new();
+        let chanc = chan.clone();
+
+        let receiver = thread::spawn(move || {
+            (0..10).map(|_| atomically(|tx| chanc.recv(tx))).collect::<Vec<_>>()
+        });
+
+        for i in 0..10 {
+            chan.send(i);
+        }
+
+        assert_eq!(receiver.join().unwrap(), (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::collections::VecDeque;
+
+use stm_core::{guard, unwrap_or_retry, StmResult, TVar, Transaction};
+
+/// A transactional bounded queue for multiple producers and consumers.
+///
+/// `push` blocks, when the buffer is full, and `pop` blocks, when it is empty.
+/// This gives producers a natural backpressure.
+/// `try_push` and `try_pop` return immediately instead.
+///
+/// All elements are stored in a single `TVar`, so every operation conflicts with
+/// every other one. Because the transactions are short, this is cheap for
+/// moderate contention.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let buffer = TRingBuffer::new(2);
+///
+/// atomically(|tx| {
+///     buffer.push(tx, 1)?;
+///     buffer.push(tx, 2)
+/// });
+///
+/// // The buffer is full.
+/// assert_eq!(atomically(|tx| buffer.try_push(tx, 3)), Err(3));
+///
+/// assert_eq!(atomically(|tx| buffer.pop(tx)), 1);
+/// ```
+#[derive(Clone)]
+pub struct TRingBuffer<T> {
+    capacity: usize,
+    elements: TVar<VecDeque<T>>,
+}
+
+impl<T> TRingBuffer<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create a new buffer, that holds up to `capacity` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> TRingBuffer<T> {
+        assert!(capacity > 0, "TRingBuffer needs a capacity of at least 1");
+        TRingBuffer {
+            capacity,
+            elements: TVar::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Append an element.
+    ///
+    /// If the buffer is full, the element is given back as error.
+    pub fn try_push(&self, tx: &mut Transaction, value: T) -> StmResult<Result<(), T>> {
+        let mut elements = self.elements.read(tx)?;
+        if elements.len() >= self.capacity {
+            return Ok(Err(value));
+        }
+        elements.push_back(value);
+        self.elements.write(tx, elements)?;
+        Ok(Ok(()))
+    }
+
+    /// Append an element.
+    ///
+    /// Retries while the buffer is full.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        let mut elements = self.elements.read(tx)?;
+        guard(elements.len() < self.capacity)?;
+        elements.push_back(value);
+        self.elements.write(tx, elements)
+    }
+
+    /// Take the oldest element, if there is one.
+    pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let mut elements = self.elements.read(tx)?;
+        let value = elements.pop_front();
+        if value.is_some() {
+            self.elements.write(tx, elements)?;
+        }
+        Ok(value)
+    }
+
+    /// Take the oldest element.
+    ///
+    /// Retries while the buffer is empty.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<T> {
+        let mut elements = self.elements.read(tx)?;
+        let value = unwrap_or_retry(elements.pop_front())?;
+        self.elements.write(tx, elements)?;
+        Ok(value)
+    }
+
+    /// Number of elements in the buffer.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.elements.read(tx)?.len())
+    }
+
+    /// Check if the buffer is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.elements.read(tx)?.is_empty())
+    }
+
+    /// Maximum number of elements in the buffer.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn boundaries() {
+        let buffer = TRingBuffer::new(2);
+
+        assert_eq!(atomically(|tx| buffer.try_pop(tx)), None);
+        assert_eq!(atomically(|tx| buffer.try_push(tx, 1)), Ok(()));
+        assert_eq!(atomically(|tx| buffer.try_push(tx, 2)), Ok(()));
+        assert_eq!(atomically(|tx| buffer.try_push(tx, 3)), Err(3));
+        assert_eq!(atomically(|tx| buffer.len(tx)), 2);
+
+        assert_eq!(atomically(|tx| buffer.try_pop(tx)), Some(1));
+        assert_eq!(atomically(|tx| buffer.pop(tx)), 2);
+        assert!(atomically(|tx| buffer.is_empty(tx)));
+    }
+
+    /// `push` blocks on a full buffer until a consumer makes room.
+    #[test]
+    fn push_blocks_when_full() {
+        use std::thread;
+        use std::time::Duration;
+
+        let buffer = TRingBuffer::new(1);
+        atomically(|tx| buffer.push(tx, 1));
+
+        let producer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || atomically(|tx| buffer.push(tx, 2)))
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(atomically(|tx| buffer.len(tx)), 1);
+
+        assert_eq!(atomically(|tx| buffer.pop(tx)), 1);
+        producer.join().unwrap();
+        assert_eq!(atomically(|tx| buffer.pop(tx)), 2);
+    }
+
+    /// Every element is received exactly once.
+    #[test]
+    fn producers_consumers() {
+        use std::thread;
+
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+        const PER_PRODUCER: usize = 500;
+
+        let buffer = TRingBuffer::new(8);
+
+        let producers: Vec<_> = (0..PRODUCERS).map(|p| {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    atomically(|tx| buffer.push(tx, p * PER_PRODUCER + i));
+                }
+            })
+        }).collect();
+
+        let consumers: Vec<_> = (0..CONSUMERS).map(|_| {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                (0..PRODUCERS * PER_PRODUCER / CONSUMERS)
+                    .map(|_| atomically(|tx| buffer.pop(tx)))
+                    .collect::<Vec<_>>()
+            })
+        }).collect();
+
+        for p in producers {
+            p.join().unwrap();
+        }
+
+        let mut received: Vec<_> = consumers.into_iter()
+            .flat_map(|c| c.join().unwrap())
+            .collect();
+        received.sort();
+
+        assert_eq!(received, (0..PRODUCERS * PER_PRODUCER).collect::<Vec<_>>());
+        assert!(atomically(|tx| buffer.is_empty(tx)));
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional state machine with guarded transitions.
+///
+/// A transition function gets the current state and returns the next one,
+/// or `None`, if the transition is not allowed from that state.
+/// The state is read in both cases, so a rejected transition depends on the
+/// state as well, and the transaction reruns, if the state changes before it commits.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// #[derive(Clone, Debug, PartialEq)]
+/// enum Door { Open, Closed }
+///
+/// let door = TStateMachine::new(Door::Open);
+///
+/// let close = |s: &Door| if *s == Door::Open { Some(Door::Closed) } else { None };
+///
+/// assert!(atomically(|tx| door.transition(tx, close)));
+/// assert!(!atomically(|tx| door.transition(tx, close)));
+/// assert_eq!(atomically(|tx| door.state(tx)), Door::Closed);
+/// ```
+#[derive(Clone)]
+pub struct TStateMachine<S> {
+    state: TVar<S>,
+}
+
+impl<S> TStateMachine<S>
+    where S: Any + Sync + Send + Clone
+{
+    /// Create a new state machine in the state `initial`.
+    pub fn new(initial: S) -> TStateMachine<S> {
+        TStateMachine {
+            state: TVar::new(initial),
+        }
+    }
+
+    /// Get the current state.
+    pub fn state(&self, tx: &mut Transaction) -> StmResult<S> {
+        self.state.read(tx)
+    }
+
+    /// Apply the transition `f` to the current state.
+    ///
+    /// Return if the transition has been taken.
+    pub fn transition<F>(&self, tx: &mut Transaction, f: F) -> StmResult<bool>
+        where F: Fn(&S) -> Option<S>
+    {
+        let state = self.state.read(tx)?;
+        match f(&state) {
+            Some(next) => {
+                self.state.write(tx, next)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Light {
+        Red,
+        RedYellow,
+        Green,
+        Yellow,
+    }
+
+    /// The only valid successor of every state.
+    fn next(light: Light) -> Light {
+        match light {
+            Light::Red       => Light::RedYellow,
+            Light::RedYellow => Light::Green,
+            Light::Green     => Light::Yellow,
+            Light::Yellow    => Light::Red,
+        }
+    }
+
+    /// Several threads try to move the light from a fixed state to its successor.
+    /// Only transitions from the current state are taken.
+    #[test]
+    fn traffic_light() {
+        use std::thread;
+
+        let light = TStateMachine::new(Light::Red);
+        let history = TVar::new(vec![Light::Red]);
+
+        let threads: Vec<_> = [Light::Red, Light::RedYellow, Light::Green, Light::Yellow]
+            .iter()
+            .map(|&from| {
+                let light = light.clone();
+                let history = history.clone();
+                thread::spawn(move || {
+                    let mut taken = 0;
+                    for _ in 0..1000 {
+                        let switched = atomically(|tx| {
+                            let switched = light.transition(tx, |&s| {
+                                if s == from { Some(next(s)) } else { None }
+                            })?;
+                            if switched {
+                                let state = light.state(tx)?;
+                                history.modify(tx, |mut h| { h.push(state); h })?;
+                            }
+                            Ok(switched)
+                        });
+                        taken += switched as usize;
+                    }
+                    taken
+                })
+            })
+            .collect();
+
+        let taken: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+        let history = history.read_atomic();
+        assert_eq!(history.len(), taken + 1);
+        for pair in history.windows(2) {
+            assert_eq!(pair[1], next(pair[0]));
+        }
+        assert_eq!(atomically(|tx| light.state(tx)), *history.last().unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional growable array with a `TVar` for every element.
+///
+/// Accessing an element only reads the list of elements, so transactions,
+/// that write different elements, don't conflict with each other.
+/// `push` writes the list and conflicts with every other access.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let vec = TVec::new();
+///
+/// atomically(|tx| {
+///     vec.push(tx, 'a')?;
+///     vec.push(tx, 'b')
+/// });
+/// atomically(|tx| vec.set(tx, 1, 'c'));
+///
+/// assert_eq!(atomically(|tx| vec.get(tx, 1)), Some('c'));
+/// assert_eq!(atomically(|tx| vec.get(tx, 2)), None);
+/// ```
+#[derive(Clone)]
+pub struct TVec<T> {
+    elements: TVar<Vec<TVar<T>>>,
+}
+
+impl<T> TVec<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create an empty vector.
+    pub fn new() -> TVec<T> {
+        TVec {
+            elements: TVar::new(Vec::new()),
+        }
+    }
+
+    /// Get the element at `index`, if it exists.
+    pub fn get(&self, tx: &mut Transaction, index: usize) -> StmResult<Option<T>> {
+        match self.elements.read(tx)?.get(index) {
+            Some(element) => element.read(tx).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn set(&self, tx: &mut Transaction, index: usize, value: T) -> StmResult<()> {
+        let elements = self.elements.read(tx)?;
+        let len = elements.len();
+        let element = elements.get(index).unwrap_or_else(||
+            panic!("TVec index {} out of bounds for length {}", index, len));
+        element.write(tx, value)
+    }
+
+    /// Append an element.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        self.elements.modify(tx, |mut elements| {
+            elements.push(TVar::new(value));
+            elements
+        })
+    }
+
+    /// Number of elements.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.elements.read(tx)?.len())
+    }
+
+    /// Check if the vector is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.elements.read(tx)?.is_empty())
+    }
+}
+
+impl<T> Default for TVec<T>
+    where T: Any + Sync + Send + Clone
+{
+    fn default() -> Self {
+        TVec::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn get_set_push() {
+        let vec = TVec::new();
+        assert!(atomically(|tx| vec.is_empty(tx)));
+
+        atomically(|tx| {
+            for i in 0..3 {
+                vec.push(tx, i)?;
+            }
+            vec.set(tx, 0, 10)
+        });
+
+        assert_eq!(atomically(|tx| vec.len(tx)), 3);
+        assert_eq!(atomically(|tx| vec.get(tx, 0)), Some(10));
+        assert_eq!(atomically(|tx| vec.get(tx, 2)), Some(2));
+        assert_eq!(atomically(|tx| vec.get(tx, 3)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn set_out_of_bounds() {
+        let vec = TVec::new();
+        atomically(|tx| vec.set(tx, 0, 1));
+    }
+
+    /// Threads, that write disjoint elements, never conflict.
+    #[test]
+    fn disjoint_writes() {
+        use std::sync::Barrier;
+        use std::sync::Arc;
+        use std::thread;
+        use stm_core::atomically_counted;
+
+        const THREADS: usize = 4;
+        const N: usize = 1000;
+
+        let vec = TVec::new();
+        atomically(|tx| {
+            for _ in 0..THREADS {
+                vec.push(tx, 0)?;
+            }
+            Ok(())
+        });
+
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let threads: Vec<_> = (0..THREADS).map(|i| {
+            let vec = vec.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                let mut reruns = 0;
+                for _ in 0..N {
+                    let ((), r) = atomically_counted(|tx| {
+                        let x = vec.get(tx, i)?.unwrap();
+                        vec.set(tx, i, x + 1)
+                    });
+                    reruns += r;
+                }
+                reruns
+            })
+        }).collect();
+
+        let reruns: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(reruns, 0);
+        for i in 0..THREADS {
+            assert_eq!(atomically(|tx| vec.get(tx, i)), Some(N));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2015-2018 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+
+use stm_core::{StmResult, TVar, Transaction};
+
+/// A transactional value with a version for optimistic concurrency.
+///
+/// This is the "if-match" pattern, where a client reads a value together with
+/// its version, works with it outside of any transaction, for example while a
+/// user edits it, and later writes it back only if nobody has changed it in between.
+/// Every write increments the version.
+///
+/// # Example
+///
+/// ```
+/// # use stm::*;
+/// let doc = TVersioned::new(String::from("draft"));
+///
+/// let (text, version) = atomically(|tx| doc.read_versioned(tx));
+///
+/// // Another client saves first.
+/// atomically(|tx| doc.write(tx, String::from("other")));
+///
+/// // The stale write is rejected.
+/// let saved = atomically(|tx| doc.write_checked(tx, version, text.clone() + "!"));
+/// assert!(!saved);
+/// ```
+#[derive(Clone)]
+pub struct TVersioned<T> {
+    /// The value together with its version.
+    value: TVar<(T, u64)>,
+}
+
+impl<T> TVersioned<T>
+    where T: Any + Sync + Send + Clone
+{
+    /// Create a new value with version 0.
+    pub fn new(value: T) -> TVersioned<T> {
+        TVersioned {
+            value: TVar::new((value, 0)),
+        }
+    }
+
+    /// Read the value together with its version.
+    pub fn read_versioned(&self, tx: &mut Transaction) -> StmResult<(T, u64)> {
+        self.value.read(tx)
+    }
+
+    /// Get the current version.
+    pub fn version(&self, tx: &mut Transaction) -> StmResult<u64> {
+        Ok(self.value.read(tx)?.1)
+    }
+
+    /// Write the value, if the version is still `expected_version`.
+    ///
+    /// Return if the value has been written.
+    pub fn write_checked(&self, tx: &mut Transaction, expected_version: u64, value: T)
+        -> StmResult<bool>
+    {
+        let version = self.version(tx)?;
+        if version != expected_version {
+            return Ok(false);
+        }
+        self.value.write(tx, (value, version + 1))?;
+        Ok(true)
+    }
+
+    /// Write the value regardless of its version.
+    pub fn write(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        let version = self.version(tx)?;
+        self.value.write(tx, (value, version + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stm_core::atomically;
+
+    #[test]
+    fn matching_version() {
+        let value = TVersioned::new(1);
+
+        let (x, version) = atomically(|tx| value.read_versioned(tx));
+        assert_eq!((x, version), (1, 0));
+
+        assert!(atomically(|tx| value.write_checked(tx, version, x + 1)));
+        assert_eq!(atomically(|tx| value.read_versioned(tx)), (2, 1));
+    }
+
+    #[test]
+    fn stale_version() {
+        let value = TVersioned::new(1);
+
+        let (_, version) = atomically(|tx| value.read_versioned(tx));
+        atomically(|tx| value.write(tx, 5));
+
+        assert!(!atomically(|tx| value.write_checked(tx, version, 10)));
+        assert_eq!(atomically(|tx| value.read_versioned(tx)), (5, 1));
+    }
+}
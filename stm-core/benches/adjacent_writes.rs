@@ -0,0 +1,42 @@
+//! Write to adjacent vars from different threads.
+//!
+//! The vars are allocated one after another, so that their control blocks
+//! would share cache lines without padding.
+//!
+//! Run with `cargo bench --bench adjacent_writes`.
+
+extern crate stm_core;
+
+use std::thread;
+use std::time::Instant;
+
+use stm_core::{atomically, TVar};
+
+const THREADS: usize = 4;
+const ITERATIONS: usize = 100_000;
+
+fn main() {
+    let vars: Vec<TVar<usize>> = (0..THREADS).map(|_| TVar::new(0)).collect();
+
+    let start = Instant::now();
+    let threads: Vec<_> = vars.iter()
+        .cloned()
+        .map(|var| thread::spawn(move || {
+            for _ in 0..ITERATIONS {
+                atomically(|tx| var.modify(tx, |x| x + 1));
+            }
+        }))
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    for var in &vars {
+        assert_eq!(var.read_atomic(), ITERATIONS);
+    }
+
+    println!("adjacent_writes: {} threads x {} transactions in {:?}",
+        THREADS, ITERATIONS, elapsed);
+}
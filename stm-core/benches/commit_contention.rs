@@ -0,0 +1,55 @@
+//! Let several threads commit to overlapping sets of vars and compare
+//! the commit modes.
+//!
+//! Run with `cargo bench --bench commit_contention`.
+
+extern crate stm_core;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stm_core::{atomically, CommitMode, TVar};
+
+const THREADS: usize = 8;
+const VARS: usize = 16;
+const VARS_PER_TX: usize = 4;
+const ITERATIONS: usize = 20_000;
+
+fn run(mode: CommitMode) -> Duration {
+    let vars: Vec<_> = (0..VARS).map(|_| TVar::new(0)).collect();
+
+    let start = Instant::now();
+    let threads: Vec<_> = (0..THREADS).map(|t| {
+        let vars = vars.clone();
+        thread::spawn(move || {
+            for i in 0..ITERATIONS {
+                // Every transaction writes a window of vars,
+                // that overlaps with the windows of other threads.
+                let first = (t + i) % VARS;
+                atomically(|tx| {
+                    tx.set_commit_mode(mode);
+                    for k in 0..VARS_PER_TX {
+                        vars[(first + k) % VARS].modify(tx, |x| x + 1)?;
+                    }
+                    Ok(())
+                });
+            }
+        })
+    }).collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let sum: usize = vars.iter().map(|v| v.read_atomic()).sum();
+    assert_eq!(sum, THREADS * ITERATIONS * VARS_PER_TX);
+    elapsed
+}
+
+fn main() {
+    for &mode in &[CommitMode::Blocking, CommitMode::TryLock] {
+        println!("commit_contention {:?}: {} transactions in {:?}",
+            mode, THREADS * ITERATIONS, run(mode));
+    }
+}
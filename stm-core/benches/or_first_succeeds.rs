@@ -0,0 +1,44 @@
+//! Enter `or` many times in a large transaction, where the first branch
+//! always succeeds.
+//!
+//! This is the common case of an `or` guarded read, where no backup
+//! of the log should be needed.
+//!
+//! Run with `cargo bench --bench or_first_succeeds`.
+
+extern crate stm_core;
+
+use std::time::Instant;
+
+use stm_core::{atomically, retry, TVar};
+
+const VARS: usize = 1_000;
+const ORS: usize = 1_000;
+const ITERATIONS: usize = 100;
+
+fn main() {
+    let vars: Vec<_> = (0..VARS).map(TVar::new).collect();
+    let flag = TVar::new(true);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let sum = atomically(|tx| {
+            let mut sum = 0;
+            for var in &vars {
+                sum += var.read(tx)?;
+            }
+            for _ in 0..ORS {
+                sum += tx.or(
+                    |tx| if flag.read(tx)? { Ok(1) } else { retry() },
+                    |_| Ok(0)
+                )?;
+            }
+            Ok(sum)
+        });
+        assert_eq!(sum, VARS * (VARS - 1) / 2 + ORS);
+    }
+    let elapsed = start.elapsed();
+
+    println!("or_first_succeeds: {} transactions with {} vars and {} ors in {:?}",
+        ITERATIONS, VARS, ORS, elapsed);
+}
@@ -0,0 +1,45 @@
+//! Let many threads validate a var, that is read by every transaction
+//! but only written rarely.
+//!
+//! Mostly read vars are validated on commit without taking their read lock.
+//!
+//! Run with `cargo bench --bench read_mostly`.
+
+extern crate stm_core;
+
+use std::thread;
+use std::time::Instant;
+
+use stm_core::{atomically, TVar};
+
+const THREADS: usize = 8;
+const ITERATIONS: usize = 100_000;
+const WRITE_EVERY: usize = 10_000;
+
+fn main() {
+    let config = TVar::new(1);
+
+    let start = Instant::now();
+    let threads: Vec<_> = (0..THREADS).map(|t| {
+        let config = config.clone();
+        thread::spawn(move || {
+            let own = TVar::new(0);
+            for i in 0..ITERATIONS {
+                if t == 0 && i % WRITE_EVERY == 0 {
+                    atomically(|tx| config.modify(tx, |x| x + 1));
+                }
+                atomically(|tx| {
+                    let step = config.read(tx)?;
+                    own.modify(tx, |x| x + step)
+                });
+            }
+        })
+    }).collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    println!("read_mostly: {} transactions in {:?}", THREADS * ITERATIONS, elapsed);
+}
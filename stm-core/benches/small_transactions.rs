@@ -0,0 +1,35 @@
+//! Run many tiny transactions, that only touch a single var.
+//!
+//! For such transactions the overhead of the commit dominates.
+//! Empty transactions measure the fixed cost of `atomically`.
+//!
+//! Run with `cargo bench --bench small_transactions`.
+
+extern crate stm_core;
+
+use std::time::Instant;
+
+use stm_core::{atomically, TVar};
+
+const ITERATIONS: usize = 1_000_000;
+
+fn main() {
+    let var = TVar::new(0);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        atomically(|tx| var.modify(tx, |x| x + 1));
+    }
+    let elapsed = start.elapsed();
+
+    assert_eq!(var.read_atomic(), ITERATIONS);
+    println!("small_transactions: {} transactions in {:?}", ITERATIONS, elapsed);
+
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        atomically(|_| Ok(i));
+    }
+    let elapsed = start.elapsed();
+
+    println!("small_transactions empty: {} transactions in {:?}", ITERATIONS, elapsed);
+}
@@ -0,0 +1,293 @@
+// Copyright 2015-2016 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::result::*;
+use super::transaction::{with_control_until, TxVersion, TransactionControl};
+use super::Transaction;
+
+/// The time to wait, before a transaction restarts after a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Restart immediately.
+    None,
+
+    /// Always wait for the same time.
+    Fixed(Duration),
+
+    /// Start with `initial` and double the time after every conflict up to `max`.
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Get the time to wait after the given number of conflicts.
+    fn delay(&self, conflicts: u32) -> Duration {
+        match *self {
+            Backoff::None                       => Duration::from_secs(0),
+            Backoff::Fixed(d)                   => d,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32 << cmp::min(conflicts.saturating_sub(1), 16);
+                cmp::min(initial.saturating_mul(factor), max)
+            }
+        }
+    }
+}
+
+/// A configurable way to run a transaction.
+///
+/// `builder` collects the options in one place, instead of providing
+/// an `atomically_*` function for every combination of them.
+/// Without any option, `run` behaves like `atomically`.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// # use std::time::Duration;
+/// let var = TVar::new(0);
+///
+/// let x = builder()
+///     .max_retries(10)
+///     .backoff(Backoff::Fixed(Duration::from_millis(1)))
+///     .run(|tx| {
+///         var.write(tx, 42)?;
+///         var.read(tx)
+///     });
+///
+/// assert_eq!(x, Some(42));
+/// ```
+pub struct AtomicallyBuilder {
+    max_retries: Option<usize>,
+    backoff: Backoff,
+    jitter: bool,
+    deadline: Option<Instant>,
+    hooks: Vec<Box<dyn FnOnce()>>,
+}
+
+impl AtomicallyBuilder {
+    /// Create a builder without any options.
+    pub fn new() -> AtomicallyBuilder {
+        AtomicallyBuilder {
+            max_retries: None,
+            backoff: Backoff::None,
+            jitter: false,
+            deadline: None,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Abort the transaction, when it has failed to commit `max` times
+    /// because of conflicts.
+    ///
+    /// Calls to `retry` are not counted, like in `atomically_bounded`.
+    pub fn max_retries(mut self, max: usize) -> AtomicallyBuilder {
+        self.max_retries = Some(max);
+        self
+    }
+
+    /// Wait according to `backoff` after a conflict.
+    pub fn backoff(mut self, backoff: Backoff) -> AtomicallyBuilder {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Wait for a random part of the backoff instead of the full time.
+    ///
+    /// This keeps transactions, that conflicted with each other,
+    /// from restarting at the same time again.
+    pub fn jitter(mut self, jitter: bool) -> AtomicallyBuilder {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Abort the transaction, when it has not finished until `deadline`.
+    ///
+    /// The deadline is checked after every failed attempt and a transaction,
+    /// that is blocked in `retry`, wakes up at the deadline to abort.
+    pub fn deadline(mut self, deadline: Instant) -> AtomicallyBuilder {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Run `hook` after the transaction has been committed.
+    ///
+    /// It does not run, when the transaction is aborted.
+    pub fn on_commit<F>(mut self, hook: F) -> AtomicallyBuilder
+        where F: FnOnce() + 'static
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Run the transaction with the configured options.
+    ///
+    /// Return `None`, if it was aborted.
+    pub fn run<T, F>(self, f: F) -> Option<T>
+        where F: Fn(&mut Transaction) -> StmResult<T>
+    {
+        let mut conflicts = 0;
+        let r = with_control_until(TxVersion::NonDeterministic, self.deadline, |e| {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return TransactionControl::Abort;
+                }
+            }
+
+            if e == StmError::Failure {
+                conflicts += 1;
+                if let Some(max) = self.max_retries {
+                    if conflicts >= max {
+                        return TransactionControl::Abort;
+                    }
+                }
+
+                let delay = self.backoff.delay(conflicts as u32);
+                let delay = if self.jitter { random_part(delay) } else { delay };
+                if delay > Duration::from_secs(0) {
+                    thread::sleep(delay);
+                }
+            }
+            TransactionControl::Retry
+        }, f);
+
+        if r.is_some() {
+            for hook in self.hooks {
+                hook();
+            }
+        }
+        r
+    }
+}
+
+impl Default for AtomicallyBuilder {
+    fn default() -> Self {
+        AtomicallyBuilder::new()
+    }
+}
+
+/// Get a random duration between zero and `d`.
+fn random_part(d: Duration) -> Duration {
+    // `RandomState` is seeded randomly, which is good enough for jitter.
+    let random = RandomState::new().build_hasher().finish();
+    let nanos = d.as_nanos() as u64;
+    if nanos == 0 {
+        return d;
+    }
+    Duration::from_nanos(random % nanos)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::super::{atomically, retry, TVar};
+
+    /// Let another thread change `var` and cause a conflict.
+    fn conflict(var: &TVar<i32>) {
+        let var = var.clone();
+        thread::spawn(move || atomically(|tx| var.modify(tx, |x| x + 1)))
+            .join().unwrap();
+    }
+
+    #[test]
+    fn max_retries_aborts() {
+        let var = TVar::new(0);
+        let attempts = Cell::new(0);
+
+        let x = AtomicallyBuilder::new().max_retries(3).run(|tx| {
+            attempts.set(attempts.get() + 1);
+            let x = var.read(tx)?;
+            conflict(&var);
+            var.write(tx, x)
+        });
+
+        assert_eq!(x, None);
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn deadline_aborts_retry() {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(20);
+        let x: Option<()> = AtomicallyBuilder::new().deadline(deadline).run(|_| retry());
+
+        assert_eq!(x, None);
+        assert!(Instant::now() >= deadline);
+        // The blocked transaction wakes up at the deadline, not at the periodic check
+        // after 100ms. Leave a margin for a loaded machine.
+        assert!(start.elapsed() < Duration::from_millis(80), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn backoff_waits() {
+        let var = TVar::new(0);
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+
+        let x = AtomicallyBuilder::new()
+            .backoff(Backoff::Fixed(Duration::from_millis(50)))
+            .run(|tx| {
+                attempts.set(attempts.get() + 1);
+                let x = var.read(tx)?;
+                if attempts.get() == 1 {
+                    conflict(&var);
+                }
+                Ok(x)
+            });
+
+        assert_eq!(x, Some(1));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(1));
+        assert_eq!(backoff.delay(2), Duration::from_millis(2));
+        assert_eq!(backoff.delay(3), Duration::from_millis(4));
+        assert_eq!(backoff.delay(4), Duration::from_millis(5));
+        assert_eq!(backoff.delay(100), Duration::from_millis(5));
+
+        assert!(random_part(Duration::from_millis(5)) < Duration::from_millis(5));
+    }
+
+    #[test]
+    fn on_commit_hook() {
+        let committed = Rc::new(Cell::new(false));
+        let c = committed.clone();
+
+        let x = AtomicallyBuilder::new()
+            .on_commit(move || c.set(true))
+            .run(|_| Ok(42));
+
+        assert_eq!(x, Some(42));
+        assert!(committed.get());
+
+        let committed = Rc::new(Cell::new(false));
+        let c = committed.clone();
+        let x: Option<()> = AtomicallyBuilder::new()
+            .deadline(Instant::now())
+            .on_commit(move || c.set(true))
+            .run(|_| retry());
+
+        assert_eq!(x, None);
+        assert!(!committed.get());
+    }
+}
@@ -85,21 +85,21 @@
 //! you should obey when dealing with software transactional memory.
 //!
 //! * Don't run code with side effects, especially no IO-code.
-//! Transactions repeat in failure cases. Using IO would repeat this IO-code.
-//! Return a closure if you have to.
+//!   Transactions repeat in failure cases. Using IO would repeat this IO-code.
+//!   Return a closure if you have to.
 //! * Don't handle `StmResult` yourself.
-//! Use `Transaction::or` to combine alternative paths and `optionally` to check if an inner
-//! function has failed. Always use `?` and 
-//! never ignore a `StmResult`.
+//!   Use `Transaction::or` to combine alternative paths and `optionally` to check if an inner
+//!   function has failed. Always use `?` and 
+//!   never ignore a `StmResult`.
 //! * Don't run `atomically` inside of another. `atomically` is designed to have side effects
-//! and will therefore break transaction safety. 
-//! Nested calls are detected at runtime and handled with panicking.
-//! When you use STM in the inner of a function, then
-//! express it in the public interface, by taking `&mut Transaction` as parameter and 
-//! returning `StmResult<T>`. Callers can safely compose it into
-//! larger blocks.
+//!   and will therefore break transaction safety. 
+//!   Nested calls are detected at runtime and handled with panicking.
+//!   When you use STM in the inner of a function, then
+//!   express it in the public interface, by taking `&mut Transaction` as parameter and 
+//!   returning `StmResult<T>`. Callers can safely compose it into
+//!   larger blocks.
 //! * Don't mix locks and transactions. Your code will easily deadlock or slow
-//! down unpredictably.
+//!   down unpredictably.
 //! * Don't use inner mutability to change the content of a `TVar`.
 //!
 //! Panicking in a transaction is transaction-safe. The transaction aborts and 
@@ -157,6 +157,11 @@
 //! Whether we call `freeze` before or after spawning the threads for the transactions is not
 //! important.
 //! But processing only starts when the set of transactions is frozen.
+//! A transaction panics, if its `DTM` has not been frozen within 10 seconds,
+//! instead of waiting forever.
+//! Dropping the `DTM` without `freeze` freezes it as well.
+//! `DTM::run` freezes the set as well, but coordinates on the calling thread and
+//! returns only after all transactions have committed.
 //!
 //! ## Limitations of the programming model
 //! 
@@ -181,20 +186,46 @@
 //! of the execution.
 //!
 extern crate parking_lot;
+extern crate smallvec;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
 
 mod transaction;
 mod tvar;
+mod tvar_arc;
+mod builder;
+mod profile;
+mod num;
 mod result;
 
 #[cfg(test)]
 mod test;
 
-pub use tvar::TVar;
-pub use transaction::Tx;
-use transaction::{with, TxVersion, Transaction, DTM, DTMHandle};
-pub use transaction::TransactionControl;
+pub use tvar::{TVar, VarId};
+pub use tvar_arc::TVarArc;
+pub use builder::{AtomicallyBuilder, Backoff};
+pub use profile::{set_profiler, clear_profiler, ProfileEvent, ProfileOutcome};
+pub use profile::{set_wait_observer, clear_wait_observer, WaitEvent};
+pub use num::TNum;
+#[cfg(feature = "stats")]
+pub use tvar::ContentionStats;
+pub use transaction::{Tx, TransactionLike};
+pub use transaction::Transaction;
+pub use transaction::log_var::LogEntryKind;
+use transaction::{with, with_control, with_control_until, TxVersion, DTM, DTMHandle};
+pub use transaction::{transactions_started, transactions_committed};
+pub use transaction::{thread_stats, reset_thread_stats, ThreadStats};
+pub use transaction::in_transaction;
+pub use transaction::{TransactionControl, CommitMode, CommitOutcome, ConflictInfo, CommitHook};
 pub use result::*;
 
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
 #[inline]
 /// Call `retry` to abort an operation and run the whole transaction again.
 ///
@@ -214,6 +245,29 @@ pub fn retry<T>() -> StmResult<T> {
     Err(StmError::Retry)
 }
 
+/// Assert, that the current thread is not running a transaction.
+///
+/// Transactions may rerun, so they must not have side effects like IO.
+/// Call this at the start of functions with side effects or blocking functions,
+/// to catch their use inside of `atomically` early.
+/// The check is only done in debug builds.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// fn log(msg: &str) {
+///     assert_no_transaction();
+///     println!("{}", msg);
+/// }
+///
+/// log("outside");
+/// ```
+#[inline]
+pub fn assert_no_transaction() {
+    debug_assert!(!in_transaction(), "STM: side effect inside of a transaction");
+}
+
 /// Run a function atomically by using Software Transactional Memory.
 /// It calls to `Transaction::with` internally, but is more explicit.
 pub fn atomically<T, F>(f: F) -> T
@@ -222,6 +276,566 @@ where F: Fn(&mut Transaction) -> StmResult<T>
     with(TxVersion::NonDeterministic, f)
 }
 
+/// Run a function atomically, but panic if it fails to commit too often.
+///
+/// This turns a silent livelock into a visible failure and is meant
+/// for development and testing.
+/// The transaction panics, when it has failed `max` times because of
+/// conflicts with other transactions. Calls to `retry` are not counted.
+///
+/// With the `stats` feature the panic message also names the vars,
+/// that were involved in the most conflicts.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let var = TVar::new(0);
+/// let x = atomically_bounded(10, |tx| {
+///     var.write(tx, 42)?;
+///     var.read(tx)
+/// });
+/// assert_eq!(x, 42);
+/// ```
+pub fn atomically_bounded<T, F>(max: usize, f: F) -> T
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    let mut failures = 0;
+    #[cfg(feature = "stats")]
+    let mut conflicts: Vec<(ConflictInfo, usize)> = Vec::new();
+    let r = with_control(TxVersion::NonDeterministic, |e| match e {
+        StmError::Failure => {
+            failures += 1;
+            #[cfg(feature = "stats")]
+            if let Some(info) = transaction::last_conflict() {
+                match conflicts.iter_mut().find(|(c, _)| c.var() == info.var()) {
+                    Some((_, n)) => *n += 1,
+                    None => conflicts.push((info, 1)),
+                }
+            }
+            if failures >= max {
+                TransactionControl::Abort
+            } else {
+                TransactionControl::Retry
+            }
+        }
+        StmError::Retry | StmError::Blocked => TransactionControl::Retry,
+        StmError::Abandoned => TransactionControl::Abort,
+    }, f);
+
+    match r {
+        Some(t) => t,
+        #[cfg(feature = "stats")]
+        None    => panic!("STM: transaction failed to commit after {} retries, most conflicts on: {}",
+                          failures, conflict_summary(conflicts)),
+        #[cfg(not(feature = "stats"))]
+        None    => panic!("STM: transaction failed to commit after {} retries", failures),
+    }
+}
+
+/// Describe the vars with the most conflicts for the panic of `atomically_bounded`.
+#[cfg(feature = "stats")]
+fn conflict_summary(mut conflicts: Vec<(ConflictInfo, usize)>) -> String {
+    /// Number of vars, that are named in the summary.
+    const SHOWN: usize = 3;
+
+    conflicts.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    let vars: Vec<String> = conflicts.iter()
+        .take(SHOWN)
+        .map(|(c, n)| match c.name() {
+            Some(name) => format!("{} ({})", name, n),
+            None => format!("{:?} ({})", c.var(), n),
+        })
+        .collect();
+    if vars.is_empty() {
+        "unknown".to_owned()
+    } else {
+        vars.join(", ")
+    }
+}
+
+/// Run a function atomically, but give up after `limit` attempts.
+///
+/// Every run of `f` counts as an attempt, no matter if the previous one has
+/// failed to commit because of a conflict or called `retry`. Under heavy contention
+/// a transaction can livelock. Here it fails fast with `StmError::Abandoned` instead.
+/// `f` runs at least once.
+///
+/// The time, that a transaction is blocked in `retry`, is not limited.
+/// Only the run after the wakeup counts.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let var = TVar::new(0);
+///
+/// let x = with_retry_limit(3, |tx| {
+///     var.write(tx, 42)?;
+///     var.read(tx)
+/// });
+/// assert_eq!(x, Ok(42));
+/// ```
+pub fn with_retry_limit<T, F>(limit: usize, f: F) -> Result<T, StmError>
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    let attempts = Cell::new(0);
+    let r = with_control(TxVersion::NonDeterministic, |_| {
+        // Another attempt would exceed the limit.
+        if attempts.get() >= limit {
+            TransactionControl::Abort
+        } else {
+            TransactionControl::Retry
+        }
+    }, |tx| {
+        attempts.set(attempts.get() + 1);
+        f(tx)
+    });
+    r.ok_or(StmError::Abandoned)
+}
+
+/// Run a function atomically, but abort, if it has not finished after `timeout`.
+///
+/// The time is measured from the start and includes the time, that the
+/// transaction is blocked in `retry`. It is checked after every failed attempt
+/// and a blocked transaction wakes up at the timeout to abort.
+/// An attempt, that is running at the timeout, is not interrupted.
+/// Return `None`, if the transaction has been aborted. Nothing is committed then.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// # use std::time::Duration;
+/// let var = TVar::new(0);
+///
+/// // The var never changes.
+/// let x = atomically_timeout(|tx| guard(var.read(tx)? > 0), Duration::from_millis(10));
+/// assert_eq!(x, None);
+/// ```
+pub fn atomically_timeout<T, F>(f: F, timeout: Duration) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    let start = Instant::now();
+    with_control_until(TxVersion::NonDeterministic, Some(start + timeout), |_| {
+        if start.elapsed() >= timeout {
+            TransactionControl::Abort
+        } else {
+            TransactionControl::Retry
+        }
+    }, f)
+}
+
+/// Run a function atomically with a control function and fall back
+/// to `on_give_up`, when `control` aborts the transaction.
+///
+/// `control` is called with `StmError::Failure` after every failed commit,
+/// with `StmError::Retry` after a call to `retry` and with `StmError::Blocked`
+/// periodically, while the transaction is blocked. It returns `TransactionControl::Abort`
+/// to give up.
+/// Nothing is committed in that case and the result of `on_give_up` is returned.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let queue = TVar::new(Vec::<i32>::new());
+///
+/// // Don't wait for an element, if the queue is empty.
+/// let x = atomically_or(
+///     |tx| unwrap_or_retry(queue.read(tx)?.pop()),
+///     |_| TransactionControl::Abort,
+///     || -1);
+/// assert_eq!(x, -1);
+/// ```
+pub fn atomically_or<T, F, C, G>(f: F, control: C, on_give_up: G) -> T
+where F: Fn(&mut Transaction) -> StmResult<T>,
+      C: FnMut(StmError) -> TransactionControl,
+      G: FnOnce() -> T,
+{
+    with_control(TxVersion::NonDeterministic, control, f)
+        .unwrap_or_else(on_give_up)
+}
+
+/// Run a function atomically with a control function, that can abort with a reason.
+///
+/// `control` is called like the control function of `atomically_or`.
+/// It returns `Ok(())` to keep going and `Err(reason)` to abort the transaction.
+/// Nothing is committed then and the reason is returned to the caller.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// #[derive(Debug, PartialEq)]
+/// enum Reason { Empty }
+///
+/// let queue = TVar::new(Vec::<i32>::new());
+///
+/// let x = atomically_with_abort(
+///     |tx| unwrap_or_retry(queue.read(tx)?.pop()),
+///     |_| Err(Reason::Empty));
+/// assert_eq!(x, Err(Reason::Empty));
+/// ```
+pub fn atomically_with_abort<T, E, F, C>(f: F, mut control: C) -> Result<T, E>
+where F: Fn(&mut Transaction) -> StmResult<T>,
+      C: FnMut(StmError) -> Result<(), E>,
+{
+    let mut reason = None;
+    let r = with_control(TxVersion::NonDeterministic, |e| match control(e) {
+        Ok(())  => TransactionControl::Retry,
+        Err(e)  => {
+            reason = Some(e);
+            TransactionControl::Abort
+        }
+    }, f);
+
+    match r {
+        Some(t) => Ok(t),
+        None    => Err(reason.expect("STM: transaction aborted without a reason")),
+    }
+}
+
+/// Run a function atomically, but abort if it accesses more than `max_vars` vars.
+///
+/// Every var, that a transaction accesses, takes an entry in its log.
+/// A buggy transaction, that touches an unbounded number of vars, could
+/// exhaust the memory. Here the access to the first var beyond the limit fails
+/// and the transaction is aborted without committing. Return `None` in that case.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let vars: Vec<_> = (0..10).map(TVar::new).collect();
+///
+/// let sum = |tx: &mut Transaction| {
+///     let mut sum = 0;
+///     for var in &vars {
+///         sum += var.read(tx)?;
+///     }
+///     Ok(sum)
+/// };
+///
+/// assert_eq!(atomically_bounded_mem(10, sum), Some(45));
+/// assert_eq!(atomically_bounded_mem(5, sum), None);
+/// ```
+pub fn atomically_bounded_mem<T, F>(max_vars: usize, f: F) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    let exceeded = Cell::new(false);
+    with_control(TxVersion::NonDeterministic, |_| {
+        if exceeded.get() {
+            TransactionControl::Abort
+        } else {
+            TransactionControl::Retry
+        }
+    }, |tx| {
+        tx.set_max_vars(Some(max_vars));
+        let r = f(tx);
+
+        // Never commit, when `f` has ignored the refused access.
+        if tx.max_vars_exceeded() {
+            exceeded.set(true);
+            return Err(StmError::Failure);
+        }
+        r
+    })
+}
+
+/// Run a function atomically and count, how often it had to be rerun.
+///
+/// Return the result together with the number of reruns, that were caused
+/// by `retry` or by a conflict on commit. An uncontended transaction returns 0.
+/// This gives a cheap measure of the contention at a single call site.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let var = TVar::new(21);
+/// let (x, reruns) = atomically_counted(|tx| {
+///     var.modify(tx, |x| x * 2)?;
+///     var.read(tx)
+/// });
+/// assert_eq!(x, 42);
+/// assert_eq!(reruns, 0);
+/// ```
+pub fn atomically_counted<T, F>(f: F) -> (T, usize)
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    let runs = Cell::new(0);
+    let t = atomically(|tx| {
+        runs.set(runs.get() + 1);
+        f(tx)
+    });
+    (t, runs.get() - 1)
+}
+
+/// Run a function atomically, but return the hooks of `on_commit` instead of running them.
+///
+/// This is meant for tests, that check the side effects of a transaction
+/// without performing them. The hooks are those of the attempt, that has been
+/// committed, in the order of registration. They can still be run by calling them.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let var = TVar::new(0);
+///
+/// let (x, hooks) = atomically_capture_hooks(|tx| {
+///     var.write(tx, 42)?;
+///     tx.on_commit(|| println!("committed"));
+///     var.read(tx)
+/// });
+/// assert_eq!(x, 42);
+/// assert_eq!(hooks.len(), 1);
+/// ```
+pub fn atomically_capture_hooks<T, F>(f: F) -> (T, Vec<CommitHook>)
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    let hooks = RefCell::new(Vec::new());
+    let t = atomically(|tx| {
+        let t = f(tx)?;
+
+        // Replace the hooks of an earlier attempt, that failed to commit.
+        *hooks.borrow_mut() = tx.take_hooks();
+        Ok(t)
+    });
+    (t, hooks.into_inner())
+}
+
+/// Run a function atomically and pass its result to `then` after the commit.
+///
+/// `then` runs outside of the transaction and may borrow from the result,
+/// for example to return a part of it, without cloning it first.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let names = TVar::new(vec![String::from("alice"), String::from("bob")]);
+///
+/// let len = atomically_scoped(|tx| names.read(tx), |names| {
+///     let longest: &str = names.iter().max_by_key(|n| n.len()).unwrap();
+///     longest.len()
+/// });
+/// assert_eq!(len, 5);
+/// ```
+pub fn atomically_scoped<R, U, F, G>(f: F, then: G) -> U
+where F: Fn(&mut Transaction) -> StmResult<R>,
+      G: FnOnce(R) -> U
+{
+    then(atomically(f))
+}
+
+/// Run `f` in the transaction of the caller or in a new one.
+///
+/// This allows a library to offer a single function, that can either be composed
+/// into a larger transaction or be called on its own.
+///
+/// * If `existing` is `Some`, `f` runs in that transaction. Nothing is committed
+///   and the result of `f` is returned as it is. The caller must propagate errors
+///   with `?` like for every other transactional function.
+/// * If `existing` is `None`, `f` runs atomically in a new transaction, which is
+///   committed. The result is always `Ok`.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// fn increment(tx: Option<&mut Transaction>, var: &TVar<i32>) -> StmResult<i32> {
+///     atomically_or_join(tx, |tx| {
+///         var.modify(tx, |x| x + 1)?;
+///         var.read(tx)
+///     })
+/// }
+///
+/// let var = TVar::new(0);
+///
+/// // Standalone.
+/// assert_eq!(increment(None, &var), Ok(1));
+///
+/// // Joined into a larger transaction.
+/// let x = atomically(|tx| {
+///     increment(Some(tx), &var)?;
+///     increment(Some(tx), &var)
+/// });
+/// assert_eq!(x, 3);
+/// ```
+pub fn atomically_or_join<T, F>(existing: Option<&mut Transaction>, f: F) -> StmResult<T>
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    match existing {
+        Some(tx)    => f(tx),
+        None        => Ok(atomically(f)),
+    }
+}
+
+/// Create a builder, that runs a transaction with more options than `atomically`.
+///
+/// See `AtomicallyBuilder` for all options.
+pub fn builder() -> AtomicallyBuilder {
+    AtomicallyBuilder::new()
+}
+
+/// Run a function atomically, but give up after `timeout`.
+///
+/// A watchdog thread sets the `expired` var after `timeout`. `f` gets this var
+/// and must check it with `guard(!expired.read(tx)?)`, when it waits for something.
+/// Because the var is read, the watchdog wakes up a blocked transaction.
+/// If the transaction then retries while `expired` is set, it is aborted and
+/// `with_watchdog` returns `None`.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// # use std::time::Duration;
+/// let var = TVar::new(0);
+///
+/// let x = with_watchdog(Duration::from_millis(100), |tx, expired| {
+///     guard(!expired.read(tx)?)?;
+///     let x = var.read(tx)?;
+///     // Wait until the var is set, which never happens.
+///     guard(x != 0)?;
+///     Ok(x)
+/// });
+/// assert_eq!(x, None);
+/// ```
+pub fn with_watchdog<T, F>(timeout: Duration, f: F) -> Option<T>
+where F: Fn(&mut Transaction, &TVar<bool>) -> StmResult<T>
+{
+    // Spawning the watchdog is a side effect.
+    assert_no_transaction();
+
+    let expired = TVar::new(false);
+    let (done_tx, done_rx) = channel::<()>();
+
+    let watchdog = expired.clone();
+    thread::spawn(move || {
+        // The channel disconnects, when the transaction has finished earlier.
+        if let Err(RecvTimeoutError::Timeout) = done_rx.recv_timeout(timeout) {
+            atomically(|tx| watchdog.write(tx, true));
+        }
+    });
+
+    let r = with_control(TxVersion::NonDeterministic, |e| {
+        if e == StmError::Retry && expired.read_atomic() {
+            TransactionControl::Abort
+        } else {
+            TransactionControl::Retry
+        }
+    }, |tx| f(tx, &expired));
+
+    drop(done_tx);
+    r
+}
+
+/// A handle, that interrupts transactions blocked in `atomically_interruptible`.
+///
+/// Clones share the same state, so a clone can be sent to another thread,
+/// for example to shut down workers, that wait for work.
+/// Once interrupted, the handle stays interrupted.
+#[derive(Clone, Debug)]
+pub struct Interrupter {
+    interrupted: TVar<bool>,
+}
+
+impl Interrupter {
+    /// Create a new handle, that is not interrupted.
+    pub fn new() -> Interrupter {
+        Interrupter {
+            interrupted: TVar::new(false),
+        }
+    }
+
+    /// Interrupt all transactions, that run with this handle.
+    ///
+    /// A blocked transaction wakes up immediately.
+    pub fn interrupt(&self) {
+        atomically(|tx| self.interrupted.write(tx, true));
+    }
+
+    /// Check if `interrupt` has been called.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.read_atomic()
+    }
+}
+
+impl Default for Interrupter {
+    fn default() -> Self {
+        Interrupter::new()
+    }
+}
+
+/// Run a function atomically, but give up, when `interrupter` is interrupted.
+///
+/// Every attempt reads the state of `interrupter` like a var, so `interrupt`
+/// wakes up a transaction, that is blocked in `retry`, like a write to any
+/// of the vars, that it waits for.
+/// Return `None`, if the transaction has been interrupted before it has committed.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let interrupter = Interrupter::new();
+/// interrupter.interrupt();
+///
+/// let var = TVar::new(0);
+/// let x = atomically_interruptible(&interrupter, |tx| {
+///     let x = var.read(tx)?;
+///     guard(x != 0)?;
+///     Ok(x)
+/// });
+/// assert_eq!(x, None);
+/// ```
+pub fn atomically_interruptible<T, F>(interrupter: &Interrupter, f: F) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    with_control(TxVersion::NonDeterministic, |e| {
+        if e == StmError::Retry && interrupter.is_interrupted() {
+            TransactionControl::Abort
+        } else {
+            TransactionControl::Retry
+        }
+    }, |tx| {
+        guard(!interrupter.interrupted.read(tx)?)?;
+        f(tx)
+    })
+}
+
+/// Atomically write a list of values to their `TVar`s.
+///
+/// This is a shortcut for the common case of setting multiple vars at once,
+/// without writing the closure for `atomically` by hand.
+/// If the same `TVar` occurs multiple times, the last write wins.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let a = TVar::new(0);
+/// let b = TVar::new(0);
+///
+/// set_all(&[(&a, 1), (&b, 2)]);
+///
+/// assert_eq!(a.read_atomic(), 1);
+/// assert_eq!(b.read_atomic(), 2);
+/// ```
+pub fn set_all<T>(pairs: &[(&TVar<T>, T)])
+where T: Any + Sync + Send + Clone
+{
+    atomically(|tx| {
+        for (var, value) in pairs {
+            var.write(tx, value.clone())?;
+        }
+        Ok(())
+    })
+}
+
 #[inline]
 /// Unwrap `Option` or call retry if it is `None`.
 ///
@@ -299,6 +913,143 @@ pub fn optionally<T,F>(tx: &mut Transaction, f: F) -> StmResult<Option<T>>
     )
 }
 
+#[inline]
+/// Run `f` and return `default`, if it calls `retry`.
+///
+/// This is the same as `optionally(tx, f)?.unwrap_or(default)`.
+/// The changes of `f` are discarded like in `optionally`, but its reads stay in
+/// the log, so that a blocking transaction still waits for them.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let var = TVar::new(None);
+///
+/// let x = atomically(|tx| or_value(tx, |tx| unwrap_or_retry(var.read(tx)?), 0));
+/// assert_eq!(x, 0);
+/// ```
+pub fn or_value<T, F>(tx: &mut Transaction, f: F, default: T) -> StmResult<T>
+    where F: Fn(&mut Transaction) -> StmResult<T>
+{
+    Ok(optionally(tx, f)?.unwrap_or(default))
+}
+
+#[inline]
+/// Run two transactional functions in the same transaction and return both results.
+///
+/// This is the same as calling `f` and `g` one after another with the same `tx`.
+/// Because both run in one transaction, their effects are committed atomically
+/// together, or not at all. `f` and `g` don't need to know about each other,
+/// which is what makes STM composable.
+///
+/// If one of them calls `retry`, the whole transaction retries.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let account_a = TVar::new(10);
+/// let account_b = TVar::new(0);
+///
+/// let withdraw = |tx: &mut Transaction| {
+///     let a = account_a.read(tx)?;
+///     guard(a >= 5)?;
+///     account_a.write(tx, a - 5)
+/// };
+/// let deposit = |tx: &mut Transaction| account_b.modify(tx, |b| b + 5);
+///
+/// // Transfer atomically.
+/// atomically(|tx| compose(tx, withdraw, deposit));
+///
+/// assert_eq!(account_a.read_atomic(), 5);
+/// assert_eq!(account_b.read_atomic(), 5);
+/// ```
+pub fn compose<A, B, F, G>(tx: &mut Transaction, f: F, g: G) -> StmResult<(A, B)>
+    where F: FnOnce(&mut Transaction) -> StmResult<A>,
+          G: FnOnce(&mut Transaction) -> StmResult<B>,
+{
+    let a = f(tx)?;
+    let b = g(tx)?;
+    Ok((a, b))
+}
+
+/// Read all `vars` and combine their values with `f`.
+///
+/// `f` combines two values into one, like `max`, `min` or `+`.
+/// The values are combined from left to right.
+/// Return `None`, if `vars` is empty.
+///
+/// All vars are read in the same transaction, so the result
+/// reflects a consistent snapshot of them.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// # use std::cmp;
+/// let vars = [TVar::new(3), TVar::new(7), TVar::new(5)];
+///
+/// let max = atomically(|tx| reduce(tx, &vars, cmp::max));
+/// assert_eq!(max, Some(7));
+/// ```
+pub fn reduce<T, F>(tx: &mut Transaction, vars: &[TVar<T>], f: F) -> StmResult<Option<T>>
+    where T: Any + Sync + Send + Clone,
+          F: Fn(T, T) -> T,
+{
+    let mut acc = None;
+    for var in vars {
+        let x = var.read(tx)?;
+        acc = Some(match acc {
+            Some(acc)   => f(acc, x),
+            None        => x,
+        });
+    }
+    Ok(acc)
+}
+
+/// Read several `TVar`s of possibly different types in one transaction.
+///
+/// `snapshot!(a, b, c)` expands to a single `atomically`, that reads all vars and
+/// returns their values as a tuple. The values are a consistent state of all vars.
+/// Without any var the snapshot is `()`.
+///
+/// Like `atomically`, it must not be used inside of a transaction.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let count = TVar::new(2);
+/// let name = TVar::new(String::from("items"));
+///
+/// let (count, name) = snapshot!(count, name);
+/// assert_eq!(format!("{} {}", count, name), "2 items");
+/// ```
+///
+/// Only `TVar`s can be read:
+///
+/// ```compile_fail
+/// # use stm_core::*;
+/// let x = 5;
+/// let _ = snapshot!(x);
+/// ```
+///
+/// At most one trailing comma is allowed:
+///
+/// ```compile_fail
+/// # use stm_core::*;
+/// let a = TVar::new(1);
+/// let _ = snapshot!(a,,);
+/// ```
+#[macro_export]
+macro_rules! snapshot {
+    () => { () };
+    ($($var:expr),+ $(,)?) => {
+        $crate::atomically(|tx| Ok(( $( $crate::TVar::read(&$var, tx)?, )+ )))
+    };
+}
+
 /// Run a function atomically by using Deterministic Software Transactional Memory.
 pub fn dtm() -> DTM {
     DTM::new()
@@ -317,6 +1068,7 @@ pub fn freeze(mut d:DTM){
 #[cfg(test)]
 mod test_lib {
     use super::*;
+    use std::cell::RefCell;
 
     #[test]
     fn infinite_retry() {
@@ -500,32 +1252,428 @@ mod test_lib {
     }
 
     #[test]
-    fn guard_true() {
-        let x = guard(true);
-        assert_eq!(x, Ok(()));
+    fn guard_true() {
+        let x = guard(true);
+        assert_eq!(x, Ok(()));
+    }
+
+    #[test]
+    fn guard_false() {
+        let x = guard(false);
+        assert_eq!(x, retry());
+    }
+
+    #[test]
+    fn optionally_succeed() {
+        let x = atomically(|t| 
+            optionally(t, |_| Ok(42)));
+        assert_eq!(x, Some(42));
+    }
+
+    #[test]
+    fn optionally_fail() {
+        let x:Option<i32> = atomically(|t| 
+            optionally(t, |_| retry()));
+        assert_eq!(x, None);
+    }
+
+    /// Set three vars at once, while another thread
+    /// checks that it sees either all old or all new values.
+    #[test]
+    fn set_all_atomic() {
+        use std::thread;
+
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let c = TVar::new(0);
+        let (ac, bc, cc) = (a.clone(), b.clone(), c.clone());
+
+        let reader = thread::spawn(move || {
+            for _ in 0..1000 {
+                let (x, y, z) = atomically(|tx|
+                    Ok((ac.read(tx)?, bc.read(tx)?, cc.read(tx)?)));
+                assert!((x, y, z) == (0, 0, 0) || (x, y, z) == (1, 2, 3));
+            }
+        });
+
+        set_all(&[(&a, 1), (&b, 2), (&c, 3)]);
+        reader.join().unwrap();
+
+        assert_eq!(a.read_atomic(), 1);
+        assert_eq!(b.read_atomic(), 2);
+        assert_eq!(c.read_atomic(), 3);
+    }
+
+    /// A transaction that always conflicts, because another thread
+    /// changes the read var before every commit.
+    #[test]
+    fn atomically_bounded_panics() {
+        use std::thread;
+        use std::panic;
+
+        let var = TVar::new(0);
+
+        let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            atomically_bounded(5, |tx| {
+                let x = var.read(tx)?;
+                let varc = var.clone();
+                thread::spawn(move || atomically(|tx| varc.modify(tx, |x| x + 1)))
+                    .join().unwrap();
+                var.write(tx, x)
+            })
+        }));
+
+        let msg = r.unwrap_err();
+        let msg = msg.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("5 retries"), "{}", msg);
+    }
+
+    /// With the `stats` feature the panic names the var, that keeps conflicting.
+    #[cfg(feature = "stats")]
+    #[test]
+    fn atomically_bounded_names_conflicts() {
+        use std::thread;
+        use std::panic;
+
+        let var = TVar::new_with_name(0, "counter");
+
+        let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            atomically_bounded(3, |tx| {
+                let x = var.read(tx)?;
+                let varc = var.clone();
+                thread::spawn(move || atomically(|tx| varc.modify(tx, |x| x + 1)))
+                    .join().unwrap();
+                var.write(tx, x)
+            })
+        }));
+
+        let msg = r.unwrap_err();
+        let msg = msg.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("3 retries"), "{}", msg);
+        assert!(msg.contains("counter (3)"), "{}", msg);
+    }
+
+    #[test]
+    fn atomically_bounded_succeeds() {
+        let var = TVar::new(21);
+        let x = atomically_bounded(1, |tx| {
+            var.modify(tx, |x| x * 2)?;
+            var.read(tx)
+        });
+        assert_eq!(x, 42);
+    }
+
+    /// A read from one function and a write from another are committed together.
+    #[test]
+    fn compose_read_write() {
+        let counter = TVar::new(1);
+        let log = TVar::new(Vec::new());
+
+        let (x, ()) = atomically(|tx| compose(tx,
+            |tx| counter.modify(tx, |x| x + 1).and_then(|_| counter.read(tx)),
+            |tx| log.modify(tx, |mut v| { v.push("incremented"); v })
+        ));
+
+        assert_eq!(x, 2);
+        assert_eq!(log.read_atomic(), ["incremented"]);
+    }
+
+    /// If the second function retries, the first one is not committed.
+    #[test]
+    fn compose_retry() {
+        let var = TVar::new(0);
+
+        let x = atomically(|tx| optionally(tx, |tx| compose(tx,
+            |tx| var.write(tx, 1),
+            |_| retry::<()>()
+        )));
+
+        assert_eq!(x, None);
+        assert_eq!(var.read_atomic(), 0);
+    }
+
+    /// `retry_on` only wakes up on changes of the chosen vars.
+    #[test]
+    fn retry_on_subset() {
+        use std::thread;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let a = TVar::new(false);
+        let b = TVar::new(0);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let (ac, bc, runsc) = (a.clone(), b.clone(), runs.clone());
+        let waiter = thread::spawn(move || atomically(|tx| {
+            runsc.fetch_add(1, Ordering::SeqCst);
+            let x = bc.read(tx)?;
+            if !ac.read(tx)? {
+                return tx.retry_on(&[&ac]);
+            }
+            Ok(x)
+        }));
+
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // A change of `b` does not wake up the transaction.
+        atomically(|tx| b.write(tx, 42));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        atomically(|tx| a.write(tx, true));
+        assert_eq!(waiter.join().unwrap(), 42);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    /// Hooks run once after the commit and may start new transactions.
+    #[test]
+    fn on_commit_runs_after_commit() {
+        use std::thread;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let var = TVar::new(0);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let attempts = Cell::new(0);
+
+        atomically(|tx| {
+            let x = var.read(tx)?;
+            let (varc, r) = (var.clone(), runs.clone());
+            tx.on_commit(move || {
+                r.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(atomically(|tx| varc.read(tx)), 11);
+            });
+
+            // The hook of the first attempt is discarded together with the attempt.
+            attempts.set(attempts.get() + 1);
+            if attempts.get() == 1 {
+                let varc = var.clone();
+                thread::spawn(move || atomically(|tx| varc.write(tx, 1)))
+                    .join().unwrap();
+            }
+
+            // Hooks in a retrying branch of `or` are discarded.
+            let r = runs.clone();
+            tx.or(move |tx| {
+                let r = r.clone();
+                tx.on_commit(move || { r.fetch_add(100, Ordering::SeqCst); });
+                retry()
+            }, |_| Ok(()))?;
+
+            var.write(tx, x + 10)
+        });
+
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    /// A panicking hook does not keep the others from running
+    /// and STM is still usable afterwards.
+    #[test]
+    fn on_commit_panic() {
+        use std::panic;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let var = TVar::new(0);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            atomically(|tx| {
+                var.write(tx, 1)?;
+                let (r1, r3) = (runs.clone(), runs.clone());
+                tx.on_commit(move || { r1.fetch_add(1, Ordering::SeqCst); });
+                tx.on_commit(|| panic!("hook failed"));
+                tx.on_commit(move || { r3.fetch_add(1, Ordering::SeqCst); });
+                Ok(())
+            })
+        }));
+
+        assert!(r.is_err());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        // The commit has succeeded and new transactions can run.
+        assert_eq!(atomically(|tx| var.read(tx)), 1);
+    }
+
+    #[test]
+    fn reduce_max() {
+        use std::cmp;
+
+        let vars: Vec<_> = [4, -2, 9, 1].iter().cloned().map(TVar::new).collect();
+        assert_eq!(atomically(|tx| reduce(tx, &vars, cmp::max)), Some(9));
+        assert_eq!(atomically(|tx| reduce(tx, &vars, |a, b| a + b)), Some(12));
+
+        let empty: Vec<TVar<i32>> = Vec::new();
+        assert_eq!(atomically(|tx| reduce(tx, &empty, cmp::max)), None);
+    }
+
+    /// A write to any of the vars invalidates the result.
+    #[test]
+    fn reduce_conflict() {
+        use std::cmp;
+
+        let vars: Vec<_> = (0..4).map(TVar::new).collect();
+
+        let mut log = Transaction::new();
+        assert_eq!(reduce(&mut log, &vars, cmp::max), Ok(Some(3)));
+
+        atomically(|tx| vars[1].write(tx, 10));
+        assert!(!log.commit());
+
+        assert_eq!(atomically(|tx| reduce(tx, &vars, cmp::max)), Some(10));
+    }
+
+    /// Readers validate two mostly read vars without locks, while a writer
+    /// occasionally changes both. No reader may commit an inconsistent pair.
+    #[test]
+    fn read_mostly_consistency() {
+        use std::thread;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let a = TVar::new(0i64);
+        let b = TVar::new(0i64);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (aw, bw, stopw) = (a.clone(), b.clone(), stop.clone());
+        let writer = thread::spawn(move || {
+            while !stopw.load(Ordering::SeqCst) {
+                atomically(|tx| {
+                    aw.modify(tx, |x| x + 1)?;
+                    bw.modify(tx, |x| x - 1)
+                });
+                thread::sleep(Duration::from_micros(200));
+            }
+        });
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let (a, b) = (a.clone(), b.clone());
+            thread::spawn(move || {
+                let sum = TVar::new(0);
+                for _ in 0..5000 {
+                    // Read only.
+                    let (x, y) = atomically(|tx| {
+                        let x = a.read(tx)?;
+                        thread::yield_now();
+                        Ok((x, b.read(tx)?))
+                    });
+                    assert_eq!(x + y, 0);
+
+                    // Read and write another var.
+                    atomically(|tx| {
+                        let x = a.read(tx)?;
+                        thread::yield_now();
+                        let y = b.read(tx)?;
+                        sum.write(tx, x + y)
+                    });
+                    assert_eq!(sum.read_atomic(), 0);
+                }
+            })
+        }).collect();
+
+        for r in readers {
+            r.join().unwrap();
+        }
+        stop.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+    }
+
+    /// Force a conflict on the first `K` runs.
+    #[test]
+    fn atomically_counted_conflicts() {
+        use std::thread;
+
+        const K: usize = 3;
+        let var = TVar::new(0);
+        let attempts = Cell::new(0);
+
+        let (x, reruns) = atomically_counted(|tx| {
+            let x = var.read(tx)?;
+            attempts.set(attempts.get() + 1);
+            if attempts.get() <= K {
+                let varc = var.clone();
+                thread::spawn(move || atomically(|tx| varc.modify(tx, |x| x + 1)))
+                    .join().unwrap();
+            }
+            var.write(tx, x * 10)?;
+            Ok(x)
+        });
+
+        assert_eq!(x, K);
+        assert_eq!(reruns, K);
+        assert_eq!(var.read_atomic(), K * 10);
+    }
+
+    /// Drain a vector while another thread pushes to it.
+    /// No element may get lost.
+    #[test]
+    fn take_concurrent_push() {
+        use std::thread;
+
+        let var = TVar::new(Vec::new());
+        let varc = var.clone();
+
+        let pusher = thread::spawn(move || {
+            for i in 0..1000 {
+                atomically(|tx| varc.modify(tx, |mut v| { v.push(i); v }));
+            }
+        });
+
+        let mut drained = Vec::new();
+        while drained.len() < 1000 {
+            drained.extend(atomically(|tx| var.take(tx)));
+        }
+        pusher.join().unwrap();
+
+        assert!(var.read_atomic().is_empty());
+        assert_eq!(drained, (0..1000).collect::<Vec<_>>());
     }
 
+    /// A transaction, that waits forever, is stopped by the watchdog.
     #[test]
-    fn guard_false() {
-        let x = guard(false);
-        assert_eq!(x, retry());
+    fn with_watchdog_timeout() {
+        use std::time::{Duration, Instant};
+
+        let var = TVar::new(0);
+        let start = Instant::now();
+
+        let x = with_watchdog(Duration::from_millis(200), |tx, expired| {
+            guard(!expired.read(tx)?)?;
+            let x = var.read(tx)?;
+            guard(x != 0)?;
+            Ok(x)
+        });
+
+        assert_eq!(x, None);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(1000));
     }
 
     #[test]
-    fn optionally_succeed() {
-        let x = atomically(|t| 
-            optionally(t, |_| Ok(42)));
+    fn with_watchdog_success() {
+        let var = TVar::new(42);
+
+        let x = with_watchdog(Duration::from_secs(10), |tx, expired| {
+            guard(!expired.read(tx)?)?;
+            var.read(tx)
+        });
+
         assert_eq!(x, Some(42));
     }
 
     #[test]
-    fn optionally_fail() {
-        let x:Option<i32> = atomically(|t| 
-            optionally(t, |_| retry()));
-        assert_eq!(x, None);
+    fn set_all_duplicate_last_wins() {
+        let a = TVar::new(0);
+
+        set_all(&[(&a, 1), (&a, 2)]);
+
+        assert_eq!(a.read_atomic(), 2);
     }
 
-#[test]
+    #[test]
     fn deterministic_dep_order() {
         use std::thread;
 
@@ -671,5 +1819,650 @@ mod test_lib {
 
         assert_eq!(2, var.read_atomic());
    }
-}
 
+    /// Dropping a DTM without freeze freezes it.
+    #[test]
+    fn deterministic_drop_freezes() {
+        let mut dtm = dtm();
+        let handle = dtm.register();
+        drop(dtm);
+
+        assert_eq!(42, det_atomically(handle, |_| Ok(42)));
+    }
+
+    /// A transaction, that touches too many vars, is aborted without committing.
+    #[test]
+    fn bounded_mem_abort() {
+        let vars: Vec<_> = (0..10).map(|_| TVar::new(0)).collect();
+
+        let r = atomically_bounded_mem(5, |tx| {
+            for var in &vars {
+                var.write(tx, 1)?;
+            }
+            Ok(())
+        });
+        assert_eq!(r, None);
+        assert!(vars.iter().all(|var| var.read_atomic() == 0));
+
+        // Accessing the same vars again does not count.
+        let r = atomically_bounded_mem(2, |tx| {
+            for _ in 0..10 {
+                vars[0].modify(tx, |x| x + 1)?;
+                vars[1].read(tx)?;
+            }
+            Ok(())
+        });
+        assert_eq!(r, Some(()));
+        assert_eq!(vars[0].read_atomic(), 10);
+    }
+
+    /// Ignoring the refused access does not lead to a partial commit.
+    #[test]
+    fn bounded_mem_ignored_error() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        let r = atomically_bounded_mem(1, |tx| {
+            a.write(tx, 1)?;
+            let _ = b.write(tx, 1);
+            Ok(())
+        });
+        assert_eq!(r, None);
+        assert_eq!(a.read_atomic(), 0);
+    }
+
+    /// When a transaction panics, the later ones in the same batch can never get
+    /// the token. They must panic instead of hanging.
+    #[test]
+    fn deterministic_peer_panicked() {
+        use std::thread;
+
+        let mut dtm = dtm();
+        let handle1 = dtm.register();
+        let handle2 = dtm.register();
+        dtm.freeze();
+
+        let t1 = thread::spawn(move || det_atomically(handle1, |_| -> StmResult<()> { panic!("tx1") }));
+        let t2 = thread::spawn(move || det_atomically(handle2, |_| Ok(())));
+
+        assert!(t1.join().is_err());
+        let err = t2.join().unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("is gone"));
+    }
+
+    /// `run` returns only after all transactions have committed.
+    #[test]
+    fn deterministic_run() {
+        use std::thread;
+
+        let var = TVar::new(0);
+        let varc1 = var.clone();
+        let varc2 = var.clone();
+
+        let mut dtm = dtm();
+        let handle1 = dtm.register();
+        let handle2 = dtm.register();
+
+        let t1 = thread::spawn(move || det_atomically(handle1, |tx| varc1.modify(tx, |x| x + 1)));
+        let t2 = thread::spawn(move || det_atomically(handle2, |tx| varc2.modify(tx, |x| x * 10)));
+
+        dtm.run();
+        assert_eq!(10, var.read_atomic());
+
+        t1.join().unwrap();
+        t2.join().unwrap();
+    }
+
+    /// The second transaction takes the first branch of `or`, because it is
+    /// ordered after the first one, no matter how the threads are scheduled.
+    #[test]
+    fn deterministic_or() {
+        use std::thread;
+
+        for _ in 0..100 {
+            let var = TVar::new(0);
+            let varc1 = var.clone();
+            let varc2 = var.clone();
+
+            let mut dtm = dtm();
+            let handle1 = dtm.register();
+            let handle2 = dtm.register();
+            dtm.freeze();
+
+            let t2 = thread::spawn(move || det_atomically(handle2, |tx| tx.or(
+                |tx| {
+                    guard(varc2.read(tx)? == 1)?;
+                    Ok("first")
+                },
+                |_| Ok("second"),
+            )));
+            let t1 = thread::spawn(move || det_atomically(handle1, |tx| varc1.write(tx, 1)));
+
+            t1.join().unwrap();
+            assert_eq!(t2.join().unwrap(), "first");
+        }
+    }
+
+    #[test]
+    fn deterministic_optionally() {
+        use std::thread;
+
+        for _ in 0..100 {
+            let var = TVar::new(0);
+            let varc1 = var.clone();
+            let varc2 = var.clone();
+
+            let mut dtm = dtm();
+            let handle1 = dtm.register();
+            let handle2 = dtm.register();
+            dtm.freeze();
+
+            let t2 = thread::spawn(move || det_atomically(handle2, |tx| optionally(tx, |tx| {
+                let x = varc2.read(tx)?;
+                guard(x == 1)?;
+                varc2.write(tx, x + 1)
+            })));
+            let t1 = thread::spawn(move || det_atomically(handle1, |tx| varc1.write(tx, 1)));
+
+            t1.join().unwrap();
+            assert_eq!(t2.join().unwrap(), Some(()));
+            assert_eq!(var.read_atomic(), 2);
+        }
+    }
+
+    thread_local!(static PROFILE_EVENTS: RefCell<Vec<ProfileEvent>> = const { RefCell::new(Vec::new()) });
+
+    fn record_profile(event: ProfileEvent) {
+        PROFILE_EVENTS.with(|events| events.borrow_mut().push(event));
+    }
+
+    /// The profiler gets one event per transaction.
+    /// Other tests run in parallel, so only the events of this thread are checked.
+    #[test]
+    fn profiler_events() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        set_profiler(record_profile);
+        atomically(|tx| {
+            a.write(tx, 1)?;
+            b.read(tx)
+        });
+        let aborted = with_control(TxVersion::NonDeterministic,
+            |_| TransactionControl::Abort,
+            |tx| { a.read(tx)?; retry::<()>() });
+        clear_profiler();
+
+        assert_eq!(aborted, None);
+
+        let events = PROFILE_EVENTS.with(|events| events.borrow().clone());
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(events[0].outcome, ProfileOutcome::Committed);
+        assert_eq!(events[0].var_count, 2);
+        assert_eq!(events[0].attempts, 1);
+
+        assert_eq!(events[1].outcome, ProfileOutcome::Aborted);
+        assert_eq!(events[1].var_count, 1);
+        assert_eq!(events[1].attempts, 1);
+    }
+
+    #[test]
+    fn or_join_standalone() {
+        let var = TVar::new(1);
+
+        let x = atomically_or_join(None, |tx| {
+            var.modify(tx, |x| x * 2)?;
+            var.read(tx)
+        });
+        assert_eq!(x, Ok(2));
+        assert_eq!(var.read_atomic(), 2);
+    }
+
+    /// The joined function does not commit on its own and a retry
+    /// is passed to the caller.
+    #[test]
+    fn or_join_existing() {
+        let var = TVar::new(1);
+
+        let x = atomically(|tx| {
+            let joined = atomically_or_join(Some(tx), |tx| {
+                var.write(tx, 5)?;
+                Ok(10)
+            })?;
+            assert_eq!(joined, 10);
+            assert_eq!(var.read_atomic(), 1);
+
+            let retried = atomically_or_join(Some(tx), |_| retry::<i32>());
+            assert_eq!(retried, Err(StmError::Retry));
+            var.read(tx)
+        });
+        assert_eq!(x, 5);
+        assert_eq!(var.read_atomic(), 5);
+    }
+
+    #[test]
+    fn in_transaction_flag() {
+        assert!(!in_transaction());
+        atomically(|_| {
+            assert!(in_transaction());
+            Ok(())
+        });
+        assert!(!in_transaction());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "side effect inside of a transaction")]
+    fn side_effect_in_transaction() {
+        fn io() {
+            assert_no_transaction();
+        }
+
+        io();
+        atomically(|_| {
+            io();
+            Ok(())
+        });
+    }
+
+    /// Threads, that conflict on every commit, yield instead of spinning,
+    /// but still count correctly.
+    #[test]
+    fn hot_conflicts() {
+        use std::thread;
+
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 2_000;
+
+        let var = TVar::new(0);
+
+        let threads: Vec<_> = (0..THREADS).map(|_| {
+            let var = var.clone();
+            thread::spawn(move || {
+                for _ in 0..ITERATIONS {
+                    atomically(|tx| var.modify(tx, |x| x + 1));
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(var.read_atomic(), THREADS * ITERATIONS);
+    }
+
+    #[test]
+    fn capture_hooks() {
+        use std::rc::Rc;
+
+        let var = TVar::new(0);
+        let effects = Rc::new(Cell::new(0));
+
+        let (_, hooks) = atomically_capture_hooks(|tx| {
+            var.write(tx, 1)?;
+            let e = effects.clone();
+            tx.on_commit(move || e.set(e.get() + 1));
+            let e = effects.clone();
+            tx.on_commit(move || e.set(e.get() + 10));
+            Ok(())
+        });
+
+        // The transaction has been committed, but no hook has run.
+        assert_eq!(var.read_atomic(), 1);
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(effects.get(), 0);
+
+        for hook in hooks {
+            hook();
+        }
+        assert_eq!(effects.get(), 11);
+    }
+
+    static WAIT_EVENTS: std::sync::Mutex<Vec<WaitEvent>> = std::sync::Mutex::new(Vec::new());
+
+    fn record_wait(event: WaitEvent) {
+        WAIT_EVENTS.lock().unwrap().push(event);
+    }
+
+    /// A transaction blocked in `retry` reports the vars, that it waits for.
+    #[test]
+    fn wait_observer_events() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        set_wait_observer(record_wait);
+
+        let waiter = {
+            let a = a.clone();
+            let b = b.clone();
+            thread::spawn(move || {
+                atomically(|tx| {
+                    let x = a.read(tx)? + b.read(tx)?;
+                    guard(x > 0)
+                })
+            })
+        };
+        let id = waiter.thread().id();
+
+        // Other tests run in parallel, so only the events of the waiter are checked.
+        let events_of_waiter = || WAIT_EVENTS.lock().unwrap().iter()
+            .filter(|e| e.thread == id)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        while events_of_waiter().is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut expected = vec![a.id(), b.id()];
+        expected.sort();
+        assert_eq!(events_of_waiter(), vec![WaitEvent { thread: id, vars: expected, blocked: true }]);
+
+        atomically(|tx| a.write(tx, 1));
+        waiter.join().unwrap();
+        clear_wait_observer();
+
+        let events = events_of_waiter();
+        assert_eq!(events.len(), 2);
+        assert!(!events[1].blocked);
+    }
+
+    #[test]
+    fn scoped_projection() {
+        let var = TVar::new((String::from("key"), 42));
+
+        let upper = atomically_scoped(|tx| {
+            var.modify(tx, |(k, v)| (k, v + 1))?;
+            var.read(tx)
+        }, |pair| {
+            // Borrow from the committed result.
+            let (ref key, value) = pair;
+            format!("{}={}", key.to_uppercase(), value)
+        });
+        assert_eq!(upper, "KEY=43");
+    }
+
+    /// An empty transaction commits without locking anything.
+    #[test]
+    fn empty_transaction() {
+        assert_eq!(atomically(|_| Ok(42)), 42);
+
+        let mut log = Transaction::new();
+        assert_eq!(log.commit_outcome(), CommitOutcome::Committed);
+    }
+
+    /// The fast path of an empty transaction still detects nesting.
+    #[test]
+    #[should_panic(expected = "Nested Transaction")]
+    fn empty_transaction_nested() {
+        atomically(|_| Ok(atomically(|_| Ok(1))));
+    }
+
+    /// An interrupt wakes up a blocked transaction.
+    #[test]
+    fn interrupt_blocked() {
+        use std::time::Instant;
+
+        let interrupter = Interrupter::new();
+        let var = TVar::new(0);
+
+        let waiter = {
+            let interrupter = interrupter.clone();
+            let var = var.clone();
+            thread::spawn(move || atomically_interruptible(&interrupter, |tx| {
+                let x = var.read(tx)?;
+                guard(x != 0)?;
+                Ok(x)
+            }))
+        };
+
+        while !var.has_waiters() {
+            thread::yield_now();
+        }
+
+        let start = Instant::now();
+        interrupter.interrupt();
+        assert_eq!(waiter.join().unwrap(), None);
+
+        // The interrupt must not wait for the periodic check of the blocked thread.
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert!(interrupter.is_interrupted());
+    }
+
+    #[test]
+    fn or_value_success() {
+        let var = TVar::new(Some(5));
+
+        let mut log = Transaction::new();
+        let x = or_value(&mut log, |tx| unwrap_or_retry(var.read(tx)?), 0);
+        assert_eq!(x, Ok(5));
+
+        // The read is validated on commit.
+        atomically(|tx| var.write(tx, None));
+        assert!(!log.commit());
+    }
+
+    #[test]
+    fn or_value_default() {
+        let var = TVar::new(None);
+        let written = TVar::new(0);
+
+        let mut log = Transaction::new();
+        let x = or_value(&mut log, |tx| {
+            written.write(tx, 1)?;
+            unwrap_or_retry(var.read(tx)?)
+        }, 0);
+        assert_eq!(x, Ok(0));
+
+        // The write is discarded, but the read is kept for blocking.
+        let entries: Vec<_> = log.log_entries().collect();
+        assert_eq!(entries, vec![(var.id(), LogEntryKind::ReadObsolete)]);
+
+        assert!(log.commit());
+        assert_eq!(written.read_atomic(), 0);
+    }
+
+    #[test]
+    fn snapshot_macro() {
+        let a = TVar::new(1);
+        let b = TVar::new(String::from("one"));
+        let c = TVar::new(vec![1]);
+
+        let () = snapshot!();
+        assert_eq!(snapshot!(a), (1,));
+        assert_eq!(snapshot!(a, b, c,), (1, String::from("one"), vec![1]));
+    }
+
+    /// The values of a snapshot are always from the same state.
+    #[test]
+    fn snapshot_consistent() {
+        let number = TVar::new(0);
+        let text = TVar::new(String::from("0"));
+
+        let writer = {
+            let number = number.clone();
+            let text = text.clone();
+            thread::spawn(move || {
+                for i in 1..500 {
+                    atomically(|tx| {
+                        number.write(tx, i)?;
+                        text.write(tx, i.to_string())
+                    });
+                }
+            })
+        };
+
+        for _ in 0..500 {
+            let (n, t) = snapshot!(number, text);
+            assert_eq!(n.to_string(), t);
+        }
+        writer.join().unwrap();
+    }
+
+    /// A transaction, that calls `retry`, is parked until a read var changes,
+    /// instead of running again and again.
+    #[test]
+    fn retry_parks_thread() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let var = TVar::new(0);
+        let unrelated = TVar::new(0);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let waiter = {
+            let (var, runs) = (var.clone(), runs.clone());
+            thread::spawn(move || atomically(|tx| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                guard(var.read(tx)? > 0)
+            }))
+        };
+
+        while !var.has_waiters() {
+            thread::yield_now();
+        }
+        thread::sleep(Duration::from_millis(100));
+        atomically(|tx| unrelated.write(tx, 1));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        atomically(|tx| var.write(tx, 1));
+        waiter.join().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    /// A transaction, that always conflicts, is given up after the limit.
+    #[test]
+    fn retry_limit_conflicts() {
+        let var = TVar::new(0);
+        let attempts = Cell::new(0);
+
+        let x = with_retry_limit(5, |tx| {
+            attempts.set(attempts.get() + 1);
+            let x = var.read(tx)?;
+            let varc = var.clone();
+            thread::spawn(move || atomically(|tx| varc.modify(tx, |x| x + 1)))
+                .join().unwrap();
+            var.write(tx, x)
+        });
+
+        assert_eq!(x, Err(StmError::Abandoned));
+        assert_eq!(attempts.get(), 5);
+        assert_eq!(var.read_atomic(), 5);
+    }
+
+    /// `atomically_or` commits, if the transaction finishes.
+    #[test]
+    fn atomically_or_commit() {
+        let var = TVar::new(1);
+
+        let x = atomically_or(|tx| {
+            var.modify(tx, |x| x + 1)?;
+            var.read(tx)
+        }, |_| TransactionControl::Abort, || 0);
+
+        assert_eq!(x, 2);
+        assert_eq!(var.read_atomic(), 2);
+    }
+
+    /// `atomically_or` gives up after a few retries and commits nothing.
+    /// Another thread keeps changing a read var, so that every retry wakes up.
+    #[test]
+    fn atomically_or_give_up() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let var = TVar::new(1);
+        let wake = TVar::new(0);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let wakec = wake.clone();
+        let donec = done.clone();
+        let waker = thread::spawn(move || {
+            while !donec.load(Ordering::SeqCst) {
+                atomically(|tx| wakec.modify(tx, |x| x + 1));
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let mut retries = 0;
+        let x = atomically_or(|tx| {
+            wake.read(tx)?;
+            var.write(tx, 10)?;
+            retry()
+        }, |e| match e {
+            StmError::Retry => {
+                retries += 1;
+                if retries < 3 { TransactionControl::Retry } else { TransactionControl::Abort }
+            }
+            StmError::Blocked => TransactionControl::Retry,
+            e => panic!("unexpected {:?}", e),
+        }, || 0);
+
+        done.store(true, Ordering::SeqCst);
+        waker.join().unwrap();
+
+        assert_eq!(x, 0);
+        assert_eq!(retries, 3);
+        assert_eq!(var.read_atomic(), 1);
+    }
+
+    /// A transaction, that waits for a var, that never changes, times out.
+    #[test]
+    fn atomically_timeout_blocked() {
+        let var = TVar::new(0);
+        let start = Instant::now();
+
+        let x = atomically_timeout(|tx| {
+            let x = var.read(tx)?;
+            guard(x > 0)?;
+            Ok(x)
+        }, Duration::from_millis(110));
+
+        assert_eq!(x, None);
+        assert!(start.elapsed() >= Duration::from_millis(110));
+        // The periodic check alone would abort only after 200ms.
+        assert!(start.elapsed() < Duration::from_millis(190), "{:?}", start.elapsed());
+    }
+
+    /// A transaction, that finishes in time, is committed.
+    #[test]
+    fn atomically_timeout_commit() {
+        let var = TVar::new(0);
+        let x = atomically_timeout(|tx| var.update_and_read(tx, |x| x + 1),
+            Duration::from_secs(10));
+        assert_eq!(x, Some(1));
+        assert_eq!(var.read_atomic(), 1);
+    }
+
+    /// The reason of an abort is passed to the caller.
+    #[test]
+    fn atomically_with_abort_reason() {
+        let var = TVar::new(0);
+        let attempts = Cell::new(0);
+
+        let x: Result<(), String> = atomically_with_abort(|tx| {
+            attempts.set(attempts.get() + 1);
+            let x = var.read(tx)?;
+            let varc = var.clone();
+            thread::spawn(move || atomically(|tx| varc.modify(tx, |x| x + 1)))
+                .join().unwrap();
+            var.write(tx, x)
+        }, |e| {
+            assert_eq!(e, StmError::Failure);
+            if attempts.get() < 2 {
+                Ok(())
+            } else {
+                Err(format!("{} conflicts", attempts.get()))
+            }
+        });
+
+        assert_eq!(x, Err(String::from("2 conflicts")));
+        assert_eq!(var.read_atomic(), 2);
+
+        let x: Result<i32, String> = atomically_with_abort(|tx| var.read(tx), |_| Ok(()));
+        assert_eq!(x, Ok(2));
+    }
+}
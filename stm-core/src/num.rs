@@ -0,0 +1,170 @@
+// Copyright 2015-2016 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::ops::{Add, Sub};
+
+use super::result::StmResult;
+use super::transaction::Transaction;
+use super::tvar::TVar;
+
+/// Numeric operations on a `TVar` in the style of `std::sync::atomic`.
+///
+/// It is implemented for `TVar`s of all integer and float types.
+/// Every operation reads the var in the transaction and writes the result,
+/// so it composes with other accesses like `read` and `write`.
+/// Unlike the atomic types, an overflow behaves like the arithmetic operators
+/// and panics in debug builds.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// let var = TVar::new(5);
+///
+/// let old = atomically(|tx| var.fetch_add(tx, 3));
+/// assert_eq!(old, 5);
+///
+/// atomically(|tx| var.fetch_max(tx, 4));
+/// assert_eq!(var.read_atomic(), 8);
+/// ```
+pub trait TNum<T> {
+    /// Add `value` and return the previous value.
+    fn fetch_add(&self, tx: &mut Transaction, value: T) -> StmResult<T>;
+
+    /// Subtract `value` and return the previous value.
+    fn fetch_sub(&self, tx: &mut Transaction, value: T) -> StmResult<T>;
+
+    /// Store the maximum of the current value and `value` and return the previous value.
+    ///
+    /// The var is only written, if `value` is larger, so that transactions,
+    /// that don't change it, don't conflict with its readers.
+    fn fetch_max(&self, tx: &mut Transaction, value: T) -> StmResult<T>;
+
+    /// Store the minimum of the current value and `value` and return the previous value.
+    ///
+    /// The var is only written, if `value` is smaller.
+    fn fetch_min(&self, tx: &mut Transaction, value: T) -> StmResult<T>;
+
+    /// Store `new`, if the current value equals `expected`.
+    ///
+    /// Return if the value has been stored. The var is read in both cases.
+    /// This is the same as `TVar::compare_and_swap`.
+    fn compare_and_swap(&self, tx: &mut Transaction, expected: &T, new: T) -> StmResult<bool>;
+}
+
+impl<T> TNum<T> for TVar<T>
+    where T: Any + Sync + Send + Copy + PartialOrd + Add<Output = T> + Sub<Output = T>
+{
+    fn fetch_add(&self, tx: &mut Transaction, value: T) -> StmResult<T> {
+        let old = self.read(tx)?;
+        self.write(tx, old + value)?;
+        Ok(old)
+    }
+
+    fn fetch_sub(&self, tx: &mut Transaction, value: T) -> StmResult<T> {
+        let old = self.read(tx)?;
+        self.write(tx, old - value)?;
+        Ok(old)
+    }
+
+    fn fetch_max(&self, tx: &mut Transaction, value: T) -> StmResult<T> {
+        let old = self.read(tx)?;
+        if value > old {
+            self.write(tx, value)?;
+        }
+        Ok(old)
+    }
+
+    fn fetch_min(&self, tx: &mut Transaction, value: T) -> StmResult<T> {
+        let old = self.read(tx)?;
+        if value < old {
+            self.write(tx, value)?;
+        }
+        Ok(old)
+    }
+
+    fn compare_and_swap(&self, tx: &mut Transaction, expected: &T, new: T) -> StmResult<bool> {
+        TVar::compare_and_swap(self, tx, expected, new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::atomically;
+    use crate::transaction::log_var::LogEntryKind;
+
+    #[test]
+    fn fetch_add_sub() {
+        let var = TVar::new(10);
+        assert_eq!(atomically(|tx| var.fetch_add(tx, 5)), 10);
+        assert_eq!(atomically(|tx| var.fetch_sub(tx, 3)), 15);
+        assert_eq!(var.read_atomic(), 12);
+
+        let var = TVar::new(1.5);
+        assert_eq!(atomically(|tx| var.fetch_add(tx, 1.0)), 1.5);
+        assert_eq!(var.read_atomic(), 2.5);
+    }
+
+    #[test]
+    fn fetch_max_min() {
+        let var = TVar::new(10);
+        assert_eq!(atomically(|tx| var.fetch_max(tx, 20)), 10);
+        assert_eq!(atomically(|tx| var.fetch_max(tx, 15)), 20);
+        assert_eq!(atomically(|tx| var.fetch_min(tx, 5)), 20);
+        assert_eq!(atomically(|tx| var.fetch_min(tx, 7)), 5);
+        assert_eq!(var.read_atomic(), 5);
+    }
+
+    /// `fetch_max` and `fetch_min` don't write, if the value stays the same.
+    #[test]
+    fn fetch_max_no_write() {
+        let var = TVar::new(10);
+        let mut tx = Transaction::new();
+
+        var.fetch_max(&mut tx, 5).unwrap();
+        var.fetch_min(&mut tx, 15).unwrap();
+        assert_eq!(tx.log_entries().map(|(_, kind)| kind).collect::<Vec<_>>(),
+                   vec![LogEntryKind::Read]);
+
+        var.fetch_max(&mut tx, 20).unwrap();
+        assert_eq!(tx.log_entries().map(|(_, kind)| kind).collect::<Vec<_>>(),
+                   vec![LogEntryKind::ReadWrite]);
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let var = TVar::new(1u8);
+        assert!(atomically(|tx| TNum::compare_and_swap(&var, tx, &1, 2)));
+        assert!(!atomically(|tx| TNum::compare_and_swap(&var, tx, &1, 3)));
+        assert_eq!(var.read_atomic(), 2);
+    }
+
+    /// Concurrent increments don't get lost.
+    #[test]
+    fn concurrent_fetch_add() {
+        use std::thread;
+
+        let var = TVar::new(0u64);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let var = var.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    atomically(|tx| var.fetch_add(tx, 1));
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(var.read_atomic(), 4000);
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2015-2016 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::ThreadId;
+use std::time::Duration;
+
+use crate::tvar::VarId;
+
+/// A slot for a callback, that is checked on hot paths.
+///
+/// The flag tells, if a callback is set, so that an empty slot
+/// costs a single relaxed load instead of taking the lock.
+struct Callback<E> {
+    is_set: AtomicBool,
+    f: RwLock<Option<fn(E)>>,
+}
+
+impl<E> Callback<E> {
+    const fn new() -> Callback<E> {
+        Callback {
+            is_set: AtomicBool::new(false),
+            f: RwLock::new(None),
+        }
+    }
+
+    fn set(&self, f: Option<fn(E)>) {
+        let mut slot = self.f.write().unwrap_or_else(|e| e.into_inner());
+        *slot = f;
+        self.is_set.store(f.is_some(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<fn(E)> {
+        if !self.is_set.load(Ordering::Relaxed) {
+            return None;
+        }
+        *self.f.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// How a profiled transaction has ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileOutcome {
+    /// The transaction has been committed.
+    Committed,
+
+    /// The control function has aborted the transaction.
+    Aborted,
+}
+
+/// A sample of a single transaction, that is passed to the profiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileEvent {
+    /// Number of vars in the log of the last attempt.
+    pub var_count: usize,
+
+    /// Time from the start of the transaction until it has ended.
+    pub duration: Duration,
+
+    /// Number of times, that the transaction has been run.
+    pub attempts: usize,
+
+    /// How the transaction has ended.
+    pub outcome: ProfileOutcome,
+}
+
+/// The current profiler.
+static PROFILER: Callback<ProfileEvent> = Callback::new();
+
+/// Set a function, that receives a `ProfileEvent` for every completed transaction.
+///
+/// The profiler runs on the thread of the transaction after it has ended, so it
+/// should be fast. Typically it samples the events or aggregates them into a
+/// histogram, which is exported elsewhere.
+/// It replaces the previous profiler.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// fn profile(event: ProfileEvent) {
+///     if event.attempts > 10 {
+///         eprintln!("slow transaction: {:?}", event);
+///     }
+/// }
+///
+/// set_profiler(profile);
+/// atomically(|_| Ok(()));
+/// clear_profiler();
+/// ```
+pub fn set_profiler(profiler: fn(ProfileEvent)) {
+    PROFILER.set(Some(profiler));
+}
+
+/// Remove the profiler.
+pub fn clear_profiler() {
+    PROFILER.set(None);
+}
+
+/// Get the current profiler.
+pub(crate) fn profiler() -> Option<fn(ProfileEvent)> {
+    PROFILER.get()
+}
+
+/// A change of the blocking state of a transaction, that is passed to the wait observer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitEvent {
+    /// The thread of the transaction.
+    pub thread: ThreadId,
+
+    /// The vars, that the transaction waits for, ordered by `VarId`.
+    /// It is empty, when the transaction stops waiting.
+    pub vars: Vec<VarId>,
+
+    /// `true`, when the transaction starts to wait in `retry`,
+    /// and `false`, when it stops waiting.
+    pub blocked: bool,
+}
+
+/// The current wait observer.
+static WAIT_OBSERVER: Callback<WaitEvent> = Callback::new();
+
+/// Set a function, that is called whenever a transaction starts or stops to wait
+/// for a change in `retry`.
+///
+/// This is meant for debugging deadlocks and livelocks. The observer can
+/// store the events by thread, so that a monitoring thread can report, which
+/// threads are blocked on which vars. It runs on the thread of the transaction.
+/// It replaces the previous observer.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// fn observe(event: WaitEvent) {
+///     if event.blocked {
+///         eprintln!("{:?} waits on {:?}", event.thread, event.vars);
+///     }
+/// }
+///
+/// set_wait_observer(observe);
+/// clear_wait_observer();
+/// ```
+pub fn set_wait_observer(observer: fn(WaitEvent)) {
+    WAIT_OBSERVER.set(Some(observer));
+}
+
+/// Remove the wait observer.
+pub fn clear_wait_observer() {
+    WAIT_OBSERVER.set(None);
+}
+
+/// Get the current wait observer.
+pub(crate) fn wait_observer() -> Option<fn(WaitEvent)> {
+    WAIT_OBSERVER.get()
+}
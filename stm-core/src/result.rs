@@ -9,6 +9,19 @@ pub enum StmError {
     ///
     /// It may block until at least one read variable has changed.
     Retry,
+
+    /// The transaction is still blocked in `retry`.
+    ///
+    /// It is only passed to the control function of a transaction on every
+    /// wakeup without a change of a read var and periodically.
+    /// It is never returned by an operation inside of a transaction.
+    Blocked,
+
+    /// The transaction has been given up, because it needed too many attempts.
+    ///
+    /// It is only returned by `with_retry_limit` and never by an operation
+    /// inside of a transaction.
+    Abandoned,
 }
 
 /// `StmResult` is a result of a single step of a STM calculation.
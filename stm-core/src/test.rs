@@ -13,7 +13,7 @@ use std::sync::mpsc::channel;
 /// If the function does not terminate, it keeps a thread alive forever,
 /// so don't run too many test (preferable just one) in sequence.
 pub fn terminates<F>(duration_ms: u64, f: F) -> bool 
-where F: Send + FnOnce() -> () + 'static,
+where F: Send + FnOnce() + 'static,
 {
     terminates_async(duration_ms, f, || {})
 }
@@ -24,8 +24,8 @@ where F: Send + FnOnce() -> () + 'static,
 /// If the function does not terminate, it keeps a thread alive forever,
 /// so don't run too many test (preferable just one) in sequence.
 pub fn terminates_async<F, G>(duration_ms: u64, f: F, g: G) -> bool 
-where F: Send + FnOnce() -> () + 'static,
-      G: FnOnce() -> ()
+where F: Send + FnOnce() + 'static,
+      G: FnOnce()
 {
     async(duration_ms, f, g).is_some()
 }
@@ -39,7 +39,7 @@ where F: Send + FnOnce() -> () + 'static,
 /// so don't run too many test (preferable just one) in sequence.
 pub fn async<T, F, G>(duration_ms: u64, f: F, g: G) -> Option<T>
 where F: Send + FnOnce() -> T + 'static,
-      G: FnOnce() -> (),
+      G: FnOnce(),
       T: Send + 'static,
 {
     let (tx, rx) = channel();
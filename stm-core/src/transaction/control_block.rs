@@ -8,6 +8,9 @@
 
 use std::thread::{self, Thread};
 use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(test)]
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
 
 #[cfg(test)]
 use super::super::test::{terminates, terminates_async};
@@ -26,16 +29,21 @@ pub struct ControlBlock {
     /// Atomic bool stores if the thread has been blocked yet. 
     /// Make sure, that park is repeated if no change has happened.
     blocked: AtomicBool,
+
+    /// Number of calls to `set_changed`.
+    #[cfg(test)]
+    pub woken: AtomicUsize,
 }
 
 impl ControlBlock {
-    #[cfg_attr(feature = "cargo-clippy", allow(new_without_default_derive))]
-
     /// Create a new StmControlBlock.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> ControlBlock {
         ControlBlock {
             thread: thread::current(),
             blocked: AtomicBool::new(true),
+            #[cfg(test)]
+            woken: AtomicUsize::new(0),
         }
     }
 
@@ -43,6 +51,9 @@ impl ControlBlock {
     ///
     /// Need to be called from outside of STM.
     pub fn set_changed(&self) {
+        #[cfg(test)]
+        self.woken.fetch_add(1, Ordering::SeqCst);
+
         // Only wakeup once.
         if self.blocked.swap(false, Ordering::SeqCst) {
             // wake thread
@@ -60,6 +71,19 @@ impl ControlBlock {
             thread::park();
         }
     }
+
+    /// Block until one variable has changed, the thread has been woken
+    /// otherwise or `timeout` has passed.
+    ///
+    /// Return `true` if a variable has changed.
+    ///
+    /// `wait_timeout` needs to be called by the STM instance itself.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        if self.blocked.load(Ordering::SeqCst) {
+            thread::park_timeout(timeout);
+        }
+        !self.blocked.load(Ordering::SeqCst)
+    }
 }
 
 
@@ -105,6 +129,16 @@ mod test {
     }
 
 
+    /// `wait_timeout` returns after the timeout without a change.
+    #[test]
+    fn wait_timeout_unchanged() {
+        let ctrl = ControlBlock::new();
+        assert!(!ctrl.wait_timeout(Duration::from_millis(10)));
+
+        ctrl.set_changed();
+        assert!(ctrl.wait_timeout(Duration::from_millis(10)));
+    }
+
     /// Perform a wakeup from another thread.
     #[test]
     fn wait_threaded_wakeup() {
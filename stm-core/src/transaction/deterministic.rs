@@ -1,12 +1,15 @@
 use crate::result::*;
-use crate::transaction::tx::{Transaction, TransactionControl, TransactionGuard};
-use crate::tvar::TVar;
-use transaction::{Tx, TxBase};
+use crate::transaction::tx::{Transaction, TransactionControl, TransactionGuard, CommitOutcome, run_hooks};
+use crate::tvar::{TVar, VarControlBlock};
+use transaction::{Tx, TxBase, TransactionLike, set_last_conflict};
 
 use std::any::Any;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread;
+use std::time::Duration;
 
 type Token = ();
 enum Done {
@@ -24,26 +27,52 @@ enum Done {
 /// In other words, however commits first succeeds.
 ///
 /// Our implementation is based on channels rather than synchronized variables.
-///
-
 struct TxCoordinationState {
     done_rx: Receiver<Done>,
     coordination_tx: Sender<(Receiver<Token>, Sender<Token>)>,
 }
 
+/// Source for the identities of the coordinations.
+static NEXT_COORDINATION_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Time, that a transaction waits for its coordination to be frozen, before it panics.
+#[cfg(not(test))]
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct TxHandle {
+    /// Identity of the coordination, that has registered this handle.
+    dtm: usize,
+
+    /// Set, when the coordination has been frozen.
+    frozen: Arc<AtomicBool>,
     done_tx: Sender<Done>,
     coordination_rx: Receiver<(Receiver<Token>, Sender<Token>)>,
 }
 
+/// The set of deterministic transactions.
+///
+/// Dropping it without `freeze` or `run` freezes it, so that the registered
+/// transactions still run instead of waiting forever.
 pub struct Coordination {
+    /// Identity of the coordination, so that handles can name it in errors.
+    id: usize,
+
+    /// Set, when the coordination has been frozen. It is shared with the handles.
+    frozen: Arc<AtomicBool>,
+
     /// This list essentially defines the order of the transactions.
     txs: Vec<TxCoordinationState>,
 }
 
 impl Coordination {
     pub(crate) fn new() -> Coordination {
-        Coordination { txs: Vec::new() }
+        Coordination {
+            id: NEXT_COORDINATION_ID.fetch_add(1, Ordering::Relaxed),
+            frozen: Arc::new(AtomicBool::new(false)),
+            txs: Vec::new(),
+        }
     }
 
     pub fn register(&mut self) -> TxHandle {
@@ -54,6 +83,8 @@ impl Coordination {
             coordination_tx,
         });
         TxHandle {
+            dtm: self.id,
+            frozen: self.frozen.clone(),
             done_tx,
             coordination_rx,
         }
@@ -83,7 +114,10 @@ impl Coordination {
     }
 
     pub(crate) fn freeze(&mut self) {
+        self.frozen.store(true, Ordering::SeqCst);
         let mut coord = Coordination {
+            id: self.id,
+            frozen: self.frozen.clone(),
             txs: self.txs.drain(..).collect(),
         };
         let _t = thread::spawn(move || coord.coordinate());
@@ -92,8 +126,18 @@ impl Coordination {
         // threads are done.
     }
 
+    /// Freeze the set of transactions and coordinate them on the calling thread.
+    ///
+    /// Unlike `freeze`, which coordinates in the background, `run` blocks until
+    /// all registered transactions have committed. That makes it the join point
+    /// for the whole set.
+    pub fn run(mut self) {
+        self.frozen.store(true, Ordering::SeqCst);
+        self.coordinate();
+    }
+
     fn coordinate(&mut self) {
-        while self.txs.len() > 0 {
+        while !self.txs.is_empty() {
             let (first_tx, last_rx) = self.assign_channels();
 
             // propagate the token through the batch
@@ -124,18 +168,70 @@ impl Coordination {
     }
 }
 
+impl TxHandle {
+    /// Wait for the channels of the next round.
+    ///
+    /// A coordination, that is gone, or one, that is not frozen in time, would never
+    /// schedule the transaction, so report it instead of hanging.
+    fn next_round(&self) -> (Receiver<Token>, Sender<Token>) {
+        match self.coordination_rx.recv_timeout(FREEZE_TIMEOUT) {
+            Ok(channels) => channels,
+            Err(RecvTimeoutError::Disconnected) => self.coordination_lost(),
+            Err(RecvTimeoutError::Timeout) if !self.frozen.load(Ordering::SeqCst) =>
+                panic!("DTMHandle: DTM #{} has not been frozen within {:?}", self.dtm, FREEZE_TIMEOUT),
+            // The transactions before this one are still running.
+            Err(RecvTimeoutError::Timeout) => match self.coordination_rx.recv() {
+                Ok(channels) => channels,
+                Err(_) => self.coordination_lost(),
+            },
+        }
+    }
+
+    /// Panic, because the coordination of this handle is gone.
+    fn coordination_lost(&self) -> ! {
+        panic!("DTMHandle: the coordination of DTM #{} is gone", self.dtm)
+    }
+}
+
+impl Drop for Coordination {
+    fn drop(&mut self) {
+        // The coordinator itself drops its set, when it panics.
+        if !self.txs.is_empty() && !thread::panicking() {
+            self.freeze();
+        }
+    }
+}
+
 pub struct Deterministic {
     handle: TxHandle,
     tx: Transaction,
+
+    /// Call `control` with `StmError::Failure` on a failed commit
+    /// instead of retrying silently.
+    report_conflicts: bool,
 }
 
 impl Deterministic {
     pub fn new(handle: TxHandle) -> Deterministic {
+        // The branch, that `or` takes, must not depend on the timing
+        // of the transactions before.
+        let mut tx = Transaction::new();
+        tx.set_validate_obsolete(true);
+
         Deterministic {
             handle,
-            tx: Transaction::new(),
+            tx,
+            report_conflicts: false,
         }
     }
+
+    /// Let `control` decide on failed commits as well.
+    ///
+    /// This is not part of the contract of `Tx::with_control`,
+    /// but the runners of this crate use it to count conflicts.
+    pub(crate) fn set_report_conflicts(&mut self, report: bool) {
+        self.report_conflicts = report;
+    }
 }
 
 impl TxBase for Deterministic {
@@ -156,6 +252,16 @@ impl TxBase for Deterministic {
     }
 }
 
+impl TransactionLike for Deterministic {
+    fn read_any(&mut self, var: &Arc<VarControlBlock>) -> StmResult<Arc<dyn Any + Send + Sync>> {
+        self.tx.read_any(var)
+    }
+
+    fn write_any(&mut self, var: &Arc<VarControlBlock>, value: Arc<dyn Any + Send + Sync>) -> StmResult<()> {
+        self.tx.write_any(var, value)
+    }
+}
+
 impl Tx for Deterministic {
     fn with_control<T, F, C>(&mut self, mut control: C, f: F) -> Option<T>
     where
@@ -164,16 +270,12 @@ impl Tx for Deterministic {
     {
         // create a log guard for initializing and cleaning up
         // the log
-        let _guard = TransactionGuard::new();
+        let guard = TransactionGuard::new();
 
         // loop until success
         loop {
             // Constraint #2: finish prev round before starting the next
-            let (token_rx, token_tx) = self
-                .handle
-                .coordination_rx
-                .recv()
-                .expect("Invariant broken: could not receive token channels.");
+            let (token_rx, token_tx) = self.handle.next_round();
 
             // run the computation
             let result = f(&mut self.tx);
@@ -183,20 +285,21 @@ impl Tx for Deterministic {
             // - All other transactions started already
             match token_rx.recv() {
                 Err(_) => {
-                    // we failed in the execution: tear down
-                    return None;
+                    // A transaction before us failed in the execution.
+                    self.handle.coordination_lost()
                 }
                 Ok(token) => {
                     let decision = match result {
                         // on success exit loop
-                        Ok(t) => {
-                            if self.tx.commit() {
-                                (TransactionControl::Abort, Some(t))
-                            } else {
-                                // retry
-                                (TransactionControl::Retry, None)
+                        Ok(t) => match self.tx.commit_outcome() {
+                            CommitOutcome::Committed => (TransactionControl::Abort, Some(t)),
+                            // conflict: let the runner decide, if it asked for it
+                            CommitOutcome::Conflict(info) if self.report_conflicts => {
+                                set_last_conflict(info);
+                                (control(StmError::Failure), None)
                             }
-                        }
+                            CommitOutcome::Conflict(_) => (TransactionControl::Retry, None),
+                        },
                         Err(e) => (control(e), None),
                     };
 
@@ -212,6 +315,15 @@ impl Tx for Deterministic {
                                 .done_tx
                                 .send(Done::Completed)
                                 .expect("Invariant broken: sending done signal failed.");
+
+                            // Run the hooks outside of the transaction.
+                            let hooks = if r.is_some() {
+                                self.tx.take_hooks()
+                            } else {
+                                Vec::new()
+                            };
+                            drop(guard);
+                            run_hooks(hooks);
                             return r;
                         }
                         (TransactionControl::Retry, _) => {
@@ -228,3 +340,41 @@ impl Tx for Deterministic {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{dtm, det_atomically, freeze};
+    use crate::result::StmResult;
+
+    /// A handle, whose coordination has been torn down by a panicking
+    /// transaction before, panics instead of waiting forever.
+    #[test]
+    #[should_panic(expected = "is gone")]
+    fn stale_handle() {
+        let mut dtm = dtm();
+        let handle1 = dtm.register();
+        let handle2 = dtm.register();
+        freeze(dtm);
+
+        let t1 = thread::spawn(move || det_atomically(handle1, |_| -> StmResult<()> { panic!("tx1") }));
+        assert!(t1.join().is_err());
+
+        det_atomically(handle2, |_| Ok(()));
+    }
+
+    /// A handle of a DTM, that is never frozen, panics and names the DTM.
+    #[test]
+    fn unfrozen_handle() {
+        let mut dtm = dtm();
+        let handle = dtm.register();
+
+        let t = thread::spawn(move || det_atomically(handle, |_| Ok(())));
+
+        let err = t.join().unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains(&format!("DTM #{} has not been frozen", dtm.id)), "{}", msg);
+        drop(dtm);
+    }
+}
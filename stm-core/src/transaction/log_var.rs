@@ -5,6 +5,27 @@ pub type ArcAny = Arc<dyn Any + Send + Sync>;
 
 /// `LogVar` is used by `Log` to track which `Var` was either read or written or both.
 /// Depending on the type, STM has to write, ensure consistency or block on this value.
+///
+/// The obsolete states are produced by `Transaction::or`. When the first branch
+/// calls `retry`, its log is discarded, but every var it has read stays in the log
+/// as obsolete, so that the transaction also blocks on them.
+/// Obsolete reads are never checked for consistency on commit, because the
+/// result of the transaction does not depend on them.
+///
+/// The transitions are:
+///
+/// | state                   | `read`                  | `write(n)`              | `obsolete`        |
+/// |-------------------------|-------------------------|-------------------------|-------------------|
+/// | `Read(r)`               | `Read(r)`               | `ReadWrite(r, n)`       | `ReadObsolete(r)` |
+/// | `Write(w)`              | `Write(w)`              | `Write(n)`              | dropped           |
+/// | `ReadWrite(r, w)`       | `ReadWrite(r, w)`       | `ReadWrite(r, n)`       | `ReadObsolete(r)` |
+/// | `ReadObsolete(r)`       | `Read(r)`               | `ReadObsoleteWrite(r, n)` | `ReadObsolete(r)` |
+/// | `ReadObsoleteWrite(r, w)` | `ReadObsoleteWrite(r, w)` | `ReadObsoleteWrite(r, n)` | `ReadObsolete(r)` |
+///
+/// Reading a written var returns the written value and therefore does not
+/// add a dependency on the original value.
+/// All states except `Write` are part of the blocking set.
+/// Only `Read` and `ReadWrite` are checked for consistency.
 #[derive(Clone)]
 pub enum LogVar {
     /// Var has been read.
@@ -43,8 +64,41 @@ pub enum LogVar {
     // and don't need a representation in the log.
 }
 
+/// The state of a var in the log of a transaction, without its values.
+///
+/// This mirrors the internal states of the log for introspection and testing.
+/// See `Transaction::log_entries`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogEntryKind {
+    /// The var has been read.
+    Read,
+
+    /// The var has been written without reading it first.
+    Write,
+
+    /// The var has been read and then written.
+    ReadWrite,
+
+    /// The var has been read in a discarded branch of `or`.
+    /// It is only used for blocking.
+    ReadObsolete,
+
+    /// The var has been read in a discarded branch of `or` and written afterwards.
+    ReadObsoleteWrite,
+}
 
 impl LogVar {
+    /// Get the kind of the entry.
+    pub fn kind(&self) -> LogEntryKind {
+        match *self {
+            LogVar::Read(_)                 => LogEntryKind::Read,
+            LogVar::Write(_)                => LogEntryKind::Write,
+            LogVar::ReadWrite(_, _)         => LogEntryKind::ReadWrite,
+            LogVar::ReadObsolete(_)         => LogEntryKind::ReadObsolete,
+            LogVar::ReadObsoleteWrite(_, _) => LogEntryKind::ReadObsoleteWrite,
+        }
+    }
+
     /// Read a value and potentially upgrade the state.
     pub fn read(&mut self) -> ArcAny {
         use self::LogVar::*;
@@ -55,19 +109,16 @@ impl LogVar {
         // but we can not fully avoid it, although these cases happen rarely.
         let this;
         let val;
-        match &*self {
-            // Use last read value or get written one
-            &Read(ref v) | &Write(ref v) | &ReadWrite(_,ref v) => { 
+        match self {
+            // Use last read value or get written one.
+            // A written value does not depend on the original, so 
+            // `ReadObsoleteWrite` stays obsolete.
+            Read(v) | Write(v) | ReadWrite(_, v) | ReadObsoleteWrite(_, v) => { 
                 return v.clone();
             }
 
-            &ReadObsoleteWrite(ref w, ref v) => {
-                val = v.clone();
-                this = ReadWrite(w.clone(), v.clone());
-            }
-
             // Upgrade to a real Read
-            &ReadObsolete(ref v)           => {
+            ReadObsolete(v)           => {
                 val = v.clone();
                 this = Read(v.clone());
             }
@@ -102,7 +153,52 @@ impl LogVar {
     {
         use self::LogVar::*;
         self.into_read_value()
-            .map(|a| ReadObsolete(a))
+            .map(ReadObsolete)
+    }
+
+    /// Turn an obsolete read into a real one, that is checked for consistency.
+    pub fn unobsolete(self) -> LogVar {
+        use self::LogVar::*;
+        match self {
+            ReadObsolete(r)             => Read(r),
+            ReadObsoleteWrite(r, w)     => ReadWrite(r, w),
+            this                        => this,
+        }
+    }
+
+    /// Add the obsolete version of the same var from a discarded branch.
+    ///
+    /// A var, that has only been written, has no read value yet, so it
+    /// remembers the obsolete read in order to block on it.
+    /// All other states already block on the var.
+    pub fn add_obsolete(&mut self, obsolete: LogVar) {
+        use self::LogVar::*;
+
+        if let Write(w) = self {
+            if let Some(r) = obsolete.into_read_value() {
+                *self = ReadObsoleteWrite(r, w.clone());
+            }
+        }
+    }
+
+    /// Get the written value of a Var and ignore the read one.
+    pub fn into_write_value(self) -> Option<ArcAny> {
+        use self::LogVar::*;
+        match self {
+            Write(w) | ReadWrite(_, w) | ReadObsoleteWrite(_, w)
+                => Some(w),
+            Read(_) | ReadObsolete(_)   => None,
+        }
+    }
+
+    /// Get a mutable reference to the written value of a Var.
+    pub fn write_value_mut(&mut self) -> Option<&mut ArcAny> {
+        use self::LogVar::*;
+        match *self {
+            Write(ref mut w) | ReadWrite(_, ref mut w) | ReadObsoleteWrite(_, ref mut w)
+                => Some(w),
+            Read(_) | ReadObsolete(_)   => None,
+        }
     }
 
     /// Ignore all Write... and get the original value of a Var.
@@ -123,3 +219,102 @@ fn test_write_obsolete_ignore() {
     assert!(t.is_none());
 }
 
+#[cfg(test)]
+fn value(v: &ArcAny) -> i32 {
+    *v.downcast_ref::<i32>().unwrap()
+}
+
+/// Check the state and the values of a `LogVar`.
+#[cfg(test)]
+fn assert_state(var: &LogVar, read: Option<i32>, written: Option<i32>, obsolete: bool) {
+    use self::LogVar::*;
+    let state = match var {
+        Read(r)                 => (Some(value(r)), None, false),
+        Write(w)                => (None, Some(value(w)), false),
+        ReadWrite(r, w)         => (Some(value(r)), Some(value(w)), false),
+        ReadObsolete(r)         => (Some(value(r)), None, true),
+        ReadObsoleteWrite(r, w) => (Some(value(r)), Some(value(w)), true),
+    };
+    assert_eq!(state, (read, written, obsolete));
+}
+
+#[test]
+fn test_read_transitions() {
+    use self::LogVar::*;
+
+    let mut t = Read(Arc::new(1));
+    assert_eq!(value(&t.read()), 1);
+    assert_state(&t, Some(1), None, false);
+
+    let mut t = Write(Arc::new(2));
+    assert_eq!(value(&t.read()), 2);
+    assert_state(&t, None, Some(2), false);
+
+    let mut t = ReadWrite(Arc::new(1), Arc::new(2));
+    assert_eq!(value(&t.read()), 2);
+    assert_state(&t, Some(1), Some(2), false);
+
+    // Reading an obsolete var makes it a real dependency.
+    let mut t = ReadObsolete(Arc::new(1));
+    assert_eq!(value(&t.read()), 1);
+    assert_state(&t, Some(1), None, false);
+
+    // Reading the own write does not depend on the obsolete read.
+    let mut t = ReadObsoleteWrite(Arc::new(1), Arc::new(2));
+    assert_eq!(value(&t.read()), 2);
+    assert_state(&t, Some(1), Some(2), true);
+}
+
+#[test]
+fn test_write_transitions() {
+    use self::LogVar::*;
+
+    let mut t = Read(Arc::new(1));
+    t.write(Arc::new(3));
+    assert_state(&t, Some(1), Some(3), false);
+
+    let mut t = Write(Arc::new(2));
+    t.write(Arc::new(3));
+    assert_state(&t, None, Some(3), false);
+
+    let mut t = ReadWrite(Arc::new(1), Arc::new(2));
+    t.write(Arc::new(3));
+    assert_state(&t, Some(1), Some(3), false);
+
+    let mut t = ReadObsolete(Arc::new(1));
+    t.write(Arc::new(3));
+    assert_state(&t, Some(1), Some(3), true);
+
+    let mut t = ReadObsoleteWrite(Arc::new(1), Arc::new(2));
+    t.write(Arc::new(3));
+    assert_state(&t, Some(1), Some(3), true);
+}
+
+#[test]
+fn test_add_obsolete() {
+    use self::LogVar::*;
+
+    let mut t = Write(Arc::new(2));
+    t.add_obsolete(ReadObsolete(Arc::new(1)));
+    assert_state(&t, Some(1), Some(2), true);
+
+    // Existing reads are kept.
+    let mut t = Read(Arc::new(1));
+    t.add_obsolete(ReadObsolete(Arc::new(0)));
+    assert_state(&t, Some(1), None, false);
+
+    let mut t = ReadObsolete(Arc::new(1));
+    t.add_obsolete(ReadObsolete(Arc::new(0)));
+    assert_state(&t, Some(1), None, true);
+}
+
+#[test]
+fn test_obsolete_transitions() {
+    use self::LogVar::*;
+
+    assert_state(&Read(Arc::new(1)).obsolete().unwrap(), Some(1), None, true);
+    assert_state(&ReadWrite(Arc::new(1), Arc::new(2)).obsolete().unwrap(), Some(1), None, true);
+    assert_state(&ReadObsolete(Arc::new(1)).obsolete().unwrap(), Some(1), None, true);
+    assert_state(&ReadObsoleteWrite(Arc::new(1), Arc::new(2)).obsolete().unwrap(),
+        Some(1), None, true);
+}
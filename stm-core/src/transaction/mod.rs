@@ -12,12 +12,18 @@ mod tx;
 mod deterministic;
 mod nondeterministic;
 
-pub use self::tx::{Transaction, TransactionControl};
+pub use self::tx::{Transaction, TransactionControl, CommitMode, CommitOutcome, ConflictInfo, CommitHook};
+pub use self::tx::in_transaction;
 pub use self::deterministic::Coordination as DTM;
 pub use self::deterministic::TxHandle as DTMHandle;
 
 use std::any::Any;
-use crate::tvar::TVar;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::tvar::{TVar, VarControlBlock};
+use crate::profile::{profiler, ProfileEvent, ProfileOutcome};
 use super::result::*;
 
 use self::deterministic::Deterministic;
@@ -56,6 +62,46 @@ pub trait TxBase {
               F2: Fn(&mut Transaction) -> StmResult<T>;
 }
 
+/// An object-safe interface to a running transaction.
+///
+/// `TxBase` and `Tx` have generic methods and can therefore not be used as trait objects.
+/// `TransactionLike` erases the type of the values instead, so that libraries can accept
+/// a `&mut dyn TransactionLike` and work with every kind of transaction without being
+/// monomorphized for each of them.
+///
+/// The typed `read` and `write` methods on `dyn TransactionLike` should be preferred.
+/// They restore type safety, because a `TVar<T>` always stores a `T`.
+/// `read_any` and `write_any` bypass this. Writing a value of the wrong type
+/// into a var does not cause undefined behavior, but every later typed read panics.
+pub trait TransactionLike {
+    /// Read a var and return its type-erased value.
+    fn read_any(&mut self, var: &Arc<VarControlBlock>) -> StmResult<Arc<dyn Any + Send + Sync>>;
+
+    /// Write a type-erased value to a var.
+    ///
+    /// The value must have the type of the var.
+    fn write_any(&mut self, var: &Arc<VarControlBlock>, value: Arc<dyn Any + Send + Sync>) -> StmResult<()>;
+}
+
+impl dyn TransactionLike + '_ {
+    /// Read a variable and return the value.
+    ///
+    /// It is equivalent to `Transaction::read`.
+    pub fn read<T: Send + Sync + Any + Clone>(&mut self, var: &TVar<T>) -> StmResult<T> {
+        let value = self.read_any(var.control_block())?;
+        Ok(value.downcast_ref::<T>()
+            .expect("wrong type in Var<T>")
+            .clone())
+    }
+
+    /// Write a variable.
+    ///
+    /// It is equivalent to `Transaction::write`.
+    pub fn write<T: Any + Send + Sync + Clone>(&mut self, var: &TVar<T>, value: T) -> StmResult<()> {
+        self.write_any(var.control_block(), Arc::new(value))
+    }
+}
+
 pub trait Tx: TxBase {
     /// Run a function with a transaction.
     ///
@@ -63,13 +109,16 @@ pub trait Tx: TxBase {
     /// can steer the control flow and possible terminate early.
     ///
     /// `control` can react to counters, timeouts or external inputs.
+    /// A commit, that failed because a read var has changed in between,
+    /// is retried without calling `control`.
+    /// While the transaction is blocked in `retry`, `control` is called with
+    /// `StmError::Blocked` on every wakeup without a change of a var and periodically.
     ///
     /// It allows the user to fall back to another strategy, like a global lock
     /// in the case of too much contention.
     ///
-    /// Please not, that the transaction may still infinitely wait for changes when `retry` is
+    /// Please note, that the transaction may still infinitely wait for changes when `retry` is
     /// called and `control` does not abort.
-    /// If you need a timeout, another thread should signal this through a TVar.
     fn with_control<T, F, C>(&mut self, control: C, f: F) -> Option<T>
     where F: Fn(&mut Transaction) -> StmResult<T>,
           C: FnMut(StmError) -> TransactionControl;
@@ -82,19 +131,220 @@ pub trait Tx: TxBase {
 pub fn with<T, F>(v: TxVersion, f: F) -> T
 where F: Fn(&mut Transaction) -> StmResult<T>,
 {
-    let r  =
-        match v {
-            TxVersion::Deterministic(handle) => 
-                Deterministic::new(handle).with_control(|_| TransactionControl::Retry, f),
-            TxVersion::NonDeterministic => 
-                NonDeterministic::new().with_control(|_| TransactionControl::Retry, f)
-        };
-    match r {
+    match with_control(v, |_| TransactionControl::Retry, f) {
         Some(t) => t,
         None    => unreachable!()
     }
 }
 
+/// Number of transactions, that have been started in this process.
+static TRANSACTIONS_STARTED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of transactions, that have been committed in this process.
+static TRANSACTIONS_COMMITTED: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of transactions, that have been started in this process.
+///
+/// Every call to `atomically` or a similar function counts once,
+/// no matter how often it has to rerun.
+pub fn transactions_started() -> u64 {
+    TRANSACTIONS_STARTED.load(Ordering::Relaxed)
+}
+
+/// Get the number of transactions, that have been committed in this process.
+///
+/// Together with `transactions_started` this gives the number of transactions,
+/// that have been aborted or are still running.
+pub fn transactions_committed() -> u64 {
+    TRANSACTIONS_COMMITTED.load(Ordering::Relaxed)
+}
+
+/// Statistics of the transactions, that have run on a thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// Number of committed transactions.
+    pub commits: u64,
+
+    /// Number of transactions, that have been aborted by their control function.
+    pub aborts: u64,
+
+    /// Number of calls to `retry`, that reached the top of a transaction.
+    pub retries: u64,
+
+    /// Number of failed commits because of conflicts with other transactions.
+    pub conflicts: u64,
+
+    /// Time spent in transactions, including the time blocked in `retry`.
+    pub time: Duration,
+}
+
+thread_local!(static THREAD_STATS: Cell<ThreadStats> = const { Cell::new(ThreadStats {
+    commits: 0,
+    aborts: 0,
+    retries: 0,
+    conflicts: 0,
+    time: Duration::ZERO,
+}) });
+
+/// Update the statistics of the current thread.
+fn update_thread_stats<F: FnOnce(&mut ThreadStats)>(f: F) {
+    THREAD_STATS.with(|stats| {
+        let mut s = stats.get();
+        f(&mut s);
+        stats.set(s);
+    });
+}
+
+thread_local!(static LAST_CONFLICT: Cell<Option<ConflictInfo>> = const { Cell::new(None) });
+
+/// Remember the conflict of the last failed commit on the current thread.
+fn set_last_conflict(info: ConflictInfo) {
+    LAST_CONFLICT.with(|c| c.set(Some(info)));
+}
+
+/// Get the conflict of the last failed commit on the current thread.
+///
+/// It is only recorded, where `control` is called on conflicts.
+#[cfg(feature = "stats")]
+pub(crate) fn last_conflict() -> Option<ConflictInfo> {
+    LAST_CONFLICT.with(|c| c.get())
+}
+
+/// Get the statistics of the transactions, that have run on the current thread.
+///
+/// The counters are thread-local, so they can be updated without contention
+/// and attribute the cost of STM to single worker threads.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// reset_thread_stats();
+/// atomically(|_| Ok(()));
+/// assert_eq!(thread_stats().commits, 1);
+/// ```
+pub fn thread_stats() -> ThreadStats {
+    THREAD_STATS.with(|stats| stats.get())
+}
+
+/// Reset the statistics of the current thread to zero.
+pub fn reset_thread_stats() {
+    THREAD_STATS.with(|stats| stats.set(ThreadStats::default()));
+}
+
+/// Run a function with a transaction and a control function.
+///
+/// `control` is called on every failed attempt. That includes a call to `retry`
+/// as well as a failed commit, which is reported as `StmError::Failure`.
+/// While blocked in `retry`, it is called with `StmError::Blocked`.
+/// Return `None` if `control` aborted the transaction.
+pub fn with_control<T, F, C>(v: TxVersion, control: C, f: F) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>,
+      C: FnMut(StmError) -> TransactionControl,
+{
+    with_control_until(v, None, control, f)
+}
+
+/// Run a function with a transaction and a control function like `with_control`.
+///
+/// A transaction, that is blocked in `retry`, calls `control` at `deadline` at the latest,
+/// so that `control` can enforce a timeout precisely.
+pub fn with_control_until<T, F, C>(v: TxVersion, deadline: Option<Instant>, control: C, f: F) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>,
+      C: FnMut(StmError) -> TransactionControl,
+{
+    TRANSACTIONS_STARTED.fetch_add(1, Ordering::Relaxed);
+    let start = Instant::now();
+
+    // `control` is called again while blocked in `retry`, so the retries
+    // are counted, where `f` returns them.
+    let f = |tx: &mut Transaction| {
+        let r = f(tx);
+        if let Err(StmError::Retry) = r {
+            update_thread_stats(|s| s.retries += 1);
+        }
+        r
+    };
+    let mut control = control;
+    let control = |e| {
+        if e == StmError::Failure {
+            update_thread_stats(|s| s.conflicts += 1);
+        }
+        control(e)
+    };
+
+    let result = match profiler() {
+        Some(profiler)  => run_profiled(v, deadline, control, f, profiler),
+        None            => run(v, deadline, control, f),
+    };
+
+    if result.is_some() {
+        TRANSACTIONS_COMMITTED.fetch_add(1, Ordering::Relaxed);
+    }
+    let committed = result.is_some();
+    update_thread_stats(|s| {
+        if committed {
+            s.commits += 1;
+        } else {
+            s.aborts += 1;
+        }
+        s.time += start.elapsed();
+    });
+    result
+}
+
+/// Run a function with the transaction of the version `v`.
+///
+/// Only a non-deterministic transaction blocks in `retry` and uses the deadline.
+fn run<T, F, C>(v: TxVersion, deadline: Option<Instant>, control: C, f: F) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>,
+      C: FnMut(StmError) -> TransactionControl,
+{
+    match v {
+        TxVersion::Deterministic(handle) => {
+            let mut tx = Deterministic::new(handle);
+            tx.set_report_conflicts(true);
+            tx.with_control(control, f)
+        }
+        TxVersion::NonDeterministic => {
+            let mut tx = NonDeterministic::new();
+            tx.set_report_conflicts(true);
+            tx.set_deadline(deadline);
+            tx.with_control(control, f)
+        }
+    }
+}
+
+/// Run a function with a transaction and report it to `profiler`.
+fn run_profiled<T, F, C>(v: TxVersion, deadline: Option<Instant>, control: C, f: F,
+                         profiler: fn(ProfileEvent)) -> Option<T>
+where F: Fn(&mut Transaction) -> StmResult<T>,
+      C: FnMut(StmError) -> TransactionControl,
+{
+    let start = Instant::now();
+    let attempts = Cell::new(0);
+    let var_count = Cell::new(0);
+
+    let result = run(v, deadline, control, |tx| {
+        attempts.set(attempts.get() + 1);
+        let r = f(tx);
+        var_count.set(tx.var_count());
+        r
+    });
+
+    profiler(ProfileEvent {
+        var_count: var_count.get(),
+        duration: start.elapsed(),
+        attempts: attempts.get(),
+        outcome: if result.is_some() {
+            ProfileOutcome::Committed
+        } else {
+            ProfileOutcome::Aborted
+        },
+    });
+    result
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -105,6 +355,53 @@ mod test {
         assert_eq!(x, 42);
     }
 
+    /// Other tests run in parallel, so the counters may grow even more.
+    #[test]
+    fn transaction_counters() {
+        let started = transactions_started();
+        let committed = transactions_committed();
+
+        for _ in 0..10 {
+            with(TxVersion::NonDeterministic, |_| Ok(()));
+        }
+        let aborted = with_control(TxVersion::NonDeterministic,
+            |_| TransactionControl::Abort, |_| Err::<(), _>(StmError::Retry));
+        assert_eq!(aborted, None);
+
+        assert!(transactions_started() - started >= 11);
+        assert!(transactions_committed() - committed >= 10);
+    }
+
+    /// Every thread only sees the statistics of its own transactions.
+    #[test]
+    fn thread_stats_per_thread() {
+        use std::thread;
+
+        let var = TVar::new(0);
+
+        let worker = |n: u64| {
+            let var = var.clone();
+            thread::spawn(move || {
+                for _ in 0..n {
+                    with(TxVersion::NonDeterministic, |tx| var.modify(tx, |x| x + 1));
+                }
+                with_control(TxVersion::NonDeterministic,
+                    |_| TransactionControl::Abort, |_| Err::<(), _>(StmError::Retry));
+                thread_stats()
+            })
+        };
+
+        let a = worker(3).join().unwrap();
+        let b = worker(5).join().unwrap();
+
+        assert_eq!((a.commits, a.aborts, a.retries, a.conflicts), (3, 1, 1, 0));
+        assert_eq!((b.commits, b.aborts, b.retries, b.conflicts), (5, 1, 1, 0));
+        assert!(a.time > Duration::ZERO);
+
+        reset_thread_stats();
+        assert_eq!(thread_stats(), ThreadStats::default());
+    }
+
     #[test]
     fn transaction_read() {
         let read = TVar::new(42);
@@ -131,6 +428,31 @@ mod test {
         assert_eq!(x, Some(42));
     }
 
+    /// A failed commit is retried without asking the control function.
+    #[test]
+    fn transaction_with_control_retries_conflicts() {
+        use std::thread;
+
+        let var = TVar::new(0);
+        let runs = Cell::new(0);
+
+        let x = NonDeterministic::new()
+            .with_control(|_| TransactionControl::Abort, |tx| {
+                runs.set(runs.get() + 1);
+                let x = var.read(tx)?;
+                if runs.get() == 1 {
+                    // Change the read var before the first commit.
+                    let varc = var.clone();
+                    thread::spawn(move || varc.write_atomic(10)).join().unwrap();
+                }
+                var.write(tx, x + 1)?;
+                Ok(x)
+            });
+
+        assert_eq!(x, Some(10));
+        assert_eq!(runs.get(), 2);
+    }
+
     /// Run a transaction with a control function, that always aborts.
     /// The transaction retries infinitely often. The control function will abort this loop.
     #[test]
@@ -143,6 +465,44 @@ mod test {
         assert_eq!(x, None);
     }
 
+    /// Block a transaction in `retry` and wake it up without changing a var.
+    /// The control function sees every wakeup as `StmError::Blocked` and aborts at the third.
+    #[test]
+    fn transaction_with_control_abort_while_blocked() {
+        use std::thread;
+        use std::time::Duration;
+
+        let var = TVar::new(0);
+
+        let t = thread::spawn(move || {
+            let mut calls = 0;
+            let x: Option<i32> = NonDeterministic::new()
+                .with_control(|e| {
+                    if e == StmError::Retry {
+                        return TransactionControl::Retry;
+                    }
+                    assert_eq!(e, StmError::Blocked);
+                    calls += 1;
+                    if calls >= 3 {
+                        TransactionControl::Abort
+                    } else {
+                        TransactionControl::Retry
+                    }
+                }, |tx| {
+                    var.read(tx)?;
+                    Err(StmError::Retry)
+                });
+            (x, calls)
+        });
+
+        // Spurious wakeups.
+        while !t.is_finished() {
+            t.thread().unpark();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(t.join().unwrap(), (None, 3));
+    }
 
     #[test]
     fn transaction_write() {
@@ -182,6 +542,40 @@ mod test {
         assert_eq!(write.read_atomic(), 42);
     }
 
+    /// Use both kinds of runners behind a trait object.
+    #[test]
+    fn transaction_like_object() {
+        let var = TVar::new(1);
+        let mut dtm = DTM::new();
+
+        let runners: Vec<Box<dyn TransactionLike>> = vec![
+            Box::new(NonDeterministic::new()),
+            Box::new(Deterministic::new(dtm.register())),
+        ];
+
+        for mut runner in runners {
+            let x = runner.read(&var).unwrap();
+            runner.write(&var, x + 1).unwrap();
+            assert_eq!(runner.read(&var), Ok(2));
+        }
+
+        // Nothing has been committed.
+        assert_eq!(var.read_atomic(), 1);
+    }
+
+    /// A library function, that works on every kind of transaction.
+    #[test]
+    fn transaction_like_parameter() {
+        fn increment(tx: &mut dyn TransactionLike, var: &TVar<i32>) -> StmResult<()> {
+            let x = tx.read(var)?;
+            tx.write(var, x + 1)
+        }
+
+        let var = TVar::new(41);
+        with(TxVersion::NonDeterministic, |tx| increment(tx, &var));
+        assert_eq!(var.read_atomic(), 42);
+    }
+
     /// Test if nested transactions are correctly detected.
     #[test]
     #[should_panic]
@@ -1,17 +1,85 @@
-use transaction::{TxBase, Tx};
-use crate::transaction::tx::{TransactionGuard, TransactionControl, Transaction};
+use transaction::{TxBase, Tx, TransactionLike, set_last_conflict};
+use crate::transaction::tx::{TransactionGuard, TransactionControl, Transaction, CommitOutcome, run_hooks};
 use crate::result::*;
-use crate::tvar::TVar;
+use crate::tvar::{TVar, VarControlBlock};
 
 use std::any::Any;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// Number of retries in a row with the same written vars, after which
+/// a warning is logged.
+#[cfg(feature = "log")]
+const NON_PRODUCTIVE_RETRIES: usize = 100;
+
+/// `ProgressCheck` detects transactions, that keep retrying
+/// without making progress.
+///
+/// The values in the log are type erased and can't be compared.
+/// Instead a transaction, that writes the same vars on every
+/// retry, is considered to repeat the same work.
+/// This often hints at a guard, that can never be satisfied.
+#[cfg(feature = "log")]
+#[derive(Default)]
+struct ProgressCheck {
+    written: Vec<Arc<VarControlBlock>>,
+    retries: usize,
+}
+
+#[cfg(feature = "log")]
+impl ProgressCheck {
+    /// Register a call to `retry` and warn once, if there were too many.
+    fn retried(&mut self, tx: &Transaction) {
+        let written = tx.written_vars();
+        if written.is_empty() || written != self.written {
+            self.written = written;
+            self.retries = 1;
+            return;
+        }
+
+        self.retries += 1;
+        if self.retries == NON_PRODUCTIVE_RETRIES {
+            warn!("STM: transaction retried {} times while writing the same {} vars. \
+                   It may be stuck on a guard, that is never satisfied.",
+                  self.retries, self.written.len());
+        }
+    }
+}
 
 pub struct NonDeterministic{
-    tx: Transaction
+    tx: Transaction,
+
+    /// Call `control` with `StmError::Failure` on a failed commit
+    /// instead of retrying silently.
+    report_conflicts: bool,
+
+    /// Call `control` at this point in time at the latest, while blocked in `retry`.
+    deadline: Option<Instant>,
 }
 
 impl NonDeterministic {
     pub fn new() -> NonDeterministic {
-        NonDeterministic { tx: Transaction::new() }
+        NonDeterministic {
+            tx: Transaction::new(),
+            report_conflicts: false,
+            deadline: None,
+        }
+    }
+
+    /// Let `control` decide on failed commits as well.
+    ///
+    /// This is not part of the contract of `Tx::with_control`,
+    /// but the runners of this crate use it to count conflicts.
+    pub(crate) fn set_report_conflicts(&mut self, report: bool) {
+        self.report_conflicts = report;
+    }
+
+    /// Let `control` check `deadline` in time, while the transaction is blocked.
+    ///
+    /// Without it `control` is only called periodically.
+    pub(crate) fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
     }
 }
 
@@ -34,6 +102,16 @@ impl TxBase for NonDeterministic {
     }
 }
 
+impl TransactionLike for NonDeterministic {
+    fn read_any(&mut self, var: &Arc<VarControlBlock>) -> StmResult<Arc<dyn Any + Send + Sync>> {
+        self.tx.read_any(var)
+    }
+
+    fn write_any(&mut self, var: &Arc<VarControlBlock>, value: Arc<dyn Any + Send + Sync>) -> StmResult<()> {
+        self.tx.write_any(var, value)
+    }
+}
+
 impl Tx for NonDeterministic {
    fn with_control<T, F, C>(&mut self, mut control: C, f: F) -> Option<T>
     where F: Fn(&mut Transaction) -> StmResult<T>,
@@ -41,7 +119,10 @@ impl Tx for NonDeterministic {
     {
         // create a log guard for initializing and cleaning up
         // the log
-        let _guard = TransactionGuard::new();
+        let guard = TransactionGuard::new();
+
+        #[cfg(feature = "log")]
+        let mut progress = ProgressCheck::default();
 
        //let mut transaction = Transaction::new();
 
@@ -51,9 +132,26 @@ impl Tx for NonDeterministic {
             match f(&mut self.tx) {
                 // on success exit loop
                 Ok(t) => {
-                    if self.tx.commit() {
+                    if let CommitOutcome::Conflict(info) = self.tx.commit_outcome() {
+                        // Let the runner decide on conflicts as well, if it asked for it.
+                        if self.report_conflicts {
+                            set_last_conflict(info);
+                            if let TransactionControl::Abort = control(StmError::Failure) {
+                                return None;
+                            }
+                        }
+                    } else {
+                        // Run the hooks outside of the transaction.
+                        let hooks = self.tx.take_hooks();
+                        drop(guard);
+                        run_hooks(hooks);
                         return Some(t);
                     }
+
+                    // The conflicting transaction may still be running on another
+                    // thread. Give it the chance to finish instead of spinning
+                    // against it.
+                    thread::yield_now();
                 }
 
                 Err(e) => {
@@ -61,6 +159,25 @@ impl Tx for NonDeterministic {
                     if let TransactionControl::Abort = control(e) {
                         return None;
                     }
+
+                    // Block until one of the read vars has changed.
+                    // The thread is parked in the meantime and registered
+                    // at the vars, so a commit to any of them wakes it.
+                    // `control` may still abort while we wait.
+                    if e == StmError::Retry {
+                        #[cfg(feature = "log")]
+                        progress.retried(&self.tx);
+
+                        let keep_waiting = ||
+                            control(StmError::Blocked) == TransactionControl::Retry;
+                        let waited = match self.deadline {
+                            Some(deadline) => self.tx.wait_for_change_until(deadline, keep_waiting),
+                            None => self.tx.wait_for_change(keep_waiting),
+                        };
+                        if !waited {
+                            return None;
+                        }
+                    }
                 }
             }
 
@@ -71,3 +188,65 @@ impl Tx for NonDeterministic {
 }
 
  
+
+#[cfg(all(test, feature = "log"))]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use log::{Log, Metadata, Record, LevelFilter};
+
+    /// Logger, that stores all messages.
+    struct TestLogger;
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static LOGGER: TestLogger = TestLogger;
+
+    impl Log for TestLogger {
+        fn enabled(&self, _: &Metadata) -> bool { true }
+
+        fn log(&self, record: &Record) {
+            MESSAGES.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// A transaction writes a var and then waits on a guard, that stays false
+    /// for too many wakeups.
+    #[test]
+    fn warn_non_productive_retries() {
+        use std::thread;
+        use std::sync::mpsc::channel;
+        use crate::guard;
+
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Warn);
+
+        let limit = NON_PRODUCTIVE_RETRIES + 10;
+        let written = TVar::new(0);
+        let wakeup = TVar::new(0);
+        let (run_tx, run_rx) = channel();
+
+        let waker = wakeup.clone();
+        let helper = thread::spawn(move || {
+            // Change the guarded var once after every run.
+            for _ in 0..limit {
+                run_rx.recv().unwrap();
+                crate::atomically(|tx| waker.modify(tx, |x| x + 1));
+            }
+        });
+
+        let x = NonDeterministic::new().with_control(|_| TransactionControl::Retry, |tx| {
+            written.write(tx, 1)?;
+            let x = wakeup.read(tx)?;
+            let _ = run_tx.send(());
+            guard(x >= limit)?;
+            Ok(x)
+        });
+        helper.join().unwrap();
+
+        assert_eq!(x, Some(limit));
+        assert!(MESSAGES.lock().unwrap().iter().any(|m|
+            m.contains(&format!("retried {} times", NON_PRODUCTIVE_RETRIES))));
+    }
+}
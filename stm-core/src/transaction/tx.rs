@@ -8,19 +8,52 @@
 
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry::*;
+use std::cmp;
 use std::mem;
 use std::sync::Arc;
 use std::any::Any;
 use std::cell::Cell;
+use std::hint;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::transaction::log_var::LogVar;
+use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
+use smallvec::SmallVec;
+
+use crate::transaction::control_block::ControlBlock;
+use crate::transaction::log_var::{LogVar, LogEntryKind, ArcAny};
+use crate::transaction::TransactionLike;
 use crate::transaction::log_var::LogVar::*;
 
-use crate::tvar::{TVar, VarControlBlock};
+use crate::tvar::{TVar, VarControlBlock, VarId};
+use crate::profile::{wait_observer, WaitEvent};
 use crate::result::*;
 use crate::result::StmError::*;
 
-thread_local!(static TRANSACTION_RUNNING: Cell<bool> = Cell::new(false));
+/// Number of vars, that a commit can handle without allocating.
+const INLINE_VARS: usize = 8;
+
+/// Interval, in which a blocked transaction checks if it should keep waiting.
+const WAIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Get the time to wait for a change, before `keep_waiting` is checked again.
+///
+/// It ends at `deadline`, if that comes before the next periodic check.
+/// After the deadline the checks are periodic again.
+fn wait_interval(deadline: Option<Instant>) -> Duration {
+    let now = Instant::now();
+    match deadline {
+        Some(deadline) if deadline > now => cmp::min(deadline - now, WAIT_CHECK_INTERVAL),
+        _ => WAIT_CHECK_INTERVAL,
+    }
+}
+
+/// Number of times, that `CommitMode::TryLock` doubles its spinning, before it yields
+/// the thread instead.
+const MAX_SPIN_BACKOFF: u32 = 6;
+
+thread_local!(static TRANSACTION_RUNNING: Cell<bool> = const { Cell::new(false) });
 
 /// `TransactionGuard` checks against nested STM calls.
 ///
@@ -45,14 +78,141 @@ impl Drop for TransactionGuard {
     }
 }
 
+/// Check if the current thread is running a transaction.
+pub fn in_transaction() -> bool {
+    TRANSACTION_RUNNING.with(|t| t.get())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionControl {
     Retry, Abort
 }
 
+/// A function, that runs after a transaction has been committed.
+pub type CommitHook = Box<dyn FnOnce()>;
+
+/// Run the hooks of a committed transaction.
+///
+/// A panicking hook does not keep the other hooks from running.
+/// When all hooks have run, the first panic is resumed.
+pub fn run_hooks(hooks: Vec<CommitHook>) {
+    let mut first_panic = None;
+    for hook in hooks {
+        if let Err(e) = panic::catch_unwind(AssertUnwindSafe(hook)) {
+            first_panic.get_or_insert(e);
+        }
+    }
+
+    if let Some(e) = first_panic {
+        panic::resume_unwind(e);
+    }
+}
+
+/// `CommitMode` determines, how a commit acquires the locks of the vars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitMode {
+    /// Block on every lock in the global order of the vars.
+    ///
+    /// This is deadlock free, but a commit holds the locks it already got,
+    /// while it waits for the next one. Under contention, commits can convoy
+    /// behind each other.
+    Blocking,
+
+    /// Only try to take every lock. If one is busy, release all held locks,
+    /// back off and start again.
+    ///
+    /// A commit never holds a partial set of locks while waiting.
+    TryLock,
+}
+
+/// Details about a failed commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConflictInfo {
+    var: VarId,
+    name: Option<&'static str>,
+}
+
+impl ConflictInfo {
+    fn new(var: &VarControlBlock) -> ConflictInfo {
+        ConflictInfo { var: var.id(), name: var.name() }
+    }
+
+    /// Get the var, that has been changed by another transaction.
+    ///
+    /// If several vars have changed, this is the first one in the order
+    /// of the commit.
+    pub fn var(&self) -> VarId {
+        self.var
+    }
+
+    /// Get the name of the changed var, if it has one.
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+}
+
+/// The result of `Transaction::commit_outcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitOutcome {
+    /// All writes have been committed.
+    Committed,
+
+    /// A read var has changed and nothing has been written.
+    Conflict(ConflictInfo),
+}
+
+/// The reason, why the locks for a commit could not be acquired.
+enum LockError {
+    /// A read var has changed.
+    Conflict(ConflictInfo),
+
+    /// A lock is held by another thread.
+    Busy,
+}
+
+/// Write lock of a var during a commit.
+///
+/// While it is held, optimistic validations of the var fail.
+struct WriteLock<'a> {
+    var: &'a VarControlBlock,
+    lock: RwLockWriteGuard<'a, ArcAny>,
+}
+
+impl<'a> WriteLock<'a> {
+    fn new(var: &'a VarControlBlock, lock: RwLockWriteGuard<'a, ArcAny>) -> WriteLock<'a> {
+        var.begin_write();
+        WriteLock { var, lock }
+    }
+}
+
+impl<'a> Drop for WriteLock<'a> {
+    fn drop(&mut self) {
+        // Publish the value before the lock is released.
+        self.var.end_write(&self.lock);
+    }
+}
+
+/// All locks held by a commit.
+struct Locks<'a> {
+    /// Read locks of vars, that are only checked.
+    reads: SmallVec<[RwLockReadGuard<'a, ArcAny>; INLINE_VARS]>,
+
+    /// Write locks in the order of the written entries in the log.
+    writes: SmallVec<[WriteLock<'a>; INLINE_VARS]>,
+
+    /// Written vars, that need to wake up their waiting threads.
+    written: SmallVec<[&'a Arc<VarControlBlock>; INLINE_VARS]>,
+}
+
 /// Transaction tracks all the read and written variables.
 ///
 /// It is used for checking vars, to ensure atomicity.
+///
+/// Normally `atomically` creates and runs the transaction. Advanced users
+/// can also drive it manually, for example to integrate it into their own scheduler:
+/// Create it with `Transaction::new`, run the body with `run_once` and
+/// decide on conflicts and `retry` themselves. `wait_for_change` blocks
+/// after a `retry` and `clear` resets the log before the next attempt.
 pub struct Transaction {
 
     /// Map of all vars that map the `VarControlBlock` of a var to a `LogVar`.
@@ -60,6 +220,35 @@ pub struct Transaction {
     ///
     /// The logs need to be accessed in a order to prevend dead-locks on locking.
     vars: BTreeMap<Arc<VarControlBlock>, LogVar>,
+
+    /// The way, in which `commit` acquires the locks.
+    commit_mode: CommitMode,
+
+    /// One undo log for every running first branch of `or`.
+    ///
+    /// It stores the entry of every var, that the branch touched, as it was
+    /// before the branch started, so that the branch can be rolled back.
+    undo: Vec<BTreeMap<Arc<VarControlBlock>, Option<LogVar>>>,
+
+    /// Vars with their original values, that `retry_on` has chosen to block on
+    /// instead of the whole read set.
+    wait_on: Option<Vec<(Arc<VarControlBlock>, ArcAny)>>,
+
+    /// Functions, that run after a successful commit.
+    hooks: Vec<CommitHook>,
+
+    /// Maximum number of vars in the log.
+    max_vars: Option<usize>,
+
+    /// Set, when an access has been refused because of `max_vars`.
+    max_vars_exceeded: bool,
+
+    /// Check the reads of discarded `or` branches on commit as well.
+    validate_obsolete: bool,
+
+    /// Key of a var in the order, in which `commit` locks the vars.
+    /// If it is not set, the vars are locked by their address.
+    lock_order: Option<Box<dyn Fn(VarId) -> u64>>,
 }
 
 impl Transaction {
@@ -67,8 +256,96 @@ impl Transaction {
     ///
     /// Normally you don't need to call this directly.
     /// Use `atomically` instead.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Transaction {
-        Transaction { vars: BTreeMap::new() }
+        Transaction {
+            vars: BTreeMap::new(),
+            commit_mode: CommitMode::Blocking,
+            undo: Vec::new(),
+            wait_on: None,
+            hooks: Vec::new(),
+            max_vars: None,
+            max_vars_exceeded: false,
+            validate_obsolete: false,
+            lock_order: None,
+        }
+    }
+
+    /// Set the way, in which the locks are acquired on commit.
+    ///
+    /// The default is `CommitMode::Blocking`. The mode stays set,
+    /// when the transaction is restarted.
+    pub fn set_commit_mode(&mut self, mode: CommitMode) {
+        self.commit_mode = mode;
+    }
+
+    /// Lock the vars on commit in the order of `key` instead of their address.
+    ///
+    /// This allows to match an external locking discipline, for example a lock
+    /// hierarchy of legacy code, that the commit must not violate.
+    /// Vars with the same key are locked by their address.
+    /// The order stays set, when the transaction is restarted.
+    ///
+    /// # Deadlocks
+    ///
+    /// The order must be the same for all transactions, that may commit
+    /// concurrently. Two commits, that lock the same vars in different orders,
+    /// can deadlock in `CommitMode::Blocking`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let outer = TVar::new(0);
+    /// let inner = TVar::new(0);
+    ///
+    /// // Always lock `outer` before `inner`.
+    /// let outer_id = outer.id();
+    ///
+    /// atomically(|tx| {
+    ///     tx.set_lock_order(move |id| if id == outer_id { 0 } else { 1 });
+    ///     outer.write(tx, 1)?;
+    ///     inner.write(tx, 1)
+    /// });
+    /// ```
+    pub fn set_lock_order<F>(&mut self, key: F)
+        where F: Fn(VarId) -> u64 + 'static
+    {
+        self.lock_order = Some(Box::new(key));
+    }
+
+    /// Check the reads of discarded `or` branches on commit.
+    ///
+    /// Normally these reads are only used for blocking. The choice of the
+    /// branch depends on them, though. A deterministic transaction must
+    /// not commit a branch, that a serial execution would not have chosen.
+    pub(crate) fn set_validate_obsolete(&mut self, validate: bool) {
+        self.validate_obsolete = validate;
+    }
+
+    /// Limit the number of vars in the log.
+    ///
+    /// An access to a new var beyond the limit fails with `StmError::Failure`
+    /// and marks the transaction, so that the runner can abort it.
+    /// The limit stays set, when the transaction is restarted.
+    pub(crate) fn set_max_vars(&mut self, max: Option<usize>) {
+        self.max_vars = max;
+    }
+
+    /// Check if an access has been refused because of the limit of vars.
+    pub(crate) fn max_vars_exceeded(&self) -> bool {
+        self.max_vars_exceeded
+    }
+
+    /// Refuse the access to `var`, if it is not yet in the log and the log is full.
+    fn check_max_vars(&mut self, var: &Arc<VarControlBlock>) -> StmResult<()> {
+        if let Some(max) = self.max_vars {
+            if self.vars.len() >= max && !self.vars.contains_key(var) {
+                self.max_vars_exceeded = true;
+                return Err(Failure);
+            }
+        }
+        Ok(())
     }
 
     /// Perform a downcast on a var.
@@ -88,25 +365,7 @@ impl Transaction {
     /// without running into infinite loops.
     /// Just the commit of wrong values is prevented by STM.
     pub fn read<T: Send + Sync + Any + Clone>(&mut self, var: &TVar<T>) -> StmResult<T> {
-        let ctrl = var.control_block().clone();
-        // Check if the same var was written before.
-        let value = match self.vars.entry(ctrl) {
-
-            // If the variable has been accessed before, then load that value.
-            Occupied(mut entry) => entry.get_mut().read(),
-
-            // Else load the variable statically.
-            Vacant(entry) => {
-                // Read the value from the var.
-                let value = var.read_ref_atomic();
-
-                // Store in in an entry.
-                entry.insert(Read(value.clone()));
-                value
-            }
-        };
-
-        // For now always succeeds, but that may change later.
+        let value = self.read_any(var.control_block())?;
         Ok(Transaction::downcast(value))
     }
 
@@ -117,16 +376,29 @@ impl Transaction {
     pub fn write<T: Any + Send + Sync + Clone>(&mut self, var: &TVar<T>, value: T) -> StmResult<()> {
         // box the value
         let boxed = Arc::new(value);
+        self.write_any(var.control_block(), boxed)
+    }
 
-        // new control block
-        let ctrl = var.control_block().clone();
-        // update or create new entry
-        match self.vars.entry(ctrl) {
-            Occupied(mut entry)     => entry.get_mut().write(boxed),
-            Vacant(entry)       => { entry.insert(Write(boxed)); }
+    /// Read several vars and return their values in the order of `vars`.
+    ///
+    /// This is the same as reading every var on its own.
+    /// The order of the reads does not matter for the commit, which always
+    /// locks the vars in the order of the log.
+    pub fn read_all<T: Send + Sync + Any + Clone>(&mut self, vars: &[TVar<T>]) -> StmResult<Vec<T>> {
+        let mut values = Vec::with_capacity(vars.len());
+        for var in vars {
+            values.push(self.read(var)?);
         }
+        Ok(values)
+    }
 
-        // For now always succeeds, but that may change later.
+    /// Write several vars at once.
+    ///
+    /// This is the same as writing every pair on its own and stops at the first error.
+    pub fn write_many<T: Any + Send + Sync + Clone>(&mut self, pairs: &[(TVar<T>, T)]) -> StmResult<()> {
+        for (var, value) in pairs {
+            self.write(var, value.clone())?;
+        }
         Ok(())
     }
 
@@ -136,23 +408,49 @@ impl Transaction {
     /// If both block, `Transaction::or` still waits for `TVar`s in both functions.
     /// Use `Transaction::or` instead of handling errors directly with the `Result::or`.
     /// The later does not handle all the blocking correctly.
+    // Vars are ordered by their address, so the interior mutability
+    // of the keys of the undo log does not affect the order.
+    #[allow(clippy::mutable_key_type)]
     pub fn or<T, F1, F2>(&mut self, first: F1, second: F2) -> StmResult<T>
         where F1: Fn(&mut Transaction) -> StmResult<T>,
               F2: Fn(&mut Transaction) -> StmResult<T>,
     {
-        // Create a backup of the log.
-        let mut copy = Transaction {
-            vars: self.vars.clone()
-        };
+        // Instead of copying the whole log up front, only remember the old
+        // entries of the vars, that the first branch touches.
+        // In the common case, that the first branch succeeds, nothing is copied.
+        self.undo.push(BTreeMap::new());
+        let hooks = self.hooks.len();
 
         // Run the first computation.
         let f = first(self);
 
-        match f {
+        let undo = self.undo.pop().expect("STM: undo log of `or` missing");
+
+        let result = match f {
             // Run other on manual retry call.
             Err(Retry)      => {
-                // swap, so that self is the current run
-                mem::swap(self, &mut copy);
+                // The hooks of the first branch must not run.
+                self.hooks.truncate(hooks);
+
+                // Roll back the first branch, but keep its log for blocking.
+                let mut discarded = Vec::with_capacity(undo.len());
+                for (var, old) in &undo {
+                    let current = match *old {
+                        Some(ref old)   => self.vars.insert(var.clone(), old.clone()),
+                        None            => self.vars.remove(var),
+                    };
+                    if let Some(current) = current {
+                        discarded.push((var.clone(), current));
+                    }
+                }
+
+                // If the first branch has chosen its vars with `retry_on`,
+                // only block on them.
+                if let Some(wait_on) = self.wait_on.take() {
+                    discarded = wait_on.into_iter()
+                        .map(|(var, value)| (var, ReadObsolete(value)))
+                        .collect();
+                }
 
                 // Run other action.
                 let s = second(self);
@@ -161,7 +459,14 @@ impl Transaction {
                 match s {
                     Err(Failure)        => Err(Failure),
                     s => {
-                        self.combine(copy);
+                        match self.wait_on {
+                            // The second branch has chosen its vars with `retry_on`.
+                            // Add the ones of the first branch.
+                            Some(ref mut wait_on) => wait_on.extend(discarded.into_iter()
+                                .filter_map(|(var, value)|
+                                    value.into_read_value().map(|v| (var, v)))),
+                            None => self.combine(discarded),
+                        }
                         s
                     }
                 }
@@ -169,116 +474,586 @@ impl Transaction {
 
             // Return success and failure directly
             x               => x,
+        };
+
+        // An enclosing `or` needs to be able to roll back the vars
+        // touched here as well.
+        if let Some(outer) = self.undo.last_mut() {
+            for (var, old) in undo {
+                outer.entry(var).or_insert(old);
+            }
         }
+
+        result
     }
 
-    /// Combine two logs into a single log, to allow waiting for all reads.
-    fn combine(&mut self, other: Transaction) {
+    /// Retry the transaction, but only block on `vars`.
+    ///
+    /// By default a retrying transaction waits for a change of any var,
+    /// that it has read. `retry_on` only waits for the given vars and ignores
+    /// the other reads. This allows to poll some vars and wait on others.
+    ///
+    /// Like `retry`, `retry_on` returns an error, that must be propagated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `vars` is empty, because the transaction could never wake up again.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use stm_core::*;
+    /// let polled = TVar::new(0);
+    /// let awaited = TVar::new(false);
+    ///
+    /// let x = atomically(|tx| {
+    ///     let x = polled.read(tx)?;
+    ///     if !awaited.read(tx)? {
+    ///         // Changes of `polled` don't wake up the transaction.
+    ///         return tx.retry_on(&[&awaited]);
+    ///     }
+    ///     Ok(x)
+    /// });
+    /// ```
+    pub fn retry_on<T, R>(&mut self, vars: &[&TVar<T>]) -> StmResult<R>
+        where T: Any + Send + Sync + Clone
+    {
+        assert!(!vars.is_empty(), "STM: `retry_on` needs at least one var to wait on");
+        let wait_on = self.wait_on.get_or_insert_with(Vec::new);
+        for var in vars {
+            let ctrl = var.control_block();
+
+            // Use the value, that the transaction has seen, or the current one
+            // if the var has not been read yet.
+            let value = match self.vars.get(ctrl).and_then(|v| v.clone().into_read_value()) {
+                Some(value) => value,
+                None        => ctrl.value.read().clone(),
+            };
+            wait_on.push((ctrl.clone(), value));
+        }
+        Err(Retry)
+    }
+
+    /// Combine the log of a discarded branch into this one, to allow waiting for all reads.
+    ///
+    /// The reads of `other` become obsolete. They are only used for blocking and 
+    /// don't override the entries of `self`.
+    /// A var, that both branches have read, is therefore validated against the
+    /// value of the taken branch on commit.
+    fn combine(&mut self, other: Vec<(Arc<VarControlBlock>, LogVar)>) {
         // combine reads
-        for (var, value) in other.vars {
-            // only insert new values
+        for (var, value) in other {
             if let Some(value) = value.obsolete() {
-                self.vars.entry(var).or_insert(value);
+                match self.vars.entry(var) {
+                    // Keep the current entry, but don't lose the read for blocking.
+                    Occupied(mut entry) => entry.get_mut().add_obsolete(value),
+                    Vacant(entry)       => { entry.insert(value); }
+                }
             }
         }
     }
 
+    /// Remember the entry of a var before the running first branch
+    /// of `or` changes it for the first time.
+    fn save_undo(&mut self, var: &Arc<VarControlBlock>) {
+        if let Some(undo) = self.undo.last_mut() {
+            if !undo.contains_key(var) {
+                undo.insert(var.clone(), self.vars.get(var).cloned());
+            }
+        }
+    }
+
+    /// Block until one of the read vars has changed.
+    ///
+    /// All vars that have been read, including the obsolete reads of `or`,
+    /// are used for blocking, unless the vars were chosen with `retry_on`.
+    /// `keep_waiting` is called on every wakeup without a change
+    /// and periodically. If it returns `false`, the waiting is canceled.
+    ///
+    /// Return `false` if the waiting was canceled.
+    /// The log is cleared afterwards.
+    pub fn wait_for_change<F>(&mut self, keep_waiting: F) -> bool
+        where F: FnMut() -> bool
+    {
+        self.wait_for_change_by(None, keep_waiting)
+    }
+
+    /// Block until one of the read vars has changed like `wait_for_change`,
+    /// but call `keep_waiting` at `deadline` at the latest.
+    ///
+    /// The periodic calls of `keep_waiting` are not precise enough for timeouts.
+    /// Here the last wait before the deadline is shortened to end at it.
+    pub fn wait_for_change_until<F>(&mut self, deadline: Instant, keep_waiting: F) -> bool
+        where F: FnMut() -> bool
+    {
+        self.wait_for_change_by(Some(deadline), keep_waiting)
+    }
+
+    /// Implementation of `wait_for_change` and `wait_for_change_until`.
+    fn wait_for_change_by<F>(&mut self, deadline: Option<Instant>, mut keep_waiting: F) -> bool
+        where F: FnMut() -> bool
+    {
+        let ctrl = Arc::new(ControlBlock::new());
+        let mut reads = Vec::with_capacity(self.vars.len());
+
+        let wait_on = match self.wait_on.take() {
+            Some(wait_on)   => wait_on,
+            None            => mem::take(&mut self.vars).into_iter()
+                .filter_map(|(var, value)| value.into_read_value().map(|v| (var, v)))
+                .collect(),
+        };
+        self.vars.clear();
+
+        // Register at all read vars.
+        let mut changed = false;
+        for (var, value) in wait_on {
+            var.wait(&ctrl);
+
+            // The var may have changed before we registered.
+            changed |= !Arc::ptr_eq(&var.value.read(), &value);
+            reads.push(var);
+
+            if changed {
+                break;
+            }
+        }
+
+        let mut waited = true;
+        if !changed {
+            let observer = wait_observer();
+            if let Some(observer) = observer {
+                observer(WaitEvent {
+                    thread: thread::current().id(),
+                    vars: reads.iter().map(|var| var.id()).collect(),
+                    blocked: true,
+                });
+            }
+
+            while !ctrl.wait_timeout(wait_interval(deadline)) {
+                if !keep_waiting() {
+                    waited = false;
+                    break;
+                }
+            }
+
+            if let Some(observer) = observer {
+                observer(WaitEvent {
+                    thread: thread::current().id(),
+                    vars: Vec::new(),
+                    blocked: false,
+                });
+            }
+        }
+
+        // Let the vars know, that we don't wait anymore.
+        for var in reads {
+            var.set_dead();
+        }
+        waited
+    }
+
+    /// Get all vars, that have been written.
+    #[cfg(feature = "log")]
+    pub fn written_vars(&self) -> Vec<Arc<VarControlBlock>> {
+        self.vars.iter()
+            .filter(|&(_, value)| matches!(value, Write(_) | ReadWrite(_, _) | ReadObsoleteWrite(_, _)))
+            .map(|(var, _)| var.clone())
+            .collect()
+    }
+
+    /// Run `f` once and try to commit.
+    ///
+    /// The log is cleared before `f` runs.
+    /// Return `Ok(Some(t))` if the transaction has been committed and `Ok(None)`,
+    /// if the commit failed, because a read var has changed.
+    /// An error of `f` is returned as it is and the log is kept, so that
+    /// `wait_for_change` can block on a `retry`.
+    ///
+    /// The hooks of `on_commit` run after a successful commit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(0);
+    /// let mut tx = Transaction::new();
+    ///
+    /// let x = loop {
+    ///     match tx.run_once(|tx| var.modify(tx, |x| x + 1)) {
+    ///         Ok(Some(x))             => break x,
+    ///         Ok(None)                => continue,
+    ///         Err(StmError::Retry)    => { tx.wait_for_change(|| true); }
+    ///         Err(_)                  => continue,
+    ///     }
+    /// };
+    /// assert_eq!(var.read_atomic(), 1);
+    /// ```
+    pub fn run_once<T, F>(&mut self, f: F) -> Result<Option<T>, StmError>
+        where F: FnOnce(&mut Transaction) -> StmResult<T>
+    {
+        self.clear();
+
+        let guard = TransactionGuard::new();
+        let t = f(self)?;
+        if !self.commit() {
+            return Ok(None);
+        }
+
+        let hooks = self.take_hooks();
+        drop(guard);
+        run_hooks(hooks);
+        Ok(Some(t))
+    }
+
+    /// Run `f` after the transaction has been committed successfully.
+    ///
+    /// The hooks run in the order of registration after the transaction
+    /// has finished, so they may start new transactions.
+    /// They are discarded, when the transaction is restarted or aborted,
+    /// or when they were registered in a branch of `or`, that called `retry`.
+    ///
+    /// When a hook panics, the commit has already succeeded.
+    /// The other hooks still run and the panic is resumed afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(0);
+    ///
+    /// atomically(|tx| {
+    ///     var.write(tx, 42)?;
+    ///     tx.on_commit(|| println!("committed"));
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn on_commit<F>(&mut self, f: F)
+        where F: FnOnce() + 'static
+    {
+        self.hooks.push(Box::new(f));
+    }
+
+    /// Take the hooks, that have been registered with `on_commit`.
+    pub fn take_hooks(&mut self) -> Vec<CommitHook> {
+        mem::take(&mut self.hooks)
+    }
+
+    /// Get the vars in the log together with the kind of access.
+    ///
+    /// The entries are ordered by `VarId`. Every var occurs once, no matter how often
+    /// it has been accessed.
+    /// This is meant for testing and tooling. The result of a transaction should not
+    /// depend on it.
+    pub fn log_entries(&self) -> impl Iterator<Item = (VarId, LogEntryKind)> + '_ {
+        self.vars.iter().map(|(var, value)| (var.id(), value.kind()))
+    }
+
+    /// Get the value, that has been written to `var` in this transaction.
+    ///
+    /// The value can be changed in place, if the log holds the only reference to it.
+    pub(crate) fn write_value_mut(&mut self, var: &Arc<VarControlBlock>) -> Option<&mut ArcAny> {
+        // A running `or` branch keeps a copy of the entry, so that it
+        // can be restored, and the value is not unique anymore.
+        self.save_undo(var);
+        self.vars.get_mut(var).and_then(LogVar::write_value_mut)
+    }
+
+    /// Get the number of vars in the log.
+    pub(crate) fn var_count(&self) -> usize {
+        self.vars.len()
+    }
+
     /// Clear the log's data.
     ///
     /// This should be used before redoing a computation, but
     /// nowhere else.
     pub fn clear(&mut self) {
         self.vars.clear();
+        self.wait_on = None;
+        self.hooks.clear();
+        self.max_vars_exceeded = false;
     }
 
     /// Write the log back to the variables.
     ///
     /// Return true for success and false, if a read var has changed
+    ///
+    /// The log is consumed in both cases, so that the written values
+    /// can be moved into the vars.
     pub fn commit(&mut self) -> bool {
+        self.commit_outcome() == CommitOutcome::Committed
+    }
+
+    /// Write the log back to the variables and report, why it failed.
+    ///
+    /// It is the same as `commit`, but tells a custom runner, which var
+    /// has caused the conflict.
+    pub fn commit_outcome(&mut self) -> CommitOutcome {
+        // A transaction without any access has nothing to lock or validate.
+        if self.vars.is_empty() {
+            return CommitOutcome::Committed;
+        }
+
         // Use two phase locking for safely writing data back to the vars.
 
+        let (vars, mut log) = self.split_log();
+
+        if self.validate_obsolete {
+            log = log.into_iter().map(LogVar::unobsolete).collect();
+        }
+
         // First phase: acquire locks.
-        // Check for consistency of all the reads and perform
-        // an early return if something is not consistent.
+        let locks = match self.commit_mode {
+            CommitMode::Blocking => Transaction::lock_vars(&vars, &log, false),
+            CommitMode::TryLock  => Transaction::try_lock_vars(&vars, &log),
+        };
+
+        let Locks { reads, mut writes, written } = match locks {
+            Ok(locks)   => locks,
+            Err(LockError::Conflict(info))
+                => return CommitOutcome::Conflict(info),
+            Err(LockError::Busy)
+                => unreachable!("busy locks are retried"),
+        };
+
+        // Second phase: write back and release
+
+        // Release the reads first.
+        // This allows other threads to continue quickly.
+        drop(reads);
+
+        // The locks have been taken in the order of the log.
+        let values = log.into_iter().filter_map(LogVar::into_write_value);
+        for (value, lock) in values.zip(writes.iter_mut()) {
+            // Commit value.
+            *lock.lock = value;
+            lock.var.count_write();
+        }
 
-        // Created arrays for storing the locks
-        // vector of locks.
-        let mut read_vec = Vec::with_capacity(self.vars.len());
+        // Release all write locks before waking up any thread.
+        // Otherwise woken threads may immediately block on our locks again.
+        drop(writes);
 
-        // vector of tuple (value, lock)
-        let mut write_vec = Vec::with_capacity(self.vars.len());
+        // Unblock all threads waiting for the written vars.
+        // A thread, that waits for several of them, is woken only once.
+        match written.len() {
+            0 => {}
+            1 => written[0].wake_all(),
+            _ => {
+                let mut threads: Vec<_> = written.iter()
+                    .flat_map(|var| var.take_waiters())
+                    .collect();
+                threads.sort_unstable_by_key(Arc::as_ptr);
+                threads.dedup_by(|a, b| Arc::ptr_eq(a, b));
 
-        // vector of written variables
-        let mut written = Vec::with_capacity(self.vars.len());
+                for thread in threads {
+                    thread.set_changed();
+                }
+            }
+        }
+
+        // Commit succeded.
+        CommitOutcome::Committed
+    }
 
+    /// Take the log and split it, so that the locks can borrow the vars,
+    /// while the values are moved out.
+    ///
+    /// Both are in the order, in which the vars are locked.
+    fn split_log(&mut self)
+        -> (SmallVec<[Arc<VarControlBlock>; INLINE_VARS]>, SmallVec<[LogVar; INLINE_VARS]>)
+    {
+        let vars = mem::take(&mut self.vars).into_iter();
+        match self.lock_order {
+            None => vars.unzip(),
+            Some(ref key) => {
+                // The log is ordered by address, so a stable sort
+                // keeps that order for equal keys.
+                let mut vars: SmallVec<[_; INLINE_VARS]> = vars.collect();
+                vars.sort_by_cached_key(|(var, _)| key(var.id()));
+                vars.into_iter().unzip()
+            }
+        }
+    }
+
+    /// Acquire the locks of all vars in the log.
+    ///
+    /// Check for consistency of all the reads and perform
+    /// an early return if something is not consistent.
+    /// If `try_lock` is set, return `LockError::Busy` instead of
+    /// blocking on a lock.
+    ///
+    /// Every var has exactly one entry in the log, no matter how often
+    /// and in which `or` branch it was accessed. Therefore every var is
+    /// locked exactly once with the strongest needed mode: 
+    /// a write lock if it is written and a read lock if it is only read.
+    /// The locks can't be upgraded, so locking twice would deadlock.
+    ///
+    /// Vars, that are mostly read, are not locked at all, but validated
+    /// optimistically after all locks have been taken.
+    fn lock_vars<'a>(vars: &'a [Arc<VarControlBlock>], log: &[LogVar], try_lock: bool)
+        -> Result<Locks<'a>, LockError>
+    {
+        // Small transactions are the common case, so the locks
+        // are stored inline without allocating.
+        let mut locks = Locks {
+            reads: SmallVec::new(),
+            writes: SmallVec::new(),
+            written: SmallVec::new(),
+        };
+        let mut optimistic: SmallVec<[_; INLINE_VARS]> = SmallVec::new();
 
-        for (var, value) in &self.vars {
+        for (var, value) in vars.iter().zip(log) {
             // lock the variable and read the value
 
             match *value {
                 // We need to take a write lock.
-                Write(ref w) | ReadObsoleteWrite(_,ref w)=> {
+                Write(_) | ReadObsoleteWrite(_, _) => {
                     // take write lock
-                    let lock = var.value.write();
+                    let lock = if try_lock {
+                        var.value.try_write().ok_or(LockError::Busy)?
+                    } else {
+                        var.value.write()
+                    };
                     // add all data to the vector
-                    write_vec.push((w, lock));
-                    written.push(var);
+                    locks.writes.push(WriteLock::new(var, lock));
+                    locks.written.push(var);
                 }
 
                 // We need to check for consistency and
                 // take a write lock.
-                ReadWrite(ref original,ref w) => {
+                ReadWrite(ref original, _) => {
                     // take write lock
-                    let lock = var.value.write();
+                    let lock = if try_lock {
+                        var.value.try_write().ok_or(LockError::Busy)?
+                    } else {
+                        var.value.write()
+                    };
 
                     if !Arc::ptr_eq(&lock, original) {
-                        return false;
+                        #[cfg(feature = "stats")]
+                        var.set_conflict();
+                        return Err(LockError::Conflict(ConflictInfo::new(var)));
                     }
                     // add all data to the vector
-                    write_vec.push((w, lock));
-                    written.push(var);
+                    locks.writes.push(WriteLock::new(var, lock));
+                    locks.written.push(var);
                 }
                 // Nothing to do. ReadObsolete is only needed for blocking, not
                 // for consistency checks.
                 ReadObsolete(_) => { }
+                // Validate mostly read vars later.
+                Read(ref original) if var.count_read() => {
+                    optimistic.push((var, original));
+                }
                 // Take read lock and check for consistency.
                 Read(ref original) => {
                     // Take a read lock.
-                    let lock = var.value.read();
+                    let lock = if try_lock {
+                        var.value.try_read().ok_or(LockError::Busy)?
+                    } else {
+                        var.value.read()
+                    };
 
                     if !Arc::ptr_eq(&lock, original) {
-                        return false;
+                        #[cfg(feature = "stats")]
+                        var.set_conflict();
+                        return Err(LockError::Conflict(ConflictInfo::new(var)));
                     }
 
-                    read_vec.push(lock);
+                    locks.reads.push(lock);
                 }
             }
         }
 
-        // Second phase: write back and release
+        // All written vars are locked now, so no other commit, that
+        // depends on them, can slip in between the validations.
+        for (var, original) in optimistic {
+            if !var.validate_optimistic(original) {
+                #[cfg(feature = "stats")]
+                var.set_conflict();
+                return Err(LockError::Conflict(ConflictInfo::new(var)));
+            }
+        }
+        Ok(locks)
+    }
 
-        // Release the reads first.
-        // This allows other threads to continue quickly.
-        drop(read_vec);
+    /// Acquire all locks without blocking on any of them.
+    ///
+    /// When a lock is busy, all held locks are released
+    /// and the whole lock phase starts again after a short backoff.
+    fn try_lock_vars<'a>(vars: &'a [Arc<VarControlBlock>], log: &[LogVar])
+        -> Result<Locks<'a>, LockError>
+    {
+        let mut backoff = 0;
+        loop {
+            match Transaction::lock_vars(vars, log, true) {
+                Err(LockError::Busy)    => {}
+                result                  => return result,
+            }
 
-        for (value, mut lock) in write_vec {
-            // Commit value.
-            *lock = value.clone();
+            // Spin for a short time first and give up the
+            // time slice, when the contention persists.
+            if backoff < MAX_SPIN_BACKOFF {
+                for _ in 0..(1 << backoff) {
+                    hint::spin_loop();
+                }
+                backoff += 1;
+            } else {
+                thread::yield_now();
+            }
         }
+    }
+}
+
+impl TransactionLike for Transaction {
+    fn read_any(&mut self, var: &Arc<VarControlBlock>) -> StmResult<ArcAny> {
+        self.check_max_vars(var)?;
+        self.save_undo(var);
+
+        let ctrl = var.clone();
+        // Check if the same var was written before.
+        let value = match self.vars.entry(ctrl) {
+
+            // If the variable has been accessed before, then load that value.
+            Occupied(mut entry) => entry.get_mut().read(),
 
-        for var in written {
-            // Unblock all threads waiting for it.
-            var.wake_all();
+            // Else load the variable statically.
+            Vacant(entry) => {
+                // Read the value from the var.
+                let value = var.value.read().clone();
+
+                // Store in in an entry.
+                entry.insert(Read(value.clone()));
+                value
+            }
+        };
+
+        // For now always succeeds, but that may change later.
+        Ok(value)
+    }
+
+    fn write_any(&mut self, var: &Arc<VarControlBlock>, value: ArcAny) -> StmResult<()> {
+        self.check_max_vars(var)?;
+        self.save_undo(var);
+
+        // new control block
+        let ctrl = var.clone();
+        // update or create new entry
+        match self.vars.entry(ctrl) {
+            Occupied(mut entry)     => entry.get_mut().write(value),
+            Vacant(entry)       => { entry.insert(Write(value)); }
         }
 
-        // Commit succeded.
-        true
+        // For now always succeeds, but that may change later.
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::retry;
 
     #[test]
     fn read() {
@@ -302,4 +1077,620 @@ mod test {
         // The original value is still preserved.
         assert_eq!(var.read_atomic(), [1, 2]);
     }
+
+    /// Commit more vars than fit into the inline storage.
+    #[test]
+    fn commit_many_vars() {
+        let mut log = Transaction::new();
+        let vars: Vec<_> = (0..3 * INLINE_VARS).map(TVar::new).collect();
+
+        for (i, var) in vars.iter().enumerate() {
+            if i % 3 == 0 {
+                log.read(var).unwrap();
+            } else {
+                var.modify(&mut log, |x| x + 1).unwrap();
+            }
+        }
+        assert!(log.commit());
+
+        for (i, var) in vars.iter().enumerate() {
+            let expected = if i % 3 == 0 { i } else { i + 1 };
+            assert_eq!(var.read_atomic(), expected);
+        }
+    }
+
+    /// A thread, that waits for several written vars, is woken once per commit.
+    #[test]
+    fn commit_wakes_once() {
+        use std::sync::atomic::Ordering;
+
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        let waiter = Arc::new(ControlBlock::new());
+        a.control_block().wait(&waiter);
+        b.control_block().wait(&waiter);
+
+        let mut log = Transaction::new();
+        log.write(&a, 1).unwrap();
+        log.write(&b, 1).unwrap();
+        assert!(log.commit());
+
+        assert_eq!(waiter.woken.load(Ordering::SeqCst), 1);
+    }
+
+    /// The commit moves the written values into the vars and leaves an empty log.
+    #[test]
+    fn commit_consumes_log() {
+        let mut log = Transaction::new();
+        let a = TVar::new(1);
+        let b = TVar::new(2);
+        let c = TVar::new(3);
+
+        log.read(&a).unwrap();
+        b.modify(&mut log, |x| x * 10).unwrap();
+
+        let written = Arc::new(30) as ArcAny;
+        log.write_any(c.control_block(), written.clone()).unwrap();
+
+        assert!(log.commit());
+        assert!(log.vars.is_empty());
+
+        assert_eq!(a.read_atomic(), 1);
+        assert_eq!(b.read_atomic(), 20);
+        assert_eq!(c.read_atomic(), 30);
+
+        // The var holds the very value from the log and not a copy.
+        assert!(Arc::ptr_eq(&c.read_ref_atomic(), &written));
+    }
+
+    /// Read and write the same var in different `or` branches.
+    /// The commit must lock it only once, or it deadlocks.
+    #[test]
+    fn or_read_write_single_lock() {
+        use crate::test::terminates;
+
+        let terminated = terminates(500, || {
+            let mut log = Transaction::new();
+            let var = TVar::new(1);
+
+            log.read(&var).unwrap();
+            let _ = log.or(
+                |tx| { var.write(tx, 2)?; retry() },
+                |tx| var.modify(tx, |x| x + 10)
+            );
+            assert!(matches!(log.vars.get(var.control_block()), Some(ReadWrite(_, _))));
+            assert!(log.commit());
+            assert_eq!(var.read_atomic(), 11);
+
+            // Read only in the discarded branch and write in the other.
+            let mut log = Transaction::new();
+            let _ = log.or(
+                |tx| { var.read(tx)?; retry() },
+                |tx| var.write(tx, 5)
+            );
+            assert!(log.commit());
+            assert_eq!(var.read_atomic(), 5);
+        });
+        assert!(terminated);
+    }
+
+    /// Threads, that wait for written vars, are only woken 
+    /// after all locks have been released.
+    #[test]
+    fn commit_wakes_after_unlock() {
+        use std::thread;
+        use std::sync::mpsc::channel;
+        use crate::transaction::control_block::ControlBlock;
+
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let (ac, bc) = (a.clone(), b.clone());
+        let (registered_tx, registered_rx) = channel();
+
+        let waiter = thread::spawn(move || {
+            let block = Arc::new(ControlBlock::new());
+            ac.control_block().wait(&block);
+            bc.control_block().wait(&block);
+            registered_tx.send(()).unwrap();
+            block.wait();
+
+            // None of the locks may still be held.
+            ac.control_block().value.try_read().is_some()
+                && bc.control_block().value.try_read().is_some()
+        });
+
+        registered_rx.recv().unwrap();
+        let mut log = Transaction::new();
+        log.write(&a, 1).unwrap();
+        log.write(&b, 1).unwrap();
+        assert!(log.commit());
+
+        assert!(waiter.join().unwrap());
+    }
+
+    /// A `TryLock` commit backs off while a lock is held
+    /// and succeeds after it was released.
+    #[test]
+    fn try_lock_commit_busy() {
+        use std::thread;
+        use std::time::Duration;
+
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let guard = b.control_block().value.read();
+
+        let (ac, bc) = (a.clone(), b.clone());
+        let committer = thread::spawn(move || {
+            let mut log = Transaction::new();
+            log.set_commit_mode(CommitMode::TryLock);
+            log.write(&ac, 1).unwrap();
+            log.write(&bc, 1).unwrap();
+            log.commit()
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(b.read_atomic(), 0);
+        drop(guard);
+
+        assert!(committer.join().unwrap());
+        assert_eq!(a.read_atomic(), 1);
+        assert_eq!(b.read_atomic(), 1);
+    }
+
+    /// A `TryLock` commit still detects conflicts.
+    #[test]
+    fn try_lock_commit_conflict() {
+        let var = TVar::new(0);
+
+        let mut log = Transaction::new();
+        log.set_commit_mode(CommitMode::TryLock);
+        var.modify(&mut log, |x| x + 1).unwrap();
+
+        let mut other = Transaction::new();
+        other.write(&var, 10).unwrap();
+        assert!(other.commit());
+
+        assert!(!log.commit());
+        assert_eq!(var.read_atomic(), 10);
+    }
+
+    /// The outcome of a failed commit names the changed var.
+    #[test]
+    fn commit_outcome_conflict() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        let mut log = Transaction::new();
+        log.read(&a).unwrap();
+        b.modify(&mut log, |x| x + 1).unwrap();
+
+        let mut other = Transaction::new();
+        other.write(&b, 10).unwrap();
+        assert_eq!(other.commit_outcome(), CommitOutcome::Committed);
+
+        match log.commit_outcome() {
+            CommitOutcome::Conflict(info) => assert_eq!(info.var(), b.id()),
+            CommitOutcome::Committed => panic!("commit must fail"),
+        }
+        assert_eq!(b.read_atomic(), 10);
+    }
+
+    /// The outcome of a failed commit names the changed var.
+    #[test]
+    fn commit_outcome_conflict_name() {
+        let a = TVar::new_with_name(0, "a");
+        let b = TVar::new_with_name(0, "b");
+
+        let mut log = Transaction::new();
+        log.read(&a).unwrap();
+        log.read(&b).unwrap();
+        log.write(&a, 1).unwrap();
+
+        b.write_atomic(10);
+
+        match log.commit_outcome() {
+            CommitOutcome::Conflict(info) => assert_eq!(info.name(), Some("b")),
+            CommitOutcome::Committed => panic!("commit must fail"),
+        }
+    }
+
+    /// Without a lock order the vars are locked by their address.
+    #[test]
+    fn default_lock_order() {
+        let vars: Vec<_> = (0..4).map(TVar::new).collect();
+
+        let mut log = Transaction::new();
+        for var in vars.iter().rev() {
+            var.write(&mut log, 0).unwrap();
+        }
+
+        let mut expected: Vec<_> = vars.iter().map(|var| var.id()).collect();
+        expected.sort();
+
+        let (locked, _) = log.split_log();
+        assert_eq!(locked.iter().map(|var| var.id()).collect::<Vec<_>>(), expected);
+    }
+
+    /// A custom lock order replaces the order by address.
+    #[test]
+    fn custom_lock_order() {
+        let vars: Vec<_> = (0..4).map(TVar::new).collect();
+        let ids: Vec<_> = vars.iter().map(|var| var.id()).collect();
+
+        // Lock the vars in reverse order of their creation.
+        let mut log = Transaction::new();
+        let order = ids.clone();
+        log.set_lock_order(move |id| 10 - order.iter().position(|&i| i == id).unwrap() as u64);
+        for var in &vars {
+            var.write(&mut log, 1).unwrap();
+        }
+
+        let (locked, _) = log.split_log();
+        let expected: Vec<_> = ids.iter().rev().cloned().collect();
+        assert_eq!(locked.iter().map(|var| var.id()).collect::<Vec<_>>(), expected);
+    }
+
+    /// Transactions, that all use the same custom order, commit concurrently.
+    #[test]
+    fn custom_lock_order_concurrent() {
+        use std::thread;
+
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let a = a.clone();
+            let b = b.clone();
+            thread::spawn(move || {
+                let b_id = b.id();
+                for _ in 0..500 {
+                    crate::atomically(|tx| {
+                        tx.set_lock_order(move |id| if id == b_id { 0 } else { 1 });
+                        a.modify(tx, |x| x + 1)?;
+                        b.modify(tx, |x| x + 1)
+                    });
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(a.read_atomic(), 2000);
+        assert_eq!(b.read_atomic(), 2000);
+    }
+
+    /// The first branch of `or` is rolled back without a copy of the log,
+    /// when it retries.
+    #[test]
+    fn or_discards_first_branch() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let c = TVar::new(0);
+
+        let mut log = Transaction::new();
+        a.write(&mut log, 1).unwrap();
+
+        let x = log.or(
+            |tx| {
+                a.write(tx, 2)?;
+                b.write(tx, 2)?;
+                c.read(tx)?;
+                retry()
+            },
+            |tx| a.read(tx)
+        );
+        assert_eq!(x, Ok(1));
+        assert!(log.undo.is_empty());
+
+        // The write to `b` is gone, the read of `c` is kept for blocking.
+        assert!(!log.vars.contains_key(b.control_block()));
+        assert_eq!(is_obsolete(&log, &c), Some(true));
+
+        assert!(log.commit());
+        assert_eq!(a.read_atomic(), 1);
+        assert_eq!(b.read_atomic(), 0);
+    }
+
+    /// A successful inner `or` is rolled back together with
+    /// the first branch of the outer `or`.
+    #[test]
+    fn or_nested_rollback() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+
+        let mut log = Transaction::new();
+        let x = log.or(
+            |tx| {
+                tx.or(|tx| a.write(tx, 1), |_| retry())?;
+                retry()
+            },
+            |tx| b.write(tx, 1)
+        );
+        assert_eq!(x, Ok(()));
+
+        assert!(log.commit());
+        assert_eq!(a.read_atomic(), 0);
+        assert_eq!(b.read_atomic(), 1);
+    }
+
+    /// Both branches of `or` read the same var, but it changes in between.
+    /// The commit validates the read of the taken branch.
+    #[test]
+    fn or_second_branch_read_survives() {
+        let var = TVar::new(0);
+
+        let mut log = Transaction::new();
+        let x = log.or(
+            |tx| {
+                if var.read(tx)? == 0 {
+                    let mut other = Transaction::new();
+                    other.write(&var, 1).unwrap();
+                    assert!(other.commit());
+                }
+                retry()
+            },
+            |tx| var.read(tx),
+        ).unwrap();
+        assert_eq!(x, 1);
+
+        match log.vars.get(var.control_block()) {
+            Some(Read(value)) => assert!(Arc::ptr_eq(value, &var.read_ref_atomic())),
+            _ => panic!("the read of the second branch must be kept"),
+        }
+        assert!(log.commit());
+    }
+
+    /// The vars of `retry_on` in the first branch of `or` replace
+    /// the reads of that branch.
+    #[test]
+    fn or_retry_on_first() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let c = TVar::new(0);
+
+        let mut log = Transaction::new();
+        b.read(&mut log).unwrap();
+        let x: StmResult<()> = log.or(
+            |tx| {
+                c.read(tx)?;
+                tx.retry_on(&[&a])
+            },
+            |_| retry()
+        );
+        assert_eq!(x, Err(Retry));
+
+        assert_eq!(is_obsolete(&log, &a), Some(true));
+        assert_eq!(is_obsolete(&log, &b), Some(false));
+        assert_eq!(is_obsolete(&log, &c), None);
+    }
+
+    #[test]
+    fn log_entries() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        let c = TVar::new(0);
+
+        let mut log = Transaction::new();
+        a.read(&mut log).unwrap();
+        b.write(&mut log, 1).unwrap();
+        c.modify(&mut log, |x| x + 1).unwrap();
+
+        let mut expected = vec![
+            (a.id(), LogEntryKind::Read),
+            (b.id(), LogEntryKind::Write),
+            (c.id(), LogEntryKind::ReadWrite),
+        ];
+        expected.sort_by_key(|&(id, _)| id);
+        assert_eq!(log.log_entries().collect::<Vec<_>>(), expected);
+    }
+
+    /// Drive the retry loop manually through a conflict to a commit.
+    #[test]
+    fn run_once_manual_loop() {
+        use std::thread;
+
+        let var = TVar::new(0);
+        let mut log = Transaction::new();
+        let mut results = Vec::new();
+
+        loop {
+            let r = log.run_once(|tx| {
+                let x = var.read(tx)?;
+                if results.is_empty() {
+                    let varc = var.clone();
+                    thread::spawn(move || crate::atomically(|tx| varc.write(tx, 10)))
+                        .join().unwrap();
+                }
+                var.write(tx, x + 1)?;
+                Ok(x)
+            });
+            results.push(r);
+            if let Ok(Some(_)) = r {
+                break;
+            }
+        }
+
+        assert_eq!(results, [Ok(None), Ok(Some(10))]);
+        assert_eq!(var.read_atomic(), 11);
+
+        // Errors are returned directly.
+        assert_eq!(log.run_once(|_| retry::<()>()), Err(Retry));
+    }
+
+    /// Check if a var is in the log and whether it is only obsolete.
+    fn is_obsolete(log: &Transaction, var: &TVar<i32>) -> Option<bool> {
+        log.vars.get(var.control_block()).map(|v|
+            matches!(v, ReadObsolete(_) | ReadObsoleteWrite(_, _)))
+    }
+
+    /// Reads of a retrying first branch are kept for blocking,
+    /// but don't take part in the consistency check.
+    #[test]
+    fn or_obsolete_not_validated() {
+        let mut log = Transaction::new();
+        let a = TVar::new(1);
+        let b = TVar::new(2);
+
+        let x = log.or(
+            |tx| { a.read(tx)?; retry() },
+            |tx| b.read(tx)
+        );
+        assert_eq!(x, Ok(2));
+        assert_eq!(is_obsolete(&log, &a), Some(true));
+        assert_eq!(is_obsolete(&log, &b), Some(false));
+
+        // A change of an obsolete read does not cause a conflict.
+        *a.control_block().value.write() = Arc::new(10);
+        assert!(log.commit());
+    }
+
+    /// A change of a read in the second branch still causes a conflict.
+    #[test]
+    fn or_second_branch_validated() {
+        let mut log = Transaction::new();
+        let a = TVar::new(1);
+        let b = TVar::new(2);
+
+        let _ = log.or(
+            |tx| { a.read(tx)?; retry() },
+            |tx| b.read(tx)
+        );
+
+        *b.control_block().value.write() = Arc::new(20);
+        assert!(!log.commit());
+    }
+
+    /// If the second branch writes a var, that the first one has read,
+    /// the obsolete read must not be lost.
+    #[test]
+    fn or_obsolete_read_with_write() {
+        let mut log = Transaction::new();
+        let a = TVar::new(1);
+
+        let _ = log.or(
+            |tx| { a.read(tx)?; retry() },
+            |tx| a.write(tx, 5)
+        );
+        assert_eq!(is_obsolete(&log, &a), Some(true));
+
+        // Further reads see the written value and keep the var obsolete.
+        assert_eq!(log.read(&a), Ok(5));
+        assert_eq!(is_obsolete(&log, &a), Some(true));
+
+        *a.control_block().value.write() = Arc::new(10);
+        assert!(log.commit());
+        assert_eq!(a.read_atomic(), 5);
+    }
+
+    /// Reading an obsolete var later makes it a real dependency.
+    #[test]
+    fn or_obsolete_read_again() {
+        let mut log = Transaction::new();
+        let a = TVar::new(1);
+
+        let _ = log.or(
+            |tx| { a.read(tx)?; retry() },
+            |_| Ok(())
+        );
+        assert_eq!(log.read(&a), Ok(1));
+        assert_eq!(is_obsolete(&log, &a), Some(false));
+
+        *a.control_block().value.write() = Arc::new(10);
+        assert!(!log.commit());
+    }
+
+    /// `read_all` returns the same values as single reads.
+    #[test]
+    fn read_all() {
+        let vars: Vec<_> = (0..10).map(TVar::new).collect();
+        let mut log = Transaction::new();
+
+        log.write(&vars[3], 30).unwrap();
+        let all = log.read_all(&vars).unwrap();
+        let single: Vec<_> = vars.iter().map(|v| log.read(v).unwrap()).collect();
+
+        assert_eq!(all, single);
+        assert_eq!(all[3], 30);
+        assert_eq!(log.log_entries().count(), 10);
+        assert!(log.commit());
+    }
+
+    /// Writes of `write_many` are visible before the commit.
+    #[test]
+    fn write_many() {
+        let vars: Vec<_> = (0..4).map(TVar::new).collect();
+        let mut log = Transaction::new();
+
+        let pairs: Vec<_> = vars.iter().map(|v| (v.clone(), 10)).collect();
+        log.write_many(&pairs).unwrap();
+        assert_eq!(log.read_all(&vars).unwrap(), vec![10; 4]);
+        assert_eq!(vars[0].read_atomic(), 0);
+
+        assert!(log.commit());
+        assert!(vars.iter().all(|v| v.read_atomic() == 10));
+    }
+
+    /// `retry_on` without any var would block forever.
+    #[test]
+    #[should_panic(expected = "at least one var")]
+    fn retry_on_empty() {
+        let mut log = Transaction::new();
+        let _ = log.retry_on::<i32, ()>(&[]);
+    }
+
+    /// `wait_for_change_until` asks to keep waiting at the deadline,
+    /// even if that is shorter than the periodic check.
+    #[test]
+    fn wait_for_change_until_deadline() {
+        let var = TVar::new(0);
+        let mut log = Transaction::new();
+        log.read(&var).unwrap();
+
+        let start = Instant::now();
+        let waited = log.wait_for_change_until(start + Duration::from_millis(20), || false);
+
+        assert!(!waited);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < WAIT_CHECK_INTERVAL);
+    }
+
+    /// A commit, that has locked a written var and then fails,
+    /// does not count as a write of that var.
+    #[test]
+    fn failed_commit_keeps_version() {
+        let a = TVar::new(0);
+        let b = TVar::new(0);
+        // Lock the written var before the read one.
+        let (written, read) = if a.id() < b.id() { (a, b) } else { (b, a) };
+
+        let mut log = Transaction::new();
+        log.write(&written, 1).unwrap();
+        log.read(&read).unwrap();
+        read.write_atomic(1);
+
+        assert!(!log.commit());
+        assert_eq!(written.version(), 0);
+        assert_eq!(written.read_atomic(), 0);
+
+        // Attempts, that find the second lock busy, are not counted either.
+        let busy = read.control_block().value.read();
+        let committer = {
+            let (written, read) = (written.clone(), read.clone());
+            thread::spawn(move || {
+                let mut log = Transaction::new();
+                log.set_commit_mode(CommitMode::TryLock);
+                log.write(&written, 2).unwrap();
+                log.write(&read, 2).unwrap();
+                assert!(log.commit());
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(busy);
+        committer.join().unwrap();
+
+        assert_eq!(written.version(), 1);
+        assert_eq!(read.version(), 2);
+    }
 }
@@ -8,24 +8,48 @@
 
 use std::sync::{Arc, Weak};
 use parking_lot::{Mutex, RwLock};
-use std::mem;
-use std::sync::atomic::{self, AtomicUsize};
+use std::collections::VecDeque;
+use std::sync::atomic::{self, AtomicU64, AtomicUsize};
 use std::cmp;
 use std::any::Any;
 use std::marker::PhantomData;
 use std::fmt::{Debug, self};
+use std::sync::atomic::Ordering;
 
 use super::result::*;
 use super::transaction::control_block::ControlBlock;
 use super::Transaction;
+use super::transaction::TransactionLike;
+
+/// Number of reads per write, above which a var counts as mostly read.
+const READ_MOSTLY_RATIO: usize = 16;
+
+/// Number of read commits, after which the access counters are halved.
+const COMMIT_COUNT_LIMIT: usize = 1 << 16;
+
+/// Get the address of a value, that identifies it as long as it is alive.
+fn address_of(value: &Arc<dyn Any + Send + Sync>) -> usize {
+    Arc::as_ptr(value) as *const u8 as usize
+}
 
 /// `VarControlBlock` contains all the useful data for a `Var` while beeing the same type.
 ///
 /// The control block is accessed from other threads directly whereas `Var`
 /// is just a typesafe wrapper around it.
+///
+/// It is aligned to a cache line, so that the locks of different vars,
+/// that are allocated next to each other, don't share a cache line.
+#[repr(align(64))]
 pub struct VarControlBlock {
-    /// `waiting_threads` is a list of all waiting threads protected by a mutex.
-    waiting_threads: Mutex<Vec<Weak<ControlBlock>>>,
+    /// `waiting_threads` is a queue of all waiting threads protected by a mutex.
+    ///
+    /// Threads are queued and woken in the order of their arrival.
+    waiting_threads: Mutex<VecDeque<Weak<ControlBlock>>>,
+
+    /// Maximal number of threads, that are woken on a single change.
+    ///
+    /// `0` means that all waiting threads are woken.
+    wake_limit: usize,
 
     /// `dead_threads` is a counter for all dead threads.
     ///
@@ -49,7 +73,54 @@ pub struct VarControlBlock {
     ///
     /// Starvation may occur, if one thread wants to write-lock but others
     /// keep holding read-locks.
+    ///
+    /// The value may only be changed by a commit, because it also
+    /// updates `version` and `value_addr`.
     pub value: RwLock<Arc<dyn Any + Send + Sync>>,
+
+    /// Sequence counter for validating the var without taking the lock.
+    ///
+    /// It is odd, while a commit holds the write lock.
+    version: AtomicUsize,
+
+    /// Address of the current value, so that it can be compared
+    /// with the read value without taking the lock.
+    value_addr: AtomicUsize,
+
+    /// Number of commits, that have only read the var.
+    commit_reads: AtomicUsize,
+
+    /// Number of commits, that have written the var.
+    commit_writes: AtomicUsize,
+
+    /// Number of committed writes since the creation of the var.
+    ///
+    /// Unlike `commit_writes`, it is never halved.
+    write_version: AtomicU64,
+
+    /// Name of the var for diagnostics.
+    name: Option<&'static str>,
+
+    /// Number of failed commits, because this var has changed.
+    #[cfg(feature = "stats")]
+    conflicts: AtomicUsize,
+
+    /// Number of threads, that have been woken by a change of this var.
+    #[cfg(feature = "stats")]
+    wakeups: AtomicUsize,
+}
+
+/// Statistics about the contention on a single `TVar`.
+///
+/// Only available with the `stats` feature.
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct ContentionStats {
+    /// Number of commits, that failed because the var has changed in between.
+    pub conflicts: usize,
+
+    /// Number of blocked threads, that have been woken by a change of the var.
+    pub wakeups: usize,
 }
 
 
@@ -58,39 +129,122 @@ impl VarControlBlock {
     pub fn new<T>(val: T) -> Arc<VarControlBlock>
         where T: Any + Sync + Send
     {
-        let ctrl = VarControlBlock {
-            waiting_threads: Mutex::new(Vec::new()),
-            dead_threads: AtomicUsize::new(0),
-            value: RwLock::new(Arc::new(val)),
-        };
-        Arc::new(ctrl)
+        VarControlBlock::with_wake_limit(val, 0)
     }
 
-    /// Wake all threads that are waiting for this block.
-    pub fn wake_all(&self) {
-        // Atomically take all waiting threads from the value.
-        let threads = {
-            let mut guard = self.waiting_threads.lock();
-            let inner: &mut Vec<_> = &mut guard;
-            mem::replace(inner, Vec::new())
-        };
+    /// Create a new `VarControlBlock`, that wakes at most `wake_limit`
+    /// threads on every change.
+    ///
+    /// A `wake_limit` of `0` wakes all threads.
+    pub fn with_wake_limit<T>(val: T, wake_limit: usize) -> Arc<VarControlBlock>
+        where T: Any + Sync + Send
+    {
+        VarControlBlock::from_arc(Arc::new(val), wake_limit)
+    }
+
+    /// Create a new `VarControlBlock` from a value, that is already shared.
+    pub fn from_arc(val: Arc<dyn Any + Send + Sync>, wake_limit: usize) -> Arc<VarControlBlock> {
+        Arc::new(VarControlBlock::build(val, wake_limit, None))
+    }
+
+    /// Create a new `VarControlBlock` with a name, that is shown in diagnostics.
+    pub fn with_name<T>(val: T, name: &'static str) -> Arc<VarControlBlock>
+        where T: Any + Sync + Send
+    {
+        Arc::new(VarControlBlock::build(Arc::new(val), 0, Some(name)))
+    }
+
+    fn build(val: Arc<dyn Any + Send + Sync>, wake_limit: usize, name: Option<&'static str>)
+        -> VarControlBlock
+    {
+        VarControlBlock {
+            waiting_threads: Mutex::new(VecDeque::new()),
+            wake_limit,
+            dead_threads: AtomicUsize::new(0),
+            version: AtomicUsize::new(0),
+            value_addr: AtomicUsize::new(address_of(&val)),
+            commit_reads: AtomicUsize::new(0),
+            commit_writes: AtomicUsize::new(0),
+            write_version: AtomicU64::new(0),
+            value: RwLock::new(val),
+            name,
+            #[cfg(feature = "stats")]
+            conflicts: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            wakeups: AtomicUsize::new(0),
+        }
+    }
 
-        // Take all, that are still alive.
-        let threads = threads.iter()
-            .filter_map(Weak::upgrade);
+    /// Get the name of the var, if it has one.
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
 
+    /// Wake the threads that are waiting for this block.
+    ///
+    /// Normally all threads are woken. If the block has a wake limit, 
+    /// only the longest waiting threads are woken and the others
+    /// stay in the queue for the next change.
+    pub fn wake_all(&self) {
         // Release all the semaphores to start the thread.
-        for thread in threads {
+        for thread in self.take_waiters() {
             // Inform thread that this var has changed.
             thread.set_changed();
         }
     }
 
+    /// Take the threads, that `wake_all` would wake, without waking them.
+    ///
+    /// This allows a commit to wake a thread, that waits for several
+    /// written vars, only once.
+    pub fn take_waiters(&self) -> Vec<Arc<ControlBlock>> {
+        // Atomically take the waiting threads from the value.
+        let threads: Vec<Arc<ControlBlock>> = {
+            let mut guard = self.waiting_threads.lock();
+            let inner: &mut VecDeque<_> = &mut guard;
+
+            if self.wake_limit == 0 {
+                // Drain instead of replacing the queue, so that it keeps
+                // its capacity for the next waiters.
+                inner.drain(..)
+                    .filter_map(|t| t.upgrade())
+                    .collect()
+            } else {
+                // Skip dead threads, so that they don't use up the limit.
+                let mut threads = Vec::with_capacity(self.wake_limit);
+                while threads.len() < self.wake_limit {
+                    match inner.pop_front() {
+                        Some(t) => threads.extend(t.upgrade()),
+                        None    => break,
+                    }
+                }
+                threads
+            }
+        };
+
+        #[cfg(feature = "stats")]
+        self.wakeups.fetch_add(threads.len(), atomic::Ordering::Relaxed);
+
+        threads
+    }
+
     /// Add another thread, that waits for mutations of `self`.
     pub fn wait(&self, thread: &Arc<ControlBlock>) {
         let mut guard = self.waiting_threads.lock();
 
-        guard.push(Arc::downgrade(thread));
+        guard.push_back(Arc::downgrade(thread));
+    }
+
+    /// Reserve space for at least `n` more waiting threads.
+    pub fn reserve_waiters(&self, n: usize) {
+        self.waiting_threads.lock().reserve(n);
+    }
+
+    /// Check if any thread is waiting for mutations of `self`.
+    pub fn has_waiters(&self) -> bool {
+        self.waiting_threads.lock()
+            .iter()
+            .any(|t| t.strong_count() > 0)
     }
 
     /// Mark another `StmControlBlock` as dead.
@@ -117,6 +271,80 @@ impl VarControlBlock {
         }
     }
 
+    /// Count a failed commit, that was caused by this var.
+    #[cfg(feature = "stats")]
+    pub fn set_conflict(&self) {
+        self.conflicts.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    /// Get the identity of the var.
+    pub fn id(&self) -> VarId {
+        VarId(self.get_address())
+    }
+
+    /// Register a commit, that only reads the var, and
+    /// return if the var is mostly read.
+    ///
+    /// Mostly read vars are validated optimistically on commit.
+    /// As soon as the var is written more often, it falls back
+    /// to taking the read lock.
+    pub fn count_read(&self) -> bool {
+        let reads = self.commit_reads.fetch_add(1, Ordering::Relaxed) + 1;
+        let writes = self.commit_writes.load(Ordering::Relaxed);
+
+        // Let old accesses fade out, so that the var adapts
+        // to a change of the access pattern.
+        if reads >= COMMIT_COUNT_LIMIT {
+            self.commit_reads.store(reads / 2, Ordering::Relaxed);
+            self.commit_writes.store(writes / 2, Ordering::Relaxed);
+        }
+
+        reads >= READ_MOSTLY_RATIO * (writes + 1)
+    }
+
+    /// Register a commit, that writes the var.
+    ///
+    /// Must only be called, when the new value is actually stored.
+    pub fn count_write(&self) {
+        self.commit_writes.fetch_add(1, Ordering::Relaxed);
+        self.write_version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Mark the var as being written.
+    ///
+    /// Must only be called while holding the write lock.
+    pub fn begin_write(&self) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Publish the new value after `begin_write`.
+    ///
+    /// Must only be called while holding the write lock.
+    pub fn end_write(&self, value: &Arc<dyn Any + Send + Sync>) {
+        self.value_addr.store(address_of(value), Ordering::SeqCst);
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Get the number of committed writes to the var.
+    ///
+    /// Commits, that have locked the var, but failed, are not counted.
+    pub fn version(&self) -> u64 {
+        self.write_version.load(Ordering::SeqCst)
+    }
+
+    /// Check without taking the lock, if the var still contains `original`
+    /// and no commit is writing it.
+    pub fn validate_optimistic(&self, original: &Arc<dyn Any + Send + Sync>) -> bool {
+        let before = self.version.load(Ordering::SeqCst);
+        if before % 2 == 1 {
+            return false;
+        }
+        let addr = self.value_addr.load(Ordering::SeqCst);
+        let after = self.version.load(Ordering::SeqCst);
+
+        before == after && addr == address_of(original)
+    }
+
     fn get_address(&self) -> usize {
         self as *const VarControlBlock as usize
     }
@@ -147,6 +375,13 @@ impl PartialOrd for VarControlBlock {
 
 
 
+/// The identity of a `TVar`.
+///
+/// All clones of a `TVar` have the same id. The id of a var
+/// may be reused, after it has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarId(usize);
+
 /// A variable that can be used in a STM-Block
 #[derive(Clone)]
 pub struct TVar<T> {
@@ -171,6 +406,44 @@ impl<T> TVar<T>
         }
     }
 
+    /// Create a new `TVar` from a value, that is already in an `Arc`.
+    ///
+    /// The `Arc` is stored as it is, so the value is neither cloned
+    /// nor moved into a new allocation.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// # use std::sync::Arc;
+    /// let value = Arc::new(vec![1, 2, 3]);
+    /// let var = TVar::from_arc(value);
+    ///
+    /// assert_eq!(var.read_atomic(), vec![1, 2, 3]);
+    /// ```
+    pub fn from_arc(val: Arc<T>) -> TVar<T> {
+        TVar {
+            control_block: VarControlBlock::from_arc(val, 0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Create a new `TVar` with a name.
+    ///
+    /// The name has no effect on transactions. It is shown by `Debug` and
+    /// in the `ConflictInfo` of a failed commit, so that a conflict can be
+    /// traced back to the var.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new_with_name(0, "counter");
+    /// assert_eq!(var.name(), Some("counter"));
+    /// ```
+    pub fn new_with_name(val: T, name: &'static str) -> TVar<T> {
+        TVar {
+            control_block: VarControlBlock::with_name(val, name),
+            _marker: PhantomData,
+        }
+    }
+
     /// `read_atomic` reads a value atomically, without starting a transaction.
     ///
     /// It is semantically equivalent to 
@@ -194,6 +467,48 @@ impl<T> TVar<T>
             .clone()
     }
 
+    /// Read a value atomically, but never block.
+    ///
+    /// Returns `None`, if a transaction is currently committing to the var.
+    /// This is meant for monitoring threads, that must not wait for the
+    /// transactions they observe.
+    pub fn try_read_atomic(&self) -> Option<T> {
+        let val = self.control_block
+            .value
+            .try_read()?
+            .clone();
+
+        Some((&*val as &dyn Any)
+            .downcast_ref::<T>()
+            .expect("wrong type in Var<T>")
+            .clone())
+    }
+
+    /// Write a value atomically, without starting a transaction.
+    ///
+    /// The value is stored directly and all threads, that are blocked on the
+    /// var, are woken. It bypasses the log, so it can not be combined with other
+    /// accesses into one atomic operation. Never call it inside of a transaction
+    /// on a var, that the transaction uses, because the transaction would not see the write.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(0);
+    /// var.write_atomic(42);
+    /// assert_eq!(var.read_atomic(), 42);
+    /// ```
+    pub fn write_atomic(&self, value: T) {
+        let value: Arc<dyn Any + Send + Sync> = Arc::new(value);
+        {
+            let mut lock = self.control_block.value.write();
+            self.control_block.begin_write();
+            self.control_block.count_write();
+            *lock = value;
+            self.control_block.end_write(&lock);
+        }
+        self.control_block.wake_all();
+    }
+
     /// Read a value atomically but return a reference.
     ///
     /// This is mostly used internally, but can be useful in
@@ -214,6 +529,41 @@ impl<T> TVar<T>
         transaction.read(self)
     }
 
+    /// Read a var inside of a transaction without cloning the value.
+    ///
+    /// The returned `Arc` is the one, that is stored in the log, so this is
+    /// cheap for large values, that are only inspected.
+    /// The read is logged like with `read`.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(vec![0u8; 1024]);
+    /// let len = atomically(|tx| Ok(var.read_ref(tx)?.len()));
+    /// assert_eq!(len, 1024);
+    /// ```
+    pub fn read_ref(&self, transaction: &mut Transaction) -> StmResult<Arc<T>> {
+        let value = transaction.read_any(&self.control_block)?;
+        Ok(value.downcast::<T>().expect("wrong type in Var<T>"))
+    }
+
+    /// Read a part of the value, that is computed by `f`.
+    ///
+    /// `f` only borrows the value, so the value is not cloned.
+    /// The whole var is logged as read, so the transaction reruns, when any
+    /// part of it changes, not only the one, that `f` looks at.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new((String::from("name"), 42));
+    /// let x = atomically(|tx| var.map(tx, |v| v.1));
+    /// assert_eq!(x, 42);
+    /// ```
+    pub fn map<U, F>(&self, transaction: &mut Transaction, f: F) -> StmResult<U>
+        where F: FnOnce(&T) -> U
+    {
+        Ok(f(&*self.read_ref(transaction)?))
+    }
+
     /// The normal way to write a var.
     ///
     /// It is equivalent to `transaction.write(&var, value)`, but more
@@ -224,6 +574,9 @@ impl<T> TVar<T>
 
     /// Modify the content of a `TVar` with the function f.
     ///
+    /// This is a read followed by a write, so the var is logged as read and written
+    /// and the transaction reruns, if another one changes the var before the commit.
+    ///
     /// ```
     /// # use stm_core::*;
     ///
@@ -241,6 +594,25 @@ impl<T> TVar<T>
         let old = self.read(transaction)?;
         self.write(transaction, f(old))
     }
+
+    /// Modify the content of a `TVar` with the function f and return the new value.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(21);
+    /// let x = atomically(|trans| 
+    ///     var.update_and_read(trans, |x| x*2)
+    /// );
+    ///
+    /// assert_eq!(x, 42);
+    /// ```
+    pub fn update_and_read<F>(&self, transaction: &mut Transaction, f: F) -> StmResult<T>
+        where F: FnOnce(T) -> T
+    {
+        let new = f(self.read(transaction)?);
+        self.write(transaction, new.clone())?;
+        Ok(new)
+    }
     
     /// Replaces the value of a `TVar` with a new one, returning
     /// the old one.
@@ -262,11 +634,181 @@ impl<T> TVar<T>
         Ok(old)
     }
 
+    /// Write a value only if `changed(old, new)` returns true.
+    ///
+    /// Return if the value has been written.
+    /// A suppressed write does not change the var, so it doesn't wake waiting
+    /// threads or conflict with other transactions, that read the var.
+    /// The var is read in both cases, though.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// // Only the first element counts.
+    /// let var = TVar::new((1, "a"));
+    ///
+    /// let written = atomically(|tx| var.write_if(tx, (1, "b"), |old, new| old.0 != new.0));
+    /// assert!(!written);
+    /// assert_eq!(var.read_atomic(), (1, "a"));
+    /// ```
+    pub fn write_if<F>(&self, transaction: &mut Transaction, value: T, changed: F) -> StmResult<bool>
+        where F: FnOnce(&T, &T) -> bool
+    {
+        let old = self.read(transaction)?;
+        if !changed(&old, &value) {
+            return Ok(false);
+        }
+        self.write(transaction, value)?;
+        Ok(true)
+    }
+
+    /// Write a value and call `cleanup` with the replaced value after the commit.
+    ///
+    /// This frees external resources, like file handles, that are attached to
+    /// the values of the var. The cleanup is registered with `Transaction::on_commit`,
+    /// so it never runs for an attempt, that is restarted or aborted.
+    /// If the var is written several times in one transaction, every replaced
+    /// value is cleaned up once, including the ones, that were never committed.
+    /// Values, that are replaced with a plain `write`, are not cleaned up.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(String::from("old"));
+    ///
+    /// atomically(|tx| var.write_with_cleanup(tx, String::from("new"), |old| {
+    ///     assert_eq!(old, "old");
+    /// }));
+    /// ```
+    pub fn write_with_cleanup<F>(&self, transaction: &mut Transaction, value: T, cleanup: F)
+        -> StmResult<()>
+        where F: FnOnce(T) + 'static
+    {
+        let old = self.replace(transaction, value)?;
+        transaction.on_commit(move || cleanup(old));
+        Ok(())
+    }
+
+    /// Create a new `TVar` with fair wakeups.
+    ///
+    /// Threads blocked on this var are woken in the order of their
+    /// arrival and at most `batch` of them per commit, that writes the var.
+    /// This prevents a few fast threads from monopolizing the var, while
+    /// slower ones starve.
+    ///
+    /// The remaining threads are only woken by later writes. Use this only
+    /// for vars that are written regularly.
+    pub fn new_fair(val: T, batch: usize) -> TVar<T> {
+        TVar {
+            control_block: VarControlBlock::with_wake_limit(val, batch),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Take the value out of a `TVar` and leave the default value in its place.
+    ///
+    /// This is useful to drain a collection atomically.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    ///
+    /// let var = TVar::new(vec![1, 2, 3]);
+    /// let x = atomically(|trans| 
+    ///     var.take(trans)
+    /// );
+    ///
+    /// assert_eq!(x, [1, 2, 3]);
+    /// assert!(var.read_atomic().is_empty());
+    /// ```
+    pub fn take(&self, transaction: &mut Transaction) -> StmResult<T>
+    where T: Default
+    {
+        self.replace(transaction, T::default())
+    }
+
+    /// Get the identity of the var.
+    pub fn id(&self) -> VarId {
+        self.control_block.id()
+    }
+
+    /// Get the version of the var.
+    ///
+    /// It starts at `0` and is incremented by every commit, that writes the var,
+    /// even if the value stays the same. Reads don't change it.
+    /// It helps to find ABA problems, where a var is changed and changed back.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(0);
+    /// atomically(|tx| var.write(tx, 1));
+    /// atomically(|tx| var.write(tx, 0));
+    /// assert_eq!(var.version(), 2);
+    /// ```
+    pub fn version(&self) -> u64 {
+        self.control_block.version()
+    }
+
+    /// Get the name of the var, if it has been created with `new_with_name`.
+    pub fn name(&self) -> Option<&'static str> {
+        self.control_block.name()
+    }
+
+    /// Reserve space for at least `n` more threads, that wait for the var.
+    ///
+    /// Threads register at the var, when they block in `retry`. For a var with
+    /// many waiters, reserving the space up front avoids reallocating it
+    /// during the registrations. The space is kept, when the waiters are woken.
+    pub fn reserve_waiters(&self, n: usize) {
+        self.control_block.reserve_waiters(n);
+    }
+
+    /// Check if a transaction is currently blocked in `retry` on this var.
+    ///
+    /// This is only a hint, for example for a producer, that decides whether
+    /// to do extra work. A transaction may start or stop waiting right after
+    /// the check.
+    pub fn has_waiters(&self) -> bool {
+        self.control_block.has_waiters()
+    }
+
     /// Check if two `TVar`s refer to the same position.
     pub fn ref_eq(this: &TVar<T>, other: &TVar<T>) -> bool {
         Arc::ptr_eq(&this.control_block, &other.control_block)
     }
+
+    /// Check if `other` is a clone of the same var.
+    ///
+    /// Aliased vars share their value, so operations like swapping two vars
+    /// behave differently, when both are the same.
+    pub fn same_var(&self, other: &TVar<T>) -> bool {
+        TVar::ref_eq(self, other)
+    }
     
+    /// Get the contention statistics of the var.
+    ///
+    /// Only available with the `stats` feature.
+    #[cfg(feature = "stats")]
+    pub fn contention_stats(&self) -> ContentionStats {
+        ContentionStats {
+            conflicts: self.control_block.conflicts.load(atomic::Ordering::Relaxed),
+            wakeups: self.control_block.wakeups.load(atomic::Ordering::Relaxed),
+        }
+    }
+
+    /// Get the number of live references to the var.
+    ///
+    /// Every clone of the `TVar` counts, but so do the logs of running
+    /// transactions, that have accessed the var. The count helps to find handles,
+    /// that keep a var alive longer than expected.
+    ///
+    /// Only available with the `stats` feature.
+    #[cfg(feature = "stats")]
+    pub fn handle_count(&self) -> usize {
+        Arc::strong_count(&self.control_block)
+    }
+
     /// Access the control block of the var.
     ///
     /// Internal use only!
@@ -275,6 +817,85 @@ impl<T> TVar<T>
     }
 }
 
+impl<T> TVar<Vec<T>>
+    where T: Any + Sync + Send + Clone
+{
+    /// Call `f` for every element of the vector inside of a transaction.
+    ///
+    /// This registers the read like `read`, but borrows the elements
+    /// instead of cloning the whole vector.
+    /// Writes to the var earlier in the same transaction are visible.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(vec![1, 2, 3]);
+    ///
+    /// let sum = atomically(|tx| {
+    ///     let mut sum = 0;
+    ///     var.for_each(tx, |x| sum += x)?;
+    ///     Ok(sum)
+    /// });
+    ///
+    /// assert_eq!(sum, 6);
+    /// ```
+    pub fn for_each<F>(&self, transaction: &mut Transaction, mut f: F) -> StmResult<()>
+    where F: FnMut(&T)
+    {
+        let val = transaction.read_any(&self.control_block)?;
+        let vec = val.downcast_ref::<Vec<T>>()
+            .expect("wrong type in Var<T>");
+
+        for x in vec {
+            f(x);
+        }
+        Ok(())
+    }
+}
+
+impl<T> TVar<Option<T>>
+    where T: Any + Sync + Send + Clone
+{
+    /// Read the option inside of the var and return `default`, if it is `None`.
+    ///
+    /// The var is registered as read in both cases, so the transaction
+    /// notices, when the value is set later.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(None);
+    ///
+    /// let x = atomically(|tx| var.read_unwrap_or(tx, 42));
+    /// assert_eq!(x, 42);
+    /// ```
+    pub fn read_unwrap_or(&self, transaction: &mut Transaction, default: T) -> StmResult<T> {
+        Ok(self.read(transaction)?.unwrap_or(default))
+    }
+}
+
+impl<T> TVar<T>
+    where T: Any + Sync + Send + Clone + PartialEq
+{
+    /// Write `new`, if the current value equals `expected`.
+    ///
+    /// Return if the value has been written.
+    /// The var is read in both cases, so a failed comparison is checked
+    /// for consistency on commit as well.
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVar::new(1);
+    ///
+    /// assert!(atomically(|tx| var.compare_and_swap(tx, &1, 2)));
+    /// assert!(!atomically(|tx| var.compare_and_swap(tx, &1, 3)));
+    /// assert_eq!(var.read_atomic(), 2);
+    /// ```
+    pub fn compare_and_swap(&self, transaction: &mut Transaction, expected: &T, new: T)
+        -> StmResult<bool>
+    {
+        self.write_if(transaction, new, |old, _| old == expected)
+    }
+}
+
 /// Debug output a struct.
 ///
 /// Note that this function does not print the state atomically.
@@ -291,8 +912,11 @@ impl<T> Debug for TVar<T>
     #[inline(never)]
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let x = self.read_atomic();
-        f.debug_struct("TVar")
-            .field("value", &x)
+        let mut s = f.debug_struct("TVar");
+        if let Some(name) = self.name() {
+            s.field("name", &name);
+        }
+        s.field("value", &x)
             .finish()
     }
 }
@@ -308,4 +932,515 @@ fn test_read_atomic() {
 }
 
 
+#[cfg(feature = "stats")]
+#[test]
+// A var, that is changed during the transaction, gets the blame for the conflicts.
+fn test_contention_stats() {
+    use std::thread;
+    use super::atomically;
+
+    let hot = TVar::new(0);
+    let cold = TVar::new(0);
+    let attempts = TVar::new(0);
+
+    atomically(|tx| {
+        let x = hot.read(tx)?;
+        cold.read(tx)?;
+
+        // Change the hot var during the first three attempts.
+        if attempts.read_atomic() < 3 {
+            let (hot, attempts) = (hot.clone(), attempts.clone());
+            thread::spawn(move || atomically(|tx| {
+                hot.modify(tx, |x| x + 1)?;
+                attempts.modify(tx, |x| x + 1)
+            })).join().unwrap();
+        }
+        hot.write(tx, x + 10)
+    });
+
+    assert_eq!(hot.contention_stats().conflicts, 3);
+    assert_eq!(cold.contention_stats().conflicts, 0);
+}
+
+#[test]
+// Test if the waiting threads of a fair var are woken one by one in the 
+// order of their arrival, so that every thread makes progress.
+fn test_fair_wakeup_order() {
+    use std::thread;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    let var = TVar::new_fair(0, 1);
+    let (woken_tx, woken_rx) = channel();
+    let mut threads = Vec::new();
+
+    for i in 0..4 {
+        let (registered_tx, registered_rx) = channel();
+        let ctrl = var.control_block().clone();
+        let woken_tx = woken_tx.clone();
+
+        threads.push(thread::spawn(move || {
+            let block = Arc::new(ControlBlock::new());
+            ctrl.wait(&block);
+            registered_tx.send(()).unwrap();
+            block.wait();
+            woken_tx.send(i).unwrap();
+        }));
+
+        // Ensure the order of arrival.
+        registered_rx.recv().unwrap();
+    }
+
+    // Every change wakes exactly the longest waiting thread.
+    for i in 0..4 {
+        var.control_block().wake_all();
+        assert_eq!(woken_rx.recv_timeout(Duration::from_secs(1)), Ok(i));
+        assert!(woken_rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[test]
+// `for_each` sees a write earlier in the same transaction.
+fn test_for_each_after_write() {
+    use super::atomically;
+
+    let var = TVar::new(vec![1, 2]);
+
+    let seen = atomically(|tx| {
+        var.write(tx, vec![3, 4, 5])?;
+
+        let mut seen = Vec::new();
+        var.for_each(tx, |x| seen.push(*x))?;
+        Ok(seen)
+    });
+
+    assert_eq!(seen, [3, 4, 5]);
+    assert_eq!(var.read_atomic(), [3, 4, 5]);
+}
+
+#[test]
+// `read_unwrap_or` returns the stored value or the default.
+fn test_read_unwrap_or() {
+    use super::atomically;
+
+    let var = TVar::new(Some(1));
+    assert_eq!(atomically(|tx| var.read_unwrap_or(tx, 42)), 1);
+
+    let var = TVar::new(None);
+    assert_eq!(atomically(|tx| var.read_unwrap_or(tx, 42)), 42);
+}
+
+#[test]
+// `read_unwrap_or` registers the read, even if the default is used.
+fn test_read_unwrap_or_registers_read() {
+    let var = TVar::new(None);
+
+    let mut log = Transaction::new();
+    assert_eq!(var.read_unwrap_or(&mut log, 42).unwrap(), 42);
+
+    let mut other = Transaction::new();
+    var.write(&mut other, Some(1)).unwrap();
+    assert!(other.commit());
+
+    assert!(!log.commit());
+}
+
+#[test]
+// A var becomes mostly read after enough read commits and falls
+// back, when it is written often.
+fn test_read_mostly() {
+    let var = TVar::new(0);
+    let ctrl = var.control_block();
+
+    assert!(!ctrl.count_read());
+    for _ in 0..READ_MOSTLY_RATIO {
+        ctrl.count_read();
+    }
+    assert!(ctrl.count_read());
+
+    for _ in 0..10 {
+        ctrl.count_write();
+    }
+    assert!(!ctrl.count_read());
+}
+
+#[test]
+// Optimistic validation fails, when the value has changed or is being written.
+fn test_validate_optimistic() {
+    let var = TVar::new(0);
+    let ctrl = var.control_block();
+    let original = var.read_ref_atomic();
+    assert!(ctrl.validate_optimistic(&original));
+
+    ctrl.begin_write();
+    assert!(!ctrl.validate_optimistic(&original));
+    ctrl.end_write(&original);
+    assert!(ctrl.validate_optimistic(&original));
+
+    let mut log = Transaction::new();
+    var.write(&mut log, 1).unwrap();
+    assert!(log.commit());
+    assert!(!ctrl.validate_optimistic(&original));
+    assert!(ctrl.validate_optimistic(&var.read_ref_atomic()));
+}
+
+#[cfg(feature = "stats")]
+#[test]
+// The handle count follows the clones of the var.
+fn test_handle_count() {
+    let var = TVar::new(0);
+    assert_eq!(var.handle_count(), 1);
+
+    let clone = var.clone();
+    assert_eq!(var.handle_count(), 2);
+
+    // The log of a transaction holds a reference as well.
+    let mut log = Transaction::new();
+    clone.read(&mut log).unwrap();
+    assert_eq!(var.handle_count(), 3);
+
+    drop(log);
+    drop(clone);
+    assert_eq!(var.handle_count(), 1);
+}
+
+#[test]
+// Clones are the same var, separately created vars are not.
+fn test_same_var() {
+    let var = TVar::new(0);
+    let clone = var.clone();
+    let other = TVar::new(0);
+
+    assert!(var.same_var(&clone));
+    assert!(clone.same_var(&var));
+    assert!(!var.same_var(&other));
+}
+
+#[test]
+// Reserved space is used by the waiters and kept after waking them.
+fn test_reserve_waiters() {
+    use std::time::Duration;
+
+    let var = TVar::new(0);
+    var.reserve_waiters(64);
+    let capacity = var.control_block.waiting_threads.lock().capacity();
+    assert!(capacity >= 64);
+
+    let waiters: Vec<_> = (0..64).map(|_| Arc::new(ControlBlock::new())).collect();
+    for w in &waiters {
+        var.control_block.wait(w);
+    }
+    assert_eq!(var.control_block.waiting_threads.lock().capacity(), capacity);
+
+    var.control_block.wake_all();
+    for w in &waiters {
+        assert!(w.wait_timeout(Duration::from_secs(0)));
+    }
+    assert_eq!(var.control_block.waiting_threads.lock().capacity(), capacity);
+}
+
+#[test]
+// A write, that is suppressed by `write_if`, does not wake a waiting thread.
+fn test_write_if() {
+    use std::sync::atomic::Ordering;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Doc {
+        version: u32,
+        cached_len: usize,
+    }
+
+    let var = TVar::new(Doc { version: 1, cached_len: 0 });
+    let waiter = Arc::new(ControlBlock::new());
+    var.control_block.wait(&waiter);
+
+    let by_version = |old: &Doc, new: &Doc| old.version != new.version;
+
+    let mut log = Transaction::new();
+    let written = var.write_if(&mut log, Doc { version: 1, cached_len: 10 }, by_version).unwrap();
+    assert!(!written);
+    assert!(log.commit());
+
+    assert_eq!(waiter.woken.load(Ordering::SeqCst), 0);
+    assert_eq!(var.read_atomic(), Doc { version: 1, cached_len: 0 });
+
+    let mut log = Transaction::new();
+    let written = var.write_if(&mut log, Doc { version: 2, cached_len: 10 }, by_version).unwrap();
+    assert!(written);
+    assert!(log.commit());
+
+    assert_eq!(waiter.woken.load(Ordering::SeqCst), 1);
+    assert_eq!(var.read_atomic(), Doc { version: 2, cached_len: 10 });
+}
+
+#[test]
+// Every replaced value is cleaned up exactly once, and only after the commit.
+fn test_write_with_cleanup() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::atomically;
+
+    let var = TVar::new(0);
+    let cleaned = Rc::new(RefCell::new(Vec::new()));
+
+    // An attempt, that is not committed, cleans up nothing.
+    let mut log = Transaction::new();
+    let c = cleaned.clone();
+    var.write_with_cleanup(&mut log, 10, move |old| c.borrow_mut().push(old)).unwrap();
+    drop(log);
+
+    atomically(|tx| {
+        let c = cleaned.clone();
+        var.write_with_cleanup(tx, 1, move |old| c.borrow_mut().push(old))?;
+        assert!(cleaned.borrow().is_empty());
+
+        let c = cleaned.clone();
+        var.write_with_cleanup(tx, 2, move |old| c.borrow_mut().push(old))
+    });
+
+    assert_eq!(var.read_atomic(), 2);
+    assert_eq!(*cleaned.borrow(), vec![0, 1]);
+}
+
+#[test]
+// `from_arc` stores the given `Arc` without a copy.
+fn test_from_arc() {
+    let value = Arc::new(String::from("shared"));
+    let var = TVar::from_arc(value.clone());
+
+    let stored = var.read_ref_atomic();
+    assert_eq!(Arc::as_ptr(&stored) as *const u8, Arc::as_ptr(&value) as *const u8);
+    assert_eq!(var.read_atomic(), "shared");
+}
+
+#[test]
+// `has_waiters` is set while a transaction is blocked on the var.
+fn test_has_waiters() {
+    use std::thread;
+    use super::{atomically, guard};
+
+    let var = TVar::new(false);
+    assert!(!var.has_waiters());
+
+    let waiter = {
+        let var = var.clone();
+        thread::spawn(move || atomically(|tx| guard(var.read(tx)?)))
+    };
+
+    while !var.has_waiters() {
+        thread::yield_now();
+    }
+
+    atomically(|tx| var.write(tx, true));
+    waiter.join().unwrap();
+    assert!(!var.has_waiters());
+}
+
+#[test]
+// `modify` logs a read and a write, so a concurrent write forces a rerun.
+fn test_modify() {
+    use std::thread;
+    use std::cell::Cell;
+    use super::{atomically, atomically_counted};
+    use super::LogEntryKind;
+
+    let var = TVar::new(0);
+
+    let mut tx = Transaction::new();
+    var.modify(&mut tx, |x| x + 1).unwrap();
+    assert_eq!(tx.log_entries().map(|(_, kind)| kind).collect::<Vec<_>>(),
+               vec![LogEntryKind::ReadWrite]);
+
+    let attempts = Cell::new(0);
+    let ((), reruns) = atomically_counted(|tx| {
+        var.modify(tx, |x| x + 1)?;
+        attempts.set(attempts.get() + 1);
+        if attempts.get() == 1 {
+            let varc = var.clone();
+            thread::spawn(move || atomically(|tx| varc.write(tx, 10)))
+                .join().unwrap();
+        }
+        Ok(())
+    });
+
+    assert_eq!(reruns, 1);
+    assert_eq!(var.read_atomic(), 11);
+}
+
+#[test]
+// Every `update_and_read` sees the result of the previous one.
+fn test_update_and_read() {
+    use super::atomically;
+
+    let var = TVar::new(1);
+
+    let steps = atomically(|tx| {
+        let a = var.update_and_read(tx, |x| x + 1)?;
+        let b = var.update_and_read(tx, |x| x * 10)?;
+        let c = var.update_and_read(tx, |x| x - 5)?;
+        Ok((a, b, c))
+    });
+
+    assert_eq!(steps, (2, 20, 15));
+    assert_eq!(var.read_atomic(), 15);
+}
+
+#[test]
+// `read_ref` returns the stored value without a copy.
+fn test_read_ref() {
+    use super::atomically;
+
+    let var = TVar::new(vec![1, 2, 3]);
+
+    let (a, b) = atomically(|tx| Ok((var.read_ref(tx)?, var.read_ref(tx)?)));
+    assert!(Arc::ptr_eq(&a, &b));
+    assert_eq!(*a, [1, 2, 3]);
+
+    // A write in between replaces the value.
+    let (a, b) = atomically(|tx| {
+        let a = var.read_ref(tx)?;
+        var.write(tx, vec![4])?;
+        Ok((a, var.read_ref(tx)?))
+    });
+    assert!(!Arc::ptr_eq(&a, &b));
+    assert_eq!(*b, [4]);
+}
+
+#[test]
+// A failed `compare_and_swap` reruns, if the var changes before the commit.
+fn test_compare_and_swap_conflict() {
+    use std::thread;
+    use std::cell::Cell;
+    use super::{atomically, atomically_counted};
+
+    let var = TVar::new(0);
+    let attempts = Cell::new(0);
+
+    let (swapped, reruns) = atomically_counted(|tx| {
+        let swapped = var.compare_and_swap(tx, &1, 2)?;
+        attempts.set(attempts.get() + 1);
+        if attempts.get() == 1 {
+            assert!(!swapped);
+            let varc = var.clone();
+            thread::spawn(move || atomically(|tx| varc.write(tx, 1)))
+                .join().unwrap();
+        }
+        Ok(swapped)
+    });
+
+    assert!(swapped);
+    assert_eq!(reruns, 1);
+    assert_eq!(var.read_atomic(), 2);
+}
+
+#[test]
+// `write_atomic` wakes a blocked transaction, which then sees the new value.
+fn test_write_atomic_wakes() {
+    use std::thread;
+    use super::{atomically, retry};
+
+    let var = TVar::new(0);
+
+    let waiter = {
+        let var = var.clone();
+        thread::spawn(move || atomically(|tx| {
+            let x = var.read(tx)?;
+            if x == 0 { retry() } else { Ok(x) }
+        }))
+    };
+
+    while !var.has_waiters() {
+        thread::yield_now();
+    }
+
+    var.write_atomic(42);
+    assert_eq!(waiter.join().unwrap(), 42);
+}
+
+#[test]
+// `map` depends on the whole value, so a write to another field forces a rerun.
+fn test_map() {
+    use std::thread;
+    use std::cell::Cell;
+    use super::{atomically, atomically_counted};
+
+    #[derive(Clone)]
+    struct Config {
+        port: u16,
+        name: String,
+    }
+
+    let var = TVar::new(Config { port: 80, name: String::from("a") });
+    let attempts = Cell::new(0);
+
+    let (port, reruns) = atomically_counted(|tx| {
+        let port = var.map(tx, |c| c.port)?;
+        attempts.set(attempts.get() + 1);
+        if attempts.get() == 1 {
+            let varc = var.clone();
+            thread::spawn(move || atomically(|tx| {
+                varc.modify(tx, |c| Config { name: String::from("b"), ..c })
+            })).join().unwrap();
+        }
+        Ok(port)
+    });
+
+    assert_eq!(port, 80);
+    assert_eq!(reruns, 1);
+    assert_eq!(atomically(|tx| var.map(tx, |c| c.name.clone())), "b");
+}
+
+#[test]
+// The name of a var is shown by `Debug`.
+fn test_name() {
+    let var = TVar::new_with_name(1, "a");
+    assert_eq!(var.control_block().name(), Some("a"));
+    assert_eq!(format!("{:?}", var), "TVar { name: \"a\", value: 1 }");
+
+    let var = TVar::new(1);
+    assert_eq!(var.name(), None);
+    assert_eq!(format!("{:?}", var), "TVar { value: 1 }");
+}
+
+#[test]
+// Only commits, that write the var, increment the version.
+fn test_version() {
+    use super::atomically;
+
+    let var = TVar::new(0);
+    let other = TVar::new(0);
+    assert_eq!(var.version(), 0);
+
+    atomically(|tx| var.write(tx, 1));
+    assert_eq!(var.version(), 1);
+
+    atomically(|tx| {
+        let x = var.read(tx)?;
+        other.write(tx, x)
+    });
+    atomically(|_| Ok(()));
+    assert_eq!(var.version(), 1);
+
+    var.write_atomic(1);
+    assert_eq!(var.version(), 2);
+}
+
+#[test]
+// `try_read_atomic` fails only while the value is locked for writing.
+fn test_try_read_atomic() {
+    let var = TVar::new(42);
+    assert_eq!(var.try_read_atomic(), Some(42));
+
+    {
+        let _lock = var.control_block.value.write();
+        assert_eq!(var.try_read_atomic(), None);
+    }
+
+    assert_eq!(var.try_read_atomic(), Some(42));
+}
+
 // More tests are in lib.rs.
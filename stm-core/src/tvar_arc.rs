@@ -0,0 +1,249 @@
+// Copyright 2015-2016 rust-stm Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::any::Any;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use super::result::*;
+use super::Transaction;
+use super::transaction::TransactionLike;
+use super::tvar::{VarControlBlock, VarId};
+
+/// A variable, that hands out its value as `Arc<T>`.
+///
+/// `TVar` clones the value on every read, so it requires `T: Clone`.
+/// `TVarArc` stores the `Arc` directly and a read only increments
+/// the reference count. This allows vars of types, that can't be cloned,
+/// and avoids copying large immutable values.
+///
+/// # Example
+///
+/// ```
+/// # use stm_core::*;
+/// # use std::sync::Arc;
+/// // `Vec<i32>` could be cloned, but it is never copied here.
+/// let var = TVarArc::new(vec![1, 2, 3]);
+///
+/// let v = atomically(|tx| {
+///     let v = var.read(tx)?;
+///     var.write(tx, Arc::new(v.iter().map(|x| x * 2).collect()))?;
+///     Ok(v)
+/// });
+///
+/// assert_eq!(*v, [1, 2, 3]);
+/// assert_eq!(*var.read_atomic(), [2, 4, 6]);
+/// ```
+pub struct TVarArc<T> {
+    /// The control block is the inner of the variable.
+    control_block: Arc<VarControlBlock>,
+
+    /// This marker is needed so that the variable can be used in a typesafe
+    /// manner.
+    _marker: PhantomData<T>,
+}
+
+impl<T> TVarArc<T>
+    where T: Any + Sync + Send
+{
+    /// Create a new `TVarArc`.
+    pub fn new(val: T) -> TVarArc<T> {
+        TVarArc::from_arc(Arc::new(val))
+    }
+
+    /// Create a new `TVarArc` from a value, that is already shared.
+    pub fn from_arc(val: Arc<T>) -> TVarArc<T> {
+        TVarArc {
+            control_block: VarControlBlock::from_arc(val, 0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Downcast a value of the var.
+    fn downcast(val: Arc<dyn Any + Send + Sync>) -> Arc<T> {
+        val.downcast::<T>()
+            .expect("wrong type in TVarArc<T>")
+    }
+
+    /// Read the value atomically, without starting a transaction.
+    pub fn read_atomic(&self) -> Arc<T> {
+        let val = self.control_block.value.read().clone();
+        TVarArc::downcast(val)
+    }
+
+    /// Read the value inside of a transaction.
+    pub fn read(&self, transaction: &mut Transaction) -> StmResult<Arc<T>> {
+        let val = transaction.read_any(&self.control_block)?;
+        Ok(TVarArc::downcast(val))
+    }
+
+    /// Write a value inside of a transaction.
+    pub fn write(&self, transaction: &mut Transaction, value: Arc<T>) -> StmResult<()> {
+        transaction.write_any(&self.control_block, value)
+    }
+
+    /// Change the value in place inside of a transaction.
+    ///
+    /// The first call in a transaction clones the value and writes the copy.
+    /// Later calls change the written copy in place, as long as no other
+    /// reference to it exists, like `Arc::make_mut`. Repeated edits of a large
+    /// value therefore only copy it once. Hold no `Arc` from `read` between the
+    /// calls, because it prevents the change in place.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use stm_core::*;
+    /// let var = TVarArc::new(vec![1, 2]);
+    ///
+    /// atomically(|tx| {
+    ///     var.modify_in_place(tx, |v| v.push(3))?;
+    ///     var.modify_in_place(tx, |v| v.push(4))
+    /// });
+    /// assert_eq!(*var.read_atomic(), [1, 2, 3, 4]);
+    /// ```
+    pub fn modify_in_place<F>(&self, transaction: &mut Transaction, f: F) -> StmResult<()>
+        where T: Clone,
+              F: FnOnce(&mut T)
+    {
+        if let Some(value) = transaction.write_value_mut(&self.control_block) {
+            if let Some(value) = Arc::get_mut(value) {
+                f(value.downcast_mut::<T>().expect("wrong type in TVarArc<T>"));
+                return Ok(());
+            }
+        }
+
+        let mut value = (*self.read(transaction)?).clone();
+        f(&mut value);
+        self.write(transaction, Arc::new(value))
+    }
+
+    /// Get the identity of the var.
+    pub fn id(&self) -> VarId {
+        self.control_block.id()
+    }
+
+    /// Access the control block of the var.
+    ///
+    /// Internal use only!
+    pub fn control_block(&self) -> &Arc<VarControlBlock> {
+        &self.control_block
+    }
+}
+
+// `derive` would require `T: Clone`.
+impl<T> Clone for TVarArc<T> {
+    fn clone(&self) -> Self {
+        TVarArc {
+            control_block: self.control_block.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::atomically;
+
+    /// A type, that can't be cloned.
+    #[derive(Debug, PartialEq)]
+    struct NonClone(i32);
+
+    #[test]
+    fn read_write_commit() {
+        let var = TVarArc::new(NonClone(1));
+
+        let old = atomically(|tx| {
+            let old = var.read(tx)?;
+            var.write(tx, Arc::new(NonClone(old.0 + 1)))?;
+            Ok(old)
+        });
+
+        assert_eq!(*old, NonClone(1));
+        assert_eq!(*var.read_atomic(), NonClone(2));
+    }
+
+    /// Reads share the stored value instead of copying it.
+    #[test]
+    fn read_shares_value() {
+        let value = Arc::new(NonClone(1));
+        let var = TVarArc::from_arc(value.clone());
+
+        let read = atomically(|tx| var.read(tx));
+        assert!(Arc::ptr_eq(&read, &value));
+        assert!(Arc::ptr_eq(&var.read_atomic(), &value));
+    }
+
+    /// A write is visible to later reads in the same transaction.
+    #[test]
+    fn read_own_write() {
+        let var = TVarArc::new(NonClone(1));
+
+        let x = atomically(|tx| {
+            var.write(tx, Arc::new(NonClone(2)))?;
+            var.read(tx)
+        });
+        assert_eq!(*x, NonClone(2));
+    }
+
+    thread_local!(static CLONES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) });
+
+    /// A vector, that counts its clones.
+    struct Counted(Vec<i32>);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.with(|c| c.set(c.get() + 1));
+            Counted(self.0.clone())
+        }
+    }
+
+    /// Several edits in one transaction clone the value only once.
+    #[test]
+    fn modify_in_place_clones_once() {
+        let var = TVarArc::new(Counted(vec![]));
+        let original = Arc::as_ptr(&var.read_atomic());
+
+        let clones = CLONES.with(|c| c.get());
+        atomically(|tx| {
+            var.modify_in_place(tx, |v| v.0.push(1))?;
+            let first = Arc::as_ptr(&var.read(tx)?);
+            assert_ne!(first, original);
+
+            var.modify_in_place(tx, |v| v.0.push(2))?;
+            var.modify_in_place(tx, |v| v.0.push(3))?;
+            assert_eq!(Arc::as_ptr(&var.read(tx)?), first);
+            Ok(())
+        });
+
+        assert_eq!(CLONES.with(|c| c.get()) - clones, 1);
+        assert_eq!(var.read_atomic().0, [1, 2, 3]);
+    }
+
+    /// An edit in a discarded branch of `or` does not change the value of the other branch.
+    #[test]
+    fn modify_in_place_or() {
+        use super::super::retry;
+
+        let var = TVarArc::new(vec![1]);
+
+        let x = atomically(|tx| {
+            var.modify_in_place(tx, |v| v.push(2))?;
+            tx.or(
+                |tx| {
+                    var.modify_in_place(tx, |v| v.push(3))?;
+                    retry()
+                },
+                |tx| var.read(tx)
+            )
+        });
+        assert_eq!(*x, [1, 2]);
+        assert_eq!(*var.read_atomic(), [1, 2]);
+    }
+}